@@ -0,0 +1,26 @@
+use std::env;
+use std::process::Command;
+
+/// Run a command and return its trimmed output, if successful
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8(output.stdout).ok()
+        .map(|s| s.trim().to_owned())
+}
+
+fn main() {
+    let revision = command_output("git", &["rev-parse", "--short", "HEAD"])
+        .unwrap_or_else(|| String::from("unknown"));
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| String::from("rustc"));
+    let rustc_version = command_output(&rustc, &["--version"])
+        .and_then(|v| v.split_whitespace().nth(1).map(str::to_owned))
+        .unwrap_or_else(|| String::from("unknown"));
+
+    println!("cargo:rustc-env=ERTH_REVISION={revision}");
+    println!("cargo:rustc-env=ERTH_RUSTC_VERSION={rustc_version}");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+}
@@ -0,0 +1,212 @@
+//! Responses of the JSON endpoints
+use std::collections::BTreeMap;
+
+use schemars::JsonSchema;
+use serde::Serialize;
+
+
+/// Expected call of a specific ticket, served on `/wait`
+#[derive(Debug,Clone,Serialize,JsonSchema)]
+pub struct WaitEstimate {
+    /// Service the ticket belongs to
+    pub service: String,
+
+    /// Requested ticket
+    pub ticket: String,
+
+    /// Ticket called most recently in the same queue
+    pub last_called_ticket: String,
+
+    /// Number of tickets called before the requested one, zero if it was called already
+    pub position: usize,
+
+    /// Tickets called per minute over the service rate window
+    pub service_rate_per_minute: f64,
+
+    /// Expected time until the ticket is called, unknown if no tickets were called recently
+    pub expected_wait_seconds: Option<f64>,
+
+    /// Expected point in time the ticket is called (RFC 3339)
+    pub expected_call_time: Option<String>,
+}
+
+/// Current state of all queues, served on `/api/v1/queues`
+#[derive(Debug,Clone,Serialize,JsonSchema)]
+pub struct Queues {
+    /// Time the data was scraped in seconds since the epoch
+    pub timestamp: f64,
+
+    /// Time the data was scraped (RFC 3339)
+    pub scraped_at: String,
+
+    /// Age of the data
+    pub age_seconds: f64,
+
+    /// Whether the data has expired and is only served because scraping fails
+    pub stale: bool,
+
+    /// Queues that could be parsed, by service name
+    pub queues: BTreeMap<String, Queue>,
+
+    /// Why queues could not be parsed, by service name
+    pub errors: BTreeMap<String, String>,
+}
+
+/// Current state of a single queue
+#[derive(Debug,Clone,Serialize,JsonSchema)]
+pub struct Queue {
+    pub people_waiting: usize,
+    pub last_called_ticket: String,
+
+    /// Waiting time estimation of the town hall
+    pub waiting_time_seconds: f64,
+
+    /// Waiting time of the last called ticket as tracked by the exporter
+    pub tracked_waiting_time_seconds: Option<f64>,
+
+    /// Exponentially weighted moving average of the tracked waiting times
+    pub smoothed_waiting_time_seconds: Option<f64>,
+
+    /// Expected waiting time of a ticket drawn now
+    pub new_ticket_wait_estimate_seconds: Option<f64>,
+
+    /// Tickets called per minute over the service rate window
+    pub service_rate_per_minute: f64,
+
+    /// People joining the queue per minute over the service rate window
+    pub arrival_rate_per_minute: f64,
+}
+
+/// Statistics of a service computed from the stored history, served on `/api/v1/stats`
+#[derive(Debug,Clone,Serialize,JsonSchema)]
+pub struct ServiceStats {
+    /// Statistics of each day, by date in the configured time zone
+    pub days: BTreeMap<String, Aggregate>,
+
+    /// Statistics of each day of the week
+    pub weekdays: BTreeMap<String, Aggregate>,
+}
+
+/// Waiting time statistics over a set of stored samples
+#[derive(Debug,Clone,Serialize,JsonSchema)]
+pub struct Aggregate {
+    /// Number of samples the statistics are based on
+    pub samples: usize,
+
+    /// Average waiting time estimation of the town hall
+    pub average_wait_seconds: f64,
+
+    /// Median waiting time estimation of the town hall
+    pub median_wait_seconds: f64,
+
+    /// Maximum waiting time estimation of the town hall
+    pub max_wait_seconds: f64,
+
+    /// Hour of the day with the longest queue on average
+    pub busiest_hour: u32,
+}
+
+/// Expected waiting time at an upcoming hour, served on `/api/v1/forecast`
+#[derive(Debug,Clone,Serialize,JsonSchema)]
+pub struct ForecastPoint {
+    /// Point in time the forecast is for (RFC 3339)
+    pub time: String,
+
+    /// Expected waiting time estimation of the town hall, unknown if there is no history for that
+    /// hour of the week
+    pub expected_wait_seconds: Option<f64>,
+}
+
+/// Encoding of the responses of the JSON API, negotiated via the `Accept` header
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum Encoding {
+    Json,
+    MessagePack,
+    Cbor,
+}
+
+
+impl Encoding {
+    /// Encoding preferred by a client according to its `Accept` header, JSON if none of the
+    /// binary encodings is requested
+    pub fn negotiate(accept: Option<&str>) -> Self {
+        let mut best = (Encoding::Json, 0.0);
+        for media_range in accept.unwrap_or_default().split(',') {
+            let mut params = media_range.split(';').map(str::trim);
+            let encoding = match params.next().unwrap_or_default().to_ascii_lowercase().as_str() {
+                "application/msgpack" | "application/x-msgpack" | "application/vnd.msgpack" => Encoding::MessagePack,
+                "application/cbor" => Encoding::Cbor,
+                "application/json" | "application/*" | "*/*" => Encoding::Json,
+                _ => continue,
+            };
+            let quality = params.find_map(|p| p.strip_prefix("q="))
+                .and_then(|q| q.parse::<f64>().ok())
+                .unwrap_or(1.0);
+            if quality > best.1 {
+                best = (encoding, quality);
+            }
+        }
+        best.0
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Encoding::Json => "application/json",
+            Encoding::MessagePack => "application/msgpack",
+            Encoding::Cbor => "application/cbor",
+        }
+    }
+
+    /// Serialize a response, with structs encoded as maps in MessagePack
+    pub fn encode<T: Serialize>(self, value: &T) -> Result<Vec<u8>, String> {
+        match self {
+            Encoding::Json => serde_json::to_vec(value)
+                .map_err(|e| e.to_string()),
+            Encoding::MessagePack => rmp_serde::to_vec_named(value)
+                .map_err(|e| e.to_string()),
+            Encoding::Cbor => {
+                let mut buffer = Vec::new();
+                ciborium::into_writer(value, &mut buffer)
+                    .map_err(|e| e.to_string())?;
+                Ok(buffer)
+            },
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negotiate() {
+        assert_eq!(Encoding::negotiate(None), Encoding::Json);
+        assert_eq!(Encoding::negotiate(Some("")), Encoding::Json);
+        assert_eq!(Encoding::negotiate(Some("text/html")), Encoding::Json);
+        assert_eq!(Encoding::negotiate(Some("application/msgpack")), Encoding::MessagePack);
+        assert_eq!(Encoding::negotiate(Some("Application/X-MsgPack")), Encoding::MessagePack);
+        assert_eq!(Encoding::negotiate(Some("application/cbor")), Encoding::Cbor);
+    }
+
+    #[test]
+    fn negotiate_quality() {
+        assert_eq!(Encoding::negotiate(Some("application/json;q=0.5, application/cbor;q=0.9")), Encoding::Cbor);
+        assert_eq!(Encoding::negotiate(Some("application/cbor;q=0.5, */*")), Encoding::Json);
+        assert_eq!(Encoding::negotiate(Some("application/msgpack;q=0.8, */*;q=0.1")), Encoding::MessagePack);
+        // ties go to the range listed first
+        assert_eq!(Encoding::negotiate(Some("application/cbor, application/msgpack")), Encoding::Cbor);
+        assert_eq!(Encoding::negotiate(Some("application/msgpack;q=0")), Encoding::Json);
+        assert_eq!(Encoding::negotiate(Some("application/msgpack;q=invalid")), Encoding::MessagePack);
+    }
+
+    #[test]
+    fn encode() {
+        let value = ForecastPoint { time: String::from("2024-01-01T09:00:00+01:00"), expected_wait_seconds: None };
+        let json = Encoding::Json.encode(&value).unwrap();
+        assert_eq!(json, br#"{"time":"2024-01-01T09:00:00+01:00","expected_wait_seconds":null}"#);
+        // structs as maps with two entries
+        assert_eq!(Encoding::MessagePack.encode(&value).unwrap()[0], 0x82);
+        assert_eq!(Encoding::Cbor.encode(&value).unwrap()[0], 0xa2);
+    }
+}
@@ -0,0 +1,56 @@
+//! Badges showing the waiting time of a queue, served on `/badge/<service>.svg`
+use crate::status::escape;
+
+
+/// Label on the left side of every badge
+const LABEL: &str = "Bürgeramt wait";
+
+/// Approximate width of a character in the badge font in pixels
+const CHAR_WIDTH: f64 = 6.5;
+
+/// Horizontal padding of both sides of a badge in pixels
+const PADDING: f64 = 10.0;
+
+
+/// Render a badge in the style of shields.io with the given message and color
+pub fn render(message: &str, color: &str) -> String {
+    let label_width = text_width(LABEL);
+    let message_width = text_width(message);
+    let width = label_width + message_width;
+    format!(concat!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width}\" height=\"20\" role=\"img\" aria-label=\"{label}: {message}\">",
+        "<title>{label}: {message}</title>",
+        "<linearGradient id=\"s\" x2=\"0\" y2=\"100%\"><stop offset=\"0\" stop-color=\"#bbb\" stop-opacity=\".1\"/><stop offset=\"1\" stop-opacity=\".1\"/></linearGradient>",
+        "<clipPath id=\"r\"><rect width=\"{width}\" height=\"20\" rx=\"3\" fill=\"#fff\"/></clipPath>",
+        "<g clip-path=\"url(#r)\"><rect width=\"{label_width}\" height=\"20\" fill=\"#555\"/>",
+        "<rect x=\"{label_width}\" width=\"{message_width}\" height=\"20\" fill=\"{color}\"/>",
+        "<rect width=\"{width}\" height=\"20\" fill=\"url(#s)\"/></g>",
+        "<g fill=\"#fff\" text-anchor=\"middle\" font-family=\"Verdana,Geneva,DejaVu Sans,sans-serif\" font-size=\"11\">",
+        "<text x=\"{label_x}\" y=\"15\" fill=\"#010101\" fill-opacity=\".3\">{label}</text><text x=\"{label_x}\" y=\"14\">{label}</text>",
+        "<text x=\"{message_x}\" y=\"15\" fill=\"#010101\" fill-opacity=\".3\">{message}</text><text x=\"{message_x}\" y=\"14\">{message}</text>",
+        "</g></svg>"),
+        width = width,
+        label_width = label_width,
+        message_width = message_width,
+        label_x = label_width / 2.0,
+        message_x = label_width + message_width / 2.0,
+        label = escape(LABEL),
+        message = escape(message),
+        color = color)
+}
+
+/// Color of a badge for a waiting time in seconds
+pub fn color(waiting_time: f64) -> &'static str {
+    match waiting_time / 60.0 {
+        m if m < 15.0 => "#4c1",
+        m if m < 30.0 => "#dfb317",
+        m if m < 60.0 => "#fe7d37",
+        _ => "#e05d44",
+    }
+}
+
+
+/// Approximate width of a text section including padding
+fn text_width(text: &str) -> f64 {
+    text.chars().count() as f64 * CHAR_WIDTH + PADDING
+}
@@ -0,0 +1,98 @@
+//! Recording pages of the town hall along with their parsed queues, run by the `capture` command
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use chrono::Utc;
+use serde::Serialize;
+
+use crate::config::Config;
+use crate::source::{self, HtmlSource};
+
+
+/// Parsed content of a captured page, stored next to it
+#[derive(Debug,Clone,Serialize)]
+struct Capture {
+    /// Time the page was fetched (RFC 3339)
+    captured_at: String,
+
+    /// Pages that were tried in order, the first one available was captured
+    urls: Vec<String>,
+
+    /// Name of the module the page was parsed with
+    module: String,
+
+    /// Queues found on the page by their service name, if the page could be parsed at all
+    queues: Option<BTreeMap<String, CapturedQueue>>,
+
+    /// Why the page could not be parsed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Values parsed from a single queue block, or why it could not be parsed
+#[derive(Debug,Clone,Serialize)]
+#[serde(untagged)]
+enum CapturedQueue {
+    Parsed {
+        people_waiting: usize,
+        last_called_ticket: String,
+
+        /// Waiting time estimation of the town hall in minutes
+        waiting_time_estimation: usize,
+    },
+    Failed {
+        error: String,
+    },
+}
+
+
+/// Fetch the configured page once and store it in `dir` as `erth-<time>.html`, together with what
+/// was parsed from it as `erth-<time>.json`
+///
+/// Pages that cannot be parsed are stored as well, with the error recorded in the JSON file.
+pub fn run(config: &Config, dir: &Path) -> Result<(), String> {
+    let mut source = HtmlSource::configured(config);
+    let (page, data) = source.fetch_raw()
+        .map_err(|e| e.to_string())?;
+
+    let (queues, error) = match data {
+        Ok(data) => {
+            let queues = data.queues.into_iter()
+                .map(|(service, queue)| (service, match queue {
+                    Ok(queue) => CapturedQueue::Parsed {
+                        people_waiting: queue.people_waiting,
+                        last_called_ticket: queue.last_called_ticket.to_string(),
+                        waiting_time_estimation: queue.waiting_time_estimation,
+                    },
+                    Err(e) => CapturedQueue::Failed { error: e.to_string() },
+                }))
+                .collect();
+            (Some(queues), None)
+        },
+        Err(e) => (None, Some(e.to_string())),
+    };
+    let capture = Capture {
+        captured_at: Utc::now().with_timezone(&config.timezone).to_rfc3339(),
+        urls: [vec![config.url.clone()], config.fallback_urls.clone()].concat(),
+        module: config.module.clone(),
+        queues, error,
+    };
+    let json = serde_json::to_string_pretty(&capture)
+        .map_err(|e| e.to_string())?;
+
+    let name = source::capture_name();
+    let html_path = dir.join(format!("{}.html", name));
+    let json_path = dir.join(format!("{}.json", name));
+    fs::create_dir_all(dir)
+        .and_then(|_| fs::write(&html_path, page))
+        .and_then(|_| fs::write(&json_path, json + "\n"))
+        .map_err(|e| format!("cannot store capture in {}: {}", dir.display(), e))?;
+
+    println!("{}", html_path.display());
+    println!("{}", json_path.display());
+    if let Some(error) = capture.error {
+        eprintln!("Warning: {}", error);
+    }
+    Ok(())
+}
@@ -0,0 +1,255 @@
+//! Nagios, Icinga and Checkmk compatible checks of the waiting times, run by the `check` command
+use std::fmt::Write;
+
+use clap::ValueEnum;
+
+use crate::config::Config;
+use crate::source::{HtmlSource, ScrapeSource};
+use crate::{QueueDataFrame, ScrapeError};
+
+
+/// Prefix of the Checkmk service names, followed by the name of the queue
+const CHECKMK_SERVICE_PREFIX: &str = "Bürgeramt";
+
+
+/// Result of a check, ordered by severity
+#[derive(Debug,Clone,Copy,PartialEq,Eq,PartialOrd,Ord)]
+pub enum Status {
+    Ok,
+    Warning,
+    Unknown,
+    Critical,
+}
+
+/// Output format of the `check` command
+#[derive(Debug,Clone,Copy,Default,ValueEnum)]
+pub enum Format {
+    /// Single status line with perfdata of a Nagios or Icinga plugin, with the worst status of
+    /// all services as exit code
+    #[default]
+    Nagios,
+
+    /// One line per service for the local directory of a Checkmk agent, always exiting with 0
+    Checkmk,
+}
+
+/// State of a single checked queue
+#[derive(Debug,Clone)]
+struct ServiceCheck {
+    /// Name of the queue, or the requested service if the page could not be checked at all
+    service: String,
+
+    status: Status,
+    summary: String,
+
+    /// Waiting time estimation of the town hall in seconds and number of people waiting, unknown
+    /// if the queue could not be parsed
+    values: Option<(f64, usize)>,
+}
+
+
+impl Status {
+    /// Exit code of the plugin, which is also the status in Checkmk local checks
+    pub fn code(self) -> i32 {
+        match self {
+            Status::Ok => 0,
+            Status::Warning => 1,
+            Status::Critical => 2,
+            Status::Unknown => 3,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Status::Ok => "OK",
+            Status::Warning => "WARNING",
+            Status::Critical => "CRITICAL",
+            Status::Unknown => "UNKNOWN",
+        }
+    }
+}
+
+
+/// Scrape the page once and print the state of the given service, or all services if unset, in
+/// the given format
+///
+/// The thresholds are waiting times in minutes. Returns the exit code of the command.
+pub fn run(config: &Config, service: Option<&str>, warn: f64, crit: f64, format: Format) -> i32 {
+    let checks = evaluate(config, service, warn, crit);
+    let (output, code) = render(&checks, warn, crit, format);
+    print!("{}", output);
+    code
+}
+
+
+/// Output of the checks in the given format along with the exit code
+fn render(checks: &[ServiceCheck], warn: f64, crit: f64, format: Format) -> (String, i32) {
+    let mut output = String::new();
+    match format {
+        Format::Nagios => {
+            let status = checks.iter().map(|c| c.status).max().unwrap_or(Status::Unknown);
+            let summary = checks.iter()
+                .map(|c| match c.service.is_empty() {
+                    true => c.summary.clone(),
+                    false => format!("{}: {}", c.service, c.summary),
+                })
+                .collect::<Vec<_>>()
+                .join(", ");
+            let mut perfdata = String::new();
+            for check in checks {
+                if let Some((wait, people_waiting)) = check.values {
+                    let _ = write!(perfdata, " '{service}_waiting_time'={}s;{};{};0 '{service}_people_waiting'={};;;0",
+                        wait, warn * 60.0, crit * 60.0, people_waiting, service = check.service);
+                }
+            }
+            let _ = match perfdata.is_empty() {
+                true => writeln!(output, "ERTH {} - {}", status.label(), summary),
+                false => writeln!(output, "ERTH {} - {} |{}", status.label(), summary, perfdata),
+            };
+            (output, status.code())
+        },
+        Format::Checkmk => {
+            for check in checks {
+                let name = match check.service.is_empty() {
+                    true => CHECKMK_SERVICE_PREFIX.to_owned(),
+                    false => format!("{} {}", CHECKMK_SERVICE_PREFIX, check.service),
+                };
+                let perfdata = match check.values {
+                    Some((wait, people_waiting)) => format!("waiting_time={};{};{};0|people_waiting={};;;0",
+                        wait, warn * 60.0, crit * 60.0, people_waiting),
+                    None => String::from("-"),
+                };
+                let _ = writeln!(output, "{} \"{}\" {} {}", check.status.code(), name, perfdata, check.summary);
+            }
+            (output, 0)
+        },
+    }
+}
+
+/// Check each selected queue
+///
+/// If the page cannot be scraped at all, a single unknown check named after the requested service
+/// is returned.
+fn evaluate(config: &Config, service: Option<&str>, warn: f64, crit: f64) -> Vec<ServiceCheck> {
+    let failed = |summary: String| vec![ServiceCheck {
+        service: service.unwrap_or_default().to_owned(),
+        status: Status::Unknown,
+        summary,
+        values: None,
+    }];
+
+    if !(warn.is_finite() && crit.is_finite() && warn >= 0.0 && crit >= warn) {
+        return failed(String::from("thresholds must satisfy 0 <= warn <= crit"));
+    }
+
+    let mut source = HtmlSource::configured(config);
+    let data = match source.fetch() {
+        Ok(Some(data)) => data,
+        Ok(None) => return failed(String::from("no data received")),
+        Err(e) => return failed(format!("cannot scrape page: {}", e)),
+    };
+
+    let checks: Vec<_> = data.queues.into_iter()
+        .filter(|(s, _)| service.is_none_or(|service| service == s.as_str()))
+        .map(|(service, queue)| check_queue(service, queue, warn, crit))
+        .collect();
+
+    if checks.is_empty() {
+        return match service {
+            Some(_) => failed(String::from("service not found")),
+            None => failed(String::from("no queues found")),
+        };
+    }
+    checks
+}
+
+/// Check a single queue against the thresholds in minutes
+fn check_queue(service: String, queue: Result<QueueDataFrame, ScrapeError>, warn: f64, crit: f64) -> ServiceCheck {
+    match queue {
+        Ok(queue) => {
+            let minutes = queue.waiting_time_estimation as f64;
+            let status = match minutes {
+                m if m > crit => Status::Critical,
+                m if m > warn => Status::Warning,
+                _ => Status::Ok,
+            };
+            ServiceCheck {
+                summary: format!("{} min waiting time, {} waiting", minutes, queue.people_waiting),
+                service,
+                status,
+                values: Some((minutes * 60.0, queue.people_waiting)),
+            }
+        },
+        Err(e) => ServiceCheck {
+            summary: e.to_string(),
+            service,
+            status: Status::Unknown,
+            values: None,
+        },
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::{Ticket, TicketType};
+
+    use super::*;
+
+    fn queue(minutes: usize, people_waiting: usize) -> Result<QueueDataFrame, ScrapeError> {
+        Ok(QueueDataFrame {
+            people_waiting,
+            last_called_ticket: Ticket(TicketType::Letter('B'), 1),
+            waiting_time_estimation: minutes,
+            tracked_waiting_time: None,
+        })
+    }
+
+    fn checks() -> Vec<ServiceCheck> {
+        vec![
+            check_queue(String::from("citizen"), queue(45, 12), 30.0, 60.0),
+            check_queue(String::from("drivers_license"), Err(ScrapeError::NotEnoughBlocks), 30.0, 60.0),
+        ]
+    }
+
+    #[test]
+    fn thresholds() {
+        let status = |minutes| check_queue(String::from("citizen"), queue(minutes, 0), 30.0, 60.0).status;
+        assert_eq!(status(0), Status::Ok);
+        assert_eq!(status(30), Status::Ok);
+        assert_eq!(status(31), Status::Warning);
+        assert_eq!(status(60), Status::Warning);
+        assert_eq!(status(61), Status::Critical);
+    }
+
+    #[test]
+    fn invalid_thresholds() {
+        for (warn, crit) in [(f64::NAN, 60.0), (30.0, f64::INFINITY), (-1.0, 60.0), (60.0, 30.0)] {
+            let checks = evaluate(&Config::default(), Some("citizen"), warn, crit);
+            assert_eq!(checks.len(), 1);
+            assert_eq!(checks[0].status, Status::Unknown);
+            assert_eq!(checks[0].service, "citizen");
+            assert!(checks[0].values.is_none());
+        }
+    }
+
+    #[test]
+    fn nagios() {
+        let (output, code) = render(&checks(), 30.0, 60.0, Format::Nagios);
+        assert_eq!(output, "ERTH UNKNOWN - citizen: 45 min waiting time, 12 waiting, drivers_license: no data blocks \
+            found | 'citizen_waiting_time'=2700s;1800;3600;0 'citizen_people_waiting'=12;;;0\n");
+        assert_eq!(code, 3);
+
+        let (output, code) = render(&checks()[..1], 30.0, 60.0, Format::Nagios);
+        assert!(output.starts_with("ERTH WARNING - "));
+        assert_eq!(code, 1);
+    }
+
+    #[test]
+    fn checkmk() {
+        let (output, code) = render(&checks(), 30.0, 60.0, Format::Checkmk);
+        assert_eq!(output, "1 \"Bürgeramt citizen\" waiting_time=2700;1800;3600;0|people_waiting=12;;;0 \
+            45 min waiting time, 12 waiting\n3 \"Bürgeramt drivers_license\" - no data blocks found\n");
+        assert_eq!(code, 0);
+    }
+}
@@ -0,0 +1,98 @@
+//! Output for the exec plugin of collectd, run by the `collectd` command
+use std::env;
+use std::fmt::Write;
+use std::fs;
+
+use crate::api::Queues;
+use crate::config::Config;
+use crate::push;
+use crate::Scraper;
+
+
+/// Name of the plugin in the value identifiers, followed by the service as plugin instance
+const PLUGIN: &str = "erth";
+
+
+/// Scrape the page once and print the values of all queues as `PUTVAL` commands
+///
+/// The host name and interval are taken from `COLLECTD_HOSTNAME` and `COLLECTD_INTERVAL`, which
+/// the exec plugin sets, and default to the system host name and the
+/// [scrape interval](Config::scrape_interval).
+pub fn run(config: &Config) -> Result<(), String> {
+    let hostname = env::var("COLLECTD_HOSTNAME").ok()
+        .or_else(|| fs::read_to_string("/proc/sys/kernel/hostname").ok())
+        .map(|h| h.trim().to_owned())
+        .filter(|h| !h.is_empty())
+        .unwrap_or_else(|| String::from("localhost"));
+    let interval = env::var("COLLECTD_INTERVAL").ok()
+        .and_then(|i| i.parse::<f64>().ok())
+        .unwrap_or(config.scrape_interval as f64);
+
+    let mut scraper = Scraper::open(config)?;
+    scraper.refresh();
+    let queues = scraper.queues(&config.timezone)
+        .ok_or_else(|| String::from("cannot scrape page"))?;
+
+    print!("{}", putval(&queues, &hostname, interval));
+    Ok(())
+}
+
+
+/// `PUTVAL` commands of all queues, with `U` for unknown values or values that are not a number
+fn putval(queues: &Queues, hostname: &str, interval: f64) -> String {
+    let mut output = String::new();
+    for (service, queue) in &queues.queues {
+        let values = [
+            ("gauge-people_waiting", Some(queue.people_waiting as f64)),
+            ("duration-waiting_time", Some(queue.waiting_time_seconds)),
+            ("duration-tracked_waiting_time", queue.tracked_waiting_time_seconds),
+        ];
+        for (kind, value) in values {
+            let value = value.filter(|v| v.is_finite())
+                .map_or_else(|| String::from("U"), |v| v.to_string());
+            let _ = writeln!(output, "PUTVAL \"{}/{}-{}/{}\" interval={} {}:{}", push::sanitize(hostname, &['.']), PLUGIN,
+                push::sanitize(service, &[]), kind, interval, queues.timestamp, value);
+        }
+    }
+    output
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::api::Queue;
+
+    use super::*;
+
+    #[test]
+    fn commands() {
+        let queue = |people_waiting, tracked| Queue {
+            people_waiting,
+            last_called_ticket: String::from("B001"),
+            waiting_time_seconds: 90.0,
+            tracked_waiting_time_seconds: tracked,
+            smoothed_waiting_time_seconds: None,
+            new_ticket_wait_estimate_seconds: None,
+            service_rate_per_minute: 0.0,
+            arrival_rate_per_minute: 0.0,
+        };
+        let queues = Queues {
+            timestamp: 1704096000.5,
+            scraped_at: String::from("2024-01-01T09:00:00+01:00"),
+            age_seconds: 0.0,
+            stale: false,
+            queues: [
+                (String::from("citizen"), queue(12, Some(120.0))),
+                (String::from("drivers license/south"), queue(0, Some(f64::NAN))),
+            ].into(),
+            errors: Default::default(),
+        };
+        assert_eq!(putval(&queues, "host.example.org/x", 10.0), "\
+            PUTVAL \"host.example.org_x/erth-citizen/gauge-people_waiting\" interval=10 1704096000.5:12\n\
+            PUTVAL \"host.example.org_x/erth-citizen/duration-waiting_time\" interval=10 1704096000.5:90\n\
+            PUTVAL \"host.example.org_x/erth-citizen/duration-tracked_waiting_time\" interval=10 1704096000.5:120\n\
+            PUTVAL \"host.example.org_x/erth-drivers_license_south/gauge-people_waiting\" interval=10 1704096000.5:0\n\
+            PUTVAL \"host.example.org_x/erth-drivers_license_south/duration-waiting_time\" interval=10 1704096000.5:90\n\
+            PUTVAL \"host.example.org_x/erth-drivers_license_south/duration-tracked_waiting_time\" interval=10 1704096000.5:U\n");
+    }
+}
@@ -0,0 +1,1276 @@
+//! Configuration of the exporter
+//!
+//! The configuration is read from a TOML file passed via `--config`. All keys are optional and
+//! default to the values for the Erlangen town hall:
+//!
+//! ```toml
+//! listen = "localhost:12080"
+//! url = "https://erlangen.de/themenseite/service/buerger/aktuelle-wartezeit"
+//! fallback_urls = ["https://mirror.example.org/aktuelle-wartezeit"]
+//! proxy = "socks5h://localhost:1080"
+//! user_agent = "erth-exporter (admin@example.org)"
+//! browser = "/usr/bin/chromium"  # requires the `browser` feature
+//! module = "erlangen"
+//! probe_targets = ["https://example.org/wartezeit"]
+//! legacy_metric_names = false
+//! sample_timestamps = false
+//! timezone = "Europe/Berlin"
+//! scrape_interval = 30
+//! min_scrape_interval = 10
+//! closed_scrape_interval = 1800
+//! service_rate_window = 900
+//! max_ticket_age = 14400
+//! max_tracked_tickets = 1000
+//! waiting_time_summary = false
+//! quantile_window = 3600
+//! smoothing_factor = 0.2
+//! history_size = 720
+//! max_staleness = 300
+//! state_file = "/var/lib/erth-exporter/state.toml"
+//! database = "/var/lib/erth-exporter/history.sqlite"
+//! admin_token = "secret"
+//!
+//! [labels]
+//! location = "erlangen"
+//!
+//! [headers]
+//! Accept-Language = "de"
+//!
+//! [retry]
+//! attempts = 3
+//! initial_backoff = 0.5
+//! max_backoff = 4.0
+//!
+//! [circuit_breaker]
+//! failures = 5
+//! cooldown = 300
+//!
+//! [anomalies]
+//! queue_spike = 10
+//! max_jump = 50
+//! stall_after = 1800
+//!
+//! [widget]
+//! service = "citizen"
+//! warning = 1200
+//! critical = 2400
+//!
+//! [retention]
+//! raw_days = 14
+//! aggregate_interval = 300
+//! aggregate_days = 365
+//! compaction_interval = 3600
+//!
+//! [redis]
+//! url = "redis://localhost/"
+//! prefix = "erth-exporter"
+//!
+//! [influxdb]
+//! url = "http://localhost:8086"
+//! database = "erth"
+//! measurement = "erth"
+//! interval = 60
+//!
+//! [graphite]
+//! host = "localhost"
+//! port = 2003
+//! prefix = "erth"
+//!
+//! [statsd]
+//! host = "localhost"
+//! port = 8125
+//! prefix = "erth"
+//! tags = true
+//!
+//! [remote_write]
+//! url = "http://localhost:9009/api/v1/push"
+//!
+//! [pushgateway]
+//! url = "http://localhost:9091"
+//! job = "erth-exporter"
+//! instance = "buergeramt"
+//!
+//! [mqtt]
+//! host = "localhost"
+//! port = 1883
+//! topic_prefix = "erth"
+//! discovery = true
+//!
+//! [zabbix]
+//! server = "zabbix.example.org"
+//! host = "buergeramt"
+//! waiting_time_key = "erth.waiting_time[{service}]"
+//!
+//! [[rules]]
+//! name = "short wait"
+//! service = "citizen"
+//! metric = "waiting_time"
+//! below = 600
+//! hysteresis = 120
+//!
+//! [[rules]]
+//! name = "queue empty"
+//! metric = "people_waiting"
+//! below = 1
+//!
+//! [[webhooks]]
+//! url = "https://example.org/hooks/erth"
+//! headers = { Authorization = "Bearer secret" }
+//!
+//! [telegram]
+//! token = "123456:bot-token"
+//! chat_ids = [12345678]
+//! commands = true
+//!
+//! [ntfy]
+//! url = "https://ntfy.sh"
+//! topic = "erth-exporter"
+//! priority = 4
+//!
+//! [smtp]  # requires the `smtp` feature
+//! host = "mail.example.org"
+//! security = "starttls"
+//! username = "erth"
+//! password = "secret"
+//! from = "Bürgeramt <erth@example.org>"
+//! to = ["me@example.org"]
+//!
+//! [matrix]
+//! homeserver = "https://matrix.example.org"
+//! room_id = "!abcdefghijklmnop:example.org"
+//! access_token = "secret"
+//! updates = true
+//! interval = 300
+//!
+//! [capture]
+//! dir = "captures"
+//! keep = 20
+//!
+//! [opening_hours]
+//! mon = ["07:30-12:00"]
+//! thu = ["07:30-12:00", "14:00-18:00"]
+//!
+//! [modules.example]
+//! block_selector = ".queue"
+//! value_selector = ".queue>span"
+//! block_content_filter = "Wartende Personen"
+//! heading_selector = "h2"
+//!
+//! [modules.example.headings]
+//! citizen = "Bürgerservice"
+//! drivers_license = "Fahrerlaubnis"
+//! ```
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::Utc;
+use chrono_tz::Tz;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+
+use crate::opening_hours::OpeningHours;
+use crate::{BLOCK_CONTENT_FILTER, BLOCK_SELECTOR, CACHE_EXPIRATION, DEFAULT_LISTEN_ADDR, DEFAULT_MODULE, HEADING_SELECTOR, URL, VALUE_SELECTOR};
+
+
+/// Default for [Config::user_agent]
+const DEFAULT_USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+/// Default for [Config::min_scrape_interval] in seconds
+const DEFAULT_MIN_SCRAPE_INTERVAL: u64 = 10;
+/// Default for [Config::service_rate_window] in seconds
+const DEFAULT_SERVICE_RATE_WINDOW: u64 = 15 * 60;
+/// Default for [Config::max_ticket_age] in seconds
+const DEFAULT_MAX_TICKET_AGE: u64 = 4 * 60 * 60;
+/// Default for [Config::max_staleness] in seconds
+const DEFAULT_MAX_STALENESS: u64 = 5 * 60;
+
+
+/// Settings for the exporter
+#[derive(Debug,Clone,Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Address the HTTP server is bound to
+    pub listen: String,
+
+    /// Page scraped for the `/metrics` endpoint, `file://` URLs refer to a local copy
+    pub url: String,
+
+    /// Pages tried in order if [Config::url] cannot be fetched, like mirrors or cached copies
+    pub fallback_urls: Vec<String>,
+
+    /// Proxy for all upstream requests (`http://`, `https://`, `socks5://` or `socks5h://`)
+    ///
+    /// If unset, the proxies from the `HTTP_PROXY`, `HTTPS_PROXY` and `ALL_PROXY` environment
+    /// variables are used.
+    pub proxy: Option<String>,
+
+    /// `User-Agent` sent with upstream requests, ideally including a way to contact the operator
+    pub user_agent: String,
+
+    /// Additional headers sent with upstream requests
+    pub headers: HashMap<String, String>,
+
+    /// Name of the [Module] used to parse [Config::url]
+    pub module: String,
+
+    /// Additional parser modules, which may also override the builtin [DEFAULT_MODULE]
+    pub modules: HashMap<String, Module>,
+
+    /// Pages that may be scraped via the `/probe` endpoint
+    pub probe_targets: Vec<String>,
+
+    /// Additionally export metrics under their old names, which are not in base units
+    pub legacy_metric_names: bool,
+
+    /// Attach the time the upstream data was observed to the exported samples
+    pub sample_timestamps: bool,
+
+    /// Constant labels attached to every exported series
+    pub labels: HashMap<String, String>,
+
+    /// Time zone of the town hall
+    pub timezone: Tz,
+
+    /// Opening hours of the town hall in [Config::timezone]
+    pub opening_hours: Option<OpeningHours>,
+
+    /// Interval in seconds at which the page is scraped, also the time-to-live of cached data
+    pub scrape_interval: u64,
+
+    /// Lower bound in seconds for the time between two fetches of the same page
+    pub min_scrape_interval: u64,
+
+    /// Interval in seconds at which the page is scraped while the town hall is closed according to
+    /// [Config::opening_hours], scraping pauses during that time if unset
+    pub closed_scrape_interval: Option<u64>,
+
+    /// Time span in seconds over which the service rate of the queues is calculated
+    pub service_rate_window: u64,
+
+    /// Age in seconds after which a tracked ticket is assumed to be abandoned and dropped
+    pub max_ticket_age: u64,
+
+    /// Number of tickets tracked per queue, the oldest ones are dropped beyond that
+    pub max_tracked_tickets: usize,
+
+    /// Export the tracked waiting times as summary with quantiles instead of a histogram
+    pub waiting_time_summary: bool,
+
+    /// Time span in seconds over which the waiting time quantiles are calculated
+    pub quantile_window: u64,
+
+    /// Weight of the latest tracked waiting time in the smoothed waiting time, between 0 and 1
+    pub smoothing_factor: f64,
+
+    /// Number of recent data frames kept in memory for the `/history` endpoint, `0` disables it
+    pub history_size: usize,
+
+    /// Retry behavior for fetching the upstream page
+    pub retry: RetryConfig,
+
+    /// Maximum age in seconds of expired data that is still served while scraping fails
+    pub max_staleness: u64,
+
+    /// File the ticket tracker is persisted to, so measurements survive restarts
+    pub state_file: Option<PathBuf>,
+
+    /// Pause scraping after repeated failures
+    pub circuit_breaker: CircuitBreakerConfig,
+
+    /// Thresholds for the anomalies reported in `erth_anomaly`
+    pub anomalies: AnomalyConfig,
+
+    /// Settings of the status bar widgets on `/widget/...`
+    pub widget: WidgetConfig,
+
+    /// Push data frames to InfluxDB, disabled if unset
+    pub influxdb: Option<InfluxDbConfig>,
+
+    /// Push metrics to Graphite, disabled if unset
+    pub graphite: Option<GraphiteConfig>,
+
+    /// Push metrics via StatsD, disabled if unset
+    pub statsd: Option<StatsdConfig>,
+
+    /// Push metrics via Prometheus remote write, disabled if unset
+    pub remote_write: Option<RemoteWriteConfig>,
+
+    /// Push metrics to a Prometheus Pushgateway, disabled if unset
+    pub pushgateway: Option<PushgatewayConfig>,
+
+    /// Publish queue states to an MQTT broker, disabled if unset
+    pub mqtt: Option<MqttConfig>,
+
+    /// Send queue states to a Zabbix server or proxy, disabled if unset
+    pub zabbix: Option<ZabbixConfig>,
+
+    /// Threshold rules that trigger notifications when crossed
+    pub rules: Vec<RuleConfig>,
+
+    /// Webhooks notified about crossed [rules](Config::rules)
+    pub webhooks: Vec<WebhookConfig>,
+
+    /// Telegram bot notified about crossed [rules](Config::rules), disabled if unset
+    pub telegram: Option<TelegramConfig>,
+
+    /// ntfy topic notified about crossed [rules](Config::rules), disabled if unset
+    pub ntfy: Option<NtfyConfig>,
+
+    /// Matrix room notified about crossed [rules](Config::rules), disabled if unset
+    pub matrix: Option<MatrixConfig>,
+
+    /// Store pages that could not be parsed, disabled if unset
+    pub capture: Option<CaptureConfig>,
+
+    /// Chromium executable used to render upstream pages headless before parsing them, sending the
+    /// [user agent](Config::user_agent), [headers](Config::headers) and [proxy](Config::proxy)
+    /// configured for direct requests
+    #[cfg(feature = "browser")]
+    pub browser: Option<PathBuf>,
+
+    /// SQLite database every scraped data frame is stored in, disabled if unset
+    #[cfg(feature = "sqlite")]
+    pub database: Option<PathBuf>,
+
+    /// Redis instance the data frames and ticket tracker are shared through with other replicas,
+    /// scraping independently if unset
+    #[cfg(feature = "redis")]
+    pub redis: Option<RedisConfig>,
+
+    /// Bearer token required for the `/admin` endpoints, which are disabled if unset
+    ///
+    /// `/admin/backup` serves a consistent snapshot of the [database](Config::database).
+    #[cfg(feature = "sqlite")]
+    pub admin_token: Option<String>,
+
+    /// Mail server used to send notifications about crossed [rules](Config::rules), disabled if
+    /// unset
+    #[cfg(feature = "smtp")]
+    pub smtp: Option<SmtpConfig>,
+
+    /// How long data frames are kept in the [database](Config::database)
+    #[cfg(feature = "sqlite")]
+    pub retention: RetentionConfig,
+}
+
+/// Exponential backoff for retrying failed upstream requests
+#[derive(Debug,Clone,Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetryConfig {
+    /// Maximum number of attempts per scrape, including the first one
+    pub attempts: u32,
+
+    /// Delay before the first retry in seconds, doubled for every further retry
+    pub initial_backoff: f64,
+
+    /// Upper bound for the delay between two attempts in seconds
+    pub max_backoff: f64,
+}
+
+/// Stops scraping for a while after repeated failures, instead of hitting the upstream server on
+/// every request
+#[derive(Debug,Clone,Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CircuitBreakerConfig {
+    /// Number of consecutive failed scrapes that open the circuit, `0` disables the circuit breaker
+    pub failures: u32,
+
+    /// Time in seconds no scrapes are attempted once the circuit is open
+    pub cooldown: u64,
+}
+
+/// Thresholds for detecting anomalies of a queue
+#[derive(Debug,Clone,Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AnomalyConfig {
+    /// Growth of the queue length between two scrapes that counts as spike
+    pub queue_spike: usize,
+
+    /// Maximum plausible number of tickets called between two scrapes
+    pub max_jump: usize,
+
+    /// Time in seconds without any called ticket after which a non-empty queue counts as stalled
+    /// while the town hall is open
+    pub stall_after: u64,
+}
+
+/// Retention and downsampling of the data frames stored in the [database](Config::database)
+#[cfg(feature = "sqlite")]
+#[derive(Debug,Clone,Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetentionConfig {
+    /// Days after which data frames are replaced by aggregates
+    pub raw_days: u64,
+
+    /// Time span in seconds covered by each aggregate
+    pub aggregate_interval: u64,
+
+    /// Days after which aggregates are deleted
+    pub aggregate_days: u64,
+
+    /// Interval in seconds at which the database is compacted
+    pub compaction_interval: u64,
+}
+
+/// Connection to the Redis instance shared by all replicas
+#[cfg(feature = "redis")]
+#[derive(Debug,Clone,Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RedisConfig {
+    /// URL of the Redis instance
+    pub url: String,
+
+    /// Prefix of all keys used by the exporter, replicas scraping the same page must share it
+    pub prefix: String,
+}
+
+/// Status bar widgets
+#[derive(Debug,Clone,Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WidgetConfig {
+    /// Service shown by default, all services if unset, can be overridden with `?service=`
+    pub service: Option<String>,
+
+    /// Waiting time in seconds from which the widget is styled as warning
+    pub warning: u64,
+
+    /// Waiting time in seconds from which the widget is styled as critical
+    pub critical: u64,
+}
+
+/// Pushing data frames to InfluxDB
+///
+/// InfluxDB 2.x is used if a [token](InfluxDbConfig::token) is set, 1.x otherwise.
+#[derive(Debug,Clone,Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct InfluxDbConfig {
+    /// Base URL of the InfluxDB server
+    pub url: String,
+
+    /// Database (1.x) or bucket (2.x) the points are written to
+    pub database: String,
+
+    /// Organization owning the bucket (2.x)
+    pub org: String,
+
+    /// API token (2.x)
+    pub token: Option<String>,
+
+    /// User for basic authentication (1.x)
+    pub username: Option<String>,
+
+    /// Password for basic authentication (1.x)
+    pub password: Option<String>,
+
+    /// Measurement the points are written to
+    pub measurement: String,
+
+    /// Interval in seconds at which new data frames are pushed, defaults to the
+    /// [scrape interval](Config::scrape_interval)
+    pub interval: Option<u64>,
+}
+
+/// Pushing metrics to a carbon server in Graphite plaintext protocol
+#[derive(Debug,Clone,Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GraphiteConfig {
+    /// Host of the carbon server
+    pub host: String,
+
+    /// Port of the plaintext receiver
+    pub port: u16,
+
+    /// Prefix of all metric paths, may be empty
+    pub prefix: String,
+
+    /// Interval in seconds at which new data is pushed, defaults to the
+    /// [scrape interval](Config::scrape_interval)
+    pub interval: Option<u64>,
+}
+
+/// Pushing metrics to a StatsD server over UDP
+#[derive(Debug,Clone,Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StatsdConfig {
+    /// Host of the StatsD server
+    pub host: String,
+
+    /// UDP port of the StatsD server
+    pub port: u16,
+
+    /// Prefix of all metric names, may be empty
+    pub prefix: String,
+
+    /// Send labels as DogStatsD tags instead of appending them to the metric name
+    pub tags: bool,
+
+    /// Interval in seconds at which new data is pushed, defaults to the
+    /// [scrape interval](Config::scrape_interval)
+    pub interval: Option<u64>,
+}
+
+/// Pushing metrics via Prometheus remote write
+#[derive(Debug,Clone,Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RemoteWriteConfig {
+    /// URL of the remote-write receiver
+    pub url: String,
+
+    /// User for basic authentication
+    pub username: Option<String>,
+
+    /// Password for basic authentication
+    pub password: Option<String>,
+
+    /// Token for bearer authentication, takes precedence over basic authentication
+    pub bearer_token: Option<String>,
+
+    /// Interval in seconds at which new data is pushed, defaults to the
+    /// [scrape interval](Config::scrape_interval)
+    pub interval: Option<u64>,
+}
+
+/// Pushing metrics to a Prometheus Pushgateway
+#[derive(Debug,Clone,Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PushgatewayConfig {
+    /// Base URL of the Pushgateway
+    pub url: String,
+
+    /// Value of the `job` label of the pushed group
+    pub job: String,
+
+    /// Value of the `instance` label of the pushed group, omitted if unset
+    pub instance: Option<String>,
+
+    /// User for basic authentication
+    pub username: Option<String>,
+
+    /// Password for basic authentication
+    pub password: Option<String>,
+
+    /// Interval in seconds at which new data is pushed, defaults to the
+    /// [scrape interval](Config::scrape_interval)
+    pub interval: Option<u64>,
+}
+
+/// Publishing queue states to an MQTT broker
+#[derive(Debug,Clone,Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MqttConfig {
+    /// Host of the broker
+    pub host: String,
+
+    /// Port of the broker, TLS is not supported
+    pub port: u16,
+
+    /// Client identifier used when connecting to the broker
+    pub client_id: String,
+
+    /// User for authentication
+    pub username: Option<String>,
+
+    /// Password for authentication
+    pub password: Option<String>,
+
+    /// Prefix of the state topics, which are named `<topic_prefix>/<service>`
+    pub topic_prefix: String,
+
+    /// Whether to announce the queues as sensors via Home Assistant MQTT discovery
+    pub discovery: bool,
+
+    /// Topic prefix Home Assistant listens on for discovery messages
+    pub discovery_prefix: String,
+
+    /// Interval in seconds at which new data is published, defaults to the
+    /// [scrape interval](Config::scrape_interval)
+    pub interval: Option<u64>,
+}
+
+/// Sending queue states to trapper items of a Zabbix server via the sender protocol
+///
+/// In the item keys, `{service}` is replaced by the name of the queue. Empty keys are not sent.
+#[derive(Debug,Clone,Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ZabbixConfig {
+    /// Host of the Zabbix server or proxy
+    pub server: String,
+
+    /// Port of the trapper of the Zabbix server
+    pub port: u16,
+
+    /// Name of the host in Zabbix the items belong to
+    pub host: String,
+
+    /// Item key of the number of people waiting
+    pub people_waiting_key: String,
+
+    /// Item key of the waiting time estimation of the town hall in seconds
+    pub waiting_time_key: String,
+
+    /// Item key of the tracked waiting time in seconds
+    pub tracked_waiting_time_key: String,
+
+    /// Interval in seconds at which new data is sent, defaults to the
+    /// [scrape interval](Config::scrape_interval)
+    pub interval: Option<u64>,
+}
+
+/// Threshold on a queue metric that triggers notifications when crossed
+///
+/// Exactly one of [below](RuleConfig::below) and [above](RuleConfig::above) must be set.
+#[derive(Debug,Clone,Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RuleConfig {
+    /// Name of the rule included in notifications
+    pub name: String,
+
+    /// Service the rule applies to, all services if unset
+    pub service: Option<String>,
+
+    /// Metric the threshold applies to
+    pub metric: RuleMetric,
+
+    /// The rule fires once the metric drops below this value
+    pub below: Option<f64>,
+
+    /// The rule fires once the metric rises above this value
+    pub above: Option<f64>,
+
+    /// Distance from the threshold the metric has to recover by before the rule is released,
+    /// which prevents flapping around the threshold
+    #[serde(default)]
+    pub hysteresis: f64,
+}
+
+/// Queue metric a [RuleConfig] applies to
+#[derive(Debug,Clone,Copy,PartialEq,Deserialize,Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RuleMetric {
+    /// Number of people waiting
+    PeopleWaiting,
+
+    /// Waiting time estimation of the town hall in seconds
+    WaitingTime,
+
+    /// Waiting time of the last called ticket as tracked by the exporter in seconds
+    TrackedWaitingTime,
+}
+
+/// Direction in which the threshold of a [RuleConfig] is crossed
+#[derive(Debug,Clone,Copy,PartialEq,Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Condition {
+    Below,
+    Above,
+}
+
+/// HTTP endpoint notified about crossed thresholds with a JSON payload
+#[derive(Debug,Clone,Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookConfig {
+    /// URL the notifications are posted to
+    pub url: String,
+
+    /// Additional headers sent with the notifications, e.g. for authentication
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+}
+
+/// Telegram bot sending notifications to chats
+#[derive(Debug,Clone,Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TelegramConfig {
+    /// Token of the bot as issued by the BotFather
+    pub token: String,
+
+    /// Chats notifications are sent to, other chats are ignored by the bot
+    pub chat_ids: Vec<i64>,
+
+    /// Whether the bot answers `/wait` commands with the current state of the queues
+    pub commands: bool,
+
+    /// Base URL of the bot API, e.g. for a self-hosted bot API server
+    pub api_url: String,
+}
+
+/// ntfy topic notifications are published to
+#[derive(Debug,Clone,Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NtfyConfig {
+    /// Base URL of the ntfy server
+    pub url: String,
+
+    /// Topic the notifications are published to
+    pub topic: String,
+
+    /// Priority of the notifications from 1 (min) to 5 (max)
+    pub priority: u8,
+
+    /// Title of the notifications, derived from the rule if unset
+    pub title: Option<String>,
+
+    /// Access token for authentication
+    pub token: Option<String>,
+
+    /// User for basic authentication
+    pub username: Option<String>,
+
+    /// Password for basic authentication
+    pub password: Option<String>,
+}
+
+/// Mail server and addresses for email notifications
+#[cfg(feature = "smtp")]
+#[derive(Debug,Clone,Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SmtpConfig {
+    /// Host of the mail server
+    pub host: String,
+
+    /// Port of the mail server, defaults to the standard port of the [security](SmtpConfig::security)
+    pub port: Option<u16>,
+
+    /// Encryption of the connection to the mail server
+    pub security: SmtpSecurity,
+
+    /// User for authentication
+    pub username: Option<String>,
+
+    /// Password for authentication
+    pub password: Option<String>,
+
+    /// Sender of the mails
+    pub from: String,
+
+    /// Recipients of the mails
+    pub to: Vec<String>,
+}
+
+/// Encryption of the connection to a mail server
+#[cfg(feature = "smtp")]
+#[derive(Debug,Clone,Copy,PartialEq,Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SmtpSecurity {
+    /// Implicit TLS, usually on port 465
+    Tls,
+
+    /// Upgrade to TLS via `STARTTLS`, usually on port 587
+    Starttls,
+
+    /// Unencrypted connection, only for mail servers on the local host
+    None,
+}
+
+/// Matrix room messages are posted to
+#[derive(Debug,Clone,Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MatrixConfig {
+    /// Base URL of the homeserver
+    pub homeserver: String,
+
+    /// Room the messages are posted to, the account must have joined it
+    pub room_id: String,
+
+    /// Access token of the account posting the messages
+    pub access_token: String,
+
+    /// Whether to also post the state of the queues whenever it changed
+    pub updates: bool,
+
+    /// Minimum interval in seconds between updates, defaults to the
+    /// [scrape interval](Config::scrape_interval)
+    pub interval: Option<u64>,
+}
+
+/// Storage of pages that could not be parsed, for later inspection or as regression fixtures
+#[derive(Debug,Clone,Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CaptureConfig {
+    /// Directory the pages are written to, with timestamped file names
+    pub dir: PathBuf,
+
+    /// Number of captured pages to keep, older ones are removed
+    pub keep: usize,
+}
+
+/// Describes how queue information is extracted from a wait-time page
+#[derive(Debug,Clone,Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Module {
+    /// CSS selector for the queue blocks
+    pub block_selector: String,
+
+    /// CSS selector for the data values inside a block
+    pub value_selector: String,
+
+    /// Only blocks containing this text are considered
+    pub block_content_filter: String,
+
+    /// CSS selector for the heading inside a block, which identifies its queue
+    pub heading_selector: String,
+
+    /// Text contained in the heading of the block for each service
+    ///
+    /// Queues with other headings are exported under a service name derived from the heading.
+    pub headings: HashMap<String, String>,
+}
+
+
+impl Config {
+    /// Read the configuration from a TOML file
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("cannot read config file {}: {}", path.display(), e))?;
+        let config: Config = toml::from_str(&content)
+            .map_err(|e| format!("cannot parse config file {}: {}", path.display(), e))?;
+
+        if config.find_module(&config.module).is_none() {
+            return Err(format!("unknown module '{}'", config.module));
+        }
+        if let Some(name) = config.labels.keys().find(|n| !Self::is_valid_label_name(n)) {
+            return Err(format!("invalid label name '{}'", name));
+        }
+        if let Some(proxy) = &config.proxy {
+            reqwest::Proxy::all(proxy)
+                .map_err(|e| format!("invalid proxy '{}': {}", proxy, e))?;
+        }
+        config.header_map()?;
+        #[cfg(feature = "browser")]
+        if let Some(browser) = config.browser.as_ref().filter(|b| !b.is_file()) {
+            return Err(format!("browser {} not found", browser.display()));
+        }
+        for rule in &config.rules {
+            if rule.below.is_some() == rule.above.is_some() {
+                return Err(format!("rule '{}' must set exactly one of below and above", rule.name));
+            }
+            if !(rule.hysteresis >= 0.0 && rule.hysteresis.is_finite()) {
+                return Err(format!("hysteresis of rule '{}' must not be negative", rule.name));
+            }
+        }
+        if config.zabbix.as_ref().is_some_and(|z| z.host.is_empty()) {
+            return Err(String::from("zabbix.host must be set"));
+        }
+        if config.telegram.as_ref().is_some_and(|t| t.token.is_empty()) {
+            return Err(String::from("telegram.token must be set"));
+        }
+        if let Some(ntfy) = &config.ntfy {
+            if ntfy.topic.is_empty() {
+                return Err(String::from("ntfy.topic must be set"));
+            }
+            if !(1..=5).contains(&ntfy.priority) {
+                return Err(String::from("ntfy.priority must be between 1 and 5"));
+            }
+        }
+        if let Some(matrix) = &config.matrix {
+            if matrix.room_id.is_empty() || matrix.access_token.is_empty() {
+                return Err(String::from("matrix.room_id and matrix.access_token must be set"));
+            }
+            Url::parse(&matrix.homeserver)
+                .map_err(|e| format!("invalid homeserver url '{}': {}", matrix.homeserver, e))?;
+        }
+        #[cfg(feature = "smtp")]
+        if let Some(smtp) = &config.smtp {
+            if smtp.to.is_empty() {
+                return Err(String::from("smtp.to must not be empty"));
+            }
+            crate::smtp::Smtp::new(smtp)?;
+        }
+        if let Some(pushgateway) = &config.pushgateway {
+            pushgateway.group_url()?;
+        }
+        if config.scrape_interval == 0 {
+            return Err(String::from("scrape_interval must be positive"));
+        }
+        if config.widget.critical == 0 {
+            return Err(String::from("widget.critical must be positive"));
+        }
+        if config.service_rate_window == 0 {
+            return Err(String::from("service_rate_window must be positive"));
+        }
+        if !(config.smoothing_factor > 0.0 && config.smoothing_factor <= 1.0) {
+            return Err(String::from("smoothing_factor must be greater than 0 and at most 1"));
+        }
+        #[cfg(feature = "sqlite")]
+        if config.retention.aggregate_interval == 0 || config.retention.compaction_interval == 0 {
+            return Err(String::from("retention intervals must be positive"));
+        }
+        if config.retry.attempts == 0 {
+            return Err(String::from("retry.attempts must be positive"));
+        }
+        if !(config.retry.initial_backoff >= 0.0 && config.retry.max_backoff >= 0.0) {
+            return Err(String::from("retry backoff must not be negative"));
+        }
+
+        Ok(config)
+    }
+
+    /// Check whether a string may be used as a Prometheus label name
+    fn is_valid_label_name(name: &str) -> bool {
+        let mut chars = name.chars();
+        let valid_start = chars.next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_');
+        valid_start && !name.starts_with("__") && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+    }
+
+    /// Headers sent with upstream requests, including the [user agent](Config::user_agent)
+    pub fn header_map(&self) -> Result<HeaderMap, String> {
+        let mut map = HeaderMap::new();
+        let headers = self.headers.iter()
+            .map(|(n, v)| (n.as_str(), v.as_str()))
+            .chain([("User-Agent", self.user_agent.as_str())]);
+        for (name, value) in headers {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| format!("invalid header name '{}'", name))?;
+            let value = HeaderValue::from_str(value)
+                .map_err(|_| format!("invalid value for header '{}'", name))?;
+            map.insert(name, value);
+        }
+        Ok(map)
+    }
+
+    /// Whether the town hall is currently open, if the opening hours are known
+    pub fn office_open(&self) -> Option<bool> {
+        let now = Utc::now().with_timezone(&self.timezone);
+        self.opening_hours.as_ref()
+            .map(|hours| hours.is_open(&now))
+    }
+
+    /// Look up a parser module by name
+    pub fn find_module(&self, name: &str) -> Option<Module> {
+        match self.modules.get(name) {
+            Some(module) => Some(module.clone()),
+            None if name == DEFAULT_MODULE => Some(Module::default()),
+            None => None,
+        }
+    }
+}
+
+impl PushgatewayConfig {
+    /// URL of the metric group identified by the job and instance
+    pub fn group_url(&self) -> Result<Url, String> {
+        let mut url = Url::parse(&self.url)
+            .map_err(|e| format!("invalid pushgateway url '{}': {}", self.url, e))?;
+        url.path_segments_mut()
+            .map_err(|_| format!("invalid pushgateway url '{}'", self.url))?
+            .pop_if_empty()
+            .extend(["metrics", "job", &self.job]);
+        if let Some(instance) = &self.instance {
+            url.path_segments_mut()
+                .expect("URL is known to have a path")
+                .extend(["instance", instance]);
+        }
+        Ok(url)
+    }
+}
+
+impl RuleConfig {
+    pub fn condition(&self) -> Condition {
+        match self.below {
+            Some(_) => Condition::Below,
+            None => Condition::Above,
+        }
+    }
+
+    pub fn threshold(&self) -> f64 {
+        self.below.or(self.above)
+            .expect("Rules are validated with the config")
+    }
+
+    /// Whether the rule fires for a value
+    pub fn holds(&self, value: f64) -> bool {
+        match self.condition() {
+            Condition::Below => value < self.threshold(),
+            Condition::Above => value > self.threshold(),
+        }
+    }
+
+    /// Whether a firing rule is released for a value, taking the hysteresis into account
+    pub fn released(&self, value: f64) -> bool {
+        match self.condition() {
+            Condition::Below => value >= self.threshold() + self.hysteresis,
+            Condition::Above => value <= self.threshold() - self.hysteresis,
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            listen: DEFAULT_LISTEN_ADDR.to_owned(),
+            url: URL.to_owned(),
+            fallback_urls: Vec::new(),
+            proxy: None,
+            user_agent: DEFAULT_USER_AGENT.to_owned(),
+            headers: HashMap::new(),
+            module: DEFAULT_MODULE.to_owned(),
+            modules: HashMap::new(),
+            probe_targets: Vec::new(),
+            legacy_metric_names: false,
+            sample_timestamps: false,
+            labels: HashMap::new(),
+            timezone: chrono_tz::Europe::Berlin,
+            opening_hours: None,
+            scrape_interval: CACHE_EXPIRATION.as_secs(),
+            min_scrape_interval: DEFAULT_MIN_SCRAPE_INTERVAL,
+            closed_scrape_interval: None,
+            service_rate_window: DEFAULT_SERVICE_RATE_WINDOW,
+            max_ticket_age: DEFAULT_MAX_TICKET_AGE,
+            max_tracked_tickets: 1000,
+            waiting_time_summary: false,
+            quantile_window: 60 * 60,
+            smoothing_factor: 0.2,
+            history_size: 720,
+            retry: RetryConfig::default(),
+            max_staleness: DEFAULT_MAX_STALENESS,
+            state_file: None,
+            circuit_breaker: CircuitBreakerConfig::default(),
+            anomalies: AnomalyConfig::default(),
+            widget: WidgetConfig::default(),
+            influxdb: None,
+            graphite: None,
+            statsd: None,
+            remote_write: None,
+            pushgateway: None,
+            mqtt: None,
+            zabbix: None,
+            rules: Vec::new(),
+            webhooks: Vec::new(),
+            telegram: None,
+            ntfy: None,
+            matrix: None,
+            capture: None,
+            #[cfg(feature = "browser")]
+            browser: None,
+            #[cfg(feature = "sqlite")]
+            database: None,
+            #[cfg(feature = "redis")]
+            redis: None,
+            #[cfg(feature = "sqlite")]
+            admin_token: None,
+            #[cfg(feature = "smtp")]
+            smtp: None,
+            #[cfg(feature = "sqlite")]
+            retention: RetentionConfig::default(),
+        }
+    }
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        CircuitBreakerConfig {
+            failures: 5,
+            cooldown: 300,
+        }
+    }
+}
+
+impl Default for AnomalyConfig {
+    fn default() -> Self {
+        AnomalyConfig {
+            queue_spike: 10,
+            max_jump: 50,
+            stall_after: 30 * 60,
+        }
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        RetentionConfig {
+            raw_days: 14,
+            aggregate_interval: 5 * 60,
+            aggregate_days: 365,
+            compaction_interval: 60 * 60,
+        }
+    }
+}
+
+#[cfg(feature = "redis")]
+impl Default for RedisConfig {
+    fn default() -> Self {
+        RedisConfig {
+            url: String::from("redis://localhost/"),
+            prefix: String::from(env!("CARGO_PKG_NAME")),
+        }
+    }
+}
+
+impl Default for WidgetConfig {
+    fn default() -> Self {
+        WidgetConfig {
+            service: None,
+            warning: 20 * 60,
+            critical: 40 * 60,
+        }
+    }
+}
+
+impl Default for InfluxDbConfig {
+    fn default() -> Self {
+        InfluxDbConfig {
+            url: String::from("http://localhost:8086"),
+            database: String::from("erth"),
+            org: String::new(),
+            token: None,
+            username: None,
+            password: None,
+            measurement: String::from("erth"),
+            interval: None,
+        }
+    }
+}
+
+impl Default for GraphiteConfig {
+    fn default() -> Self {
+        GraphiteConfig {
+            host: String::from("localhost"),
+            port: 2003,
+            prefix: String::from("erth"),
+            interval: None,
+        }
+    }
+}
+
+impl Default for StatsdConfig {
+    fn default() -> Self {
+        StatsdConfig {
+            host: String::from("localhost"),
+            port: 8125,
+            prefix: String::from("erth"),
+            tags: false,
+            interval: None,
+        }
+    }
+}
+
+impl Default for PushgatewayConfig {
+    fn default() -> Self {
+        PushgatewayConfig {
+            url: String::from("http://localhost:9091"),
+            job: String::from(env!("CARGO_PKG_NAME")),
+            instance: None,
+            username: None,
+            password: None,
+            interval: None,
+        }
+    }
+}
+
+impl Default for MqttConfig {
+    fn default() -> Self {
+        MqttConfig {
+            host: String::from("localhost"),
+            port: 1883,
+            client_id: String::from(env!("CARGO_PKG_NAME")),
+            username: None,
+            password: None,
+            topic_prefix: String::from("erth"),
+            discovery: true,
+            discovery_prefix: String::from("homeassistant"),
+            interval: None,
+        }
+    }
+}
+
+impl Default for ZabbixConfig {
+    fn default() -> Self {
+        ZabbixConfig {
+            server: String::from("localhost"),
+            port: 10051,
+            host: String::new(),
+            people_waiting_key: String::from("erth.people_waiting[{service}]"),
+            waiting_time_key: String::from("erth.waiting_time[{service}]"),
+            tracked_waiting_time_key: String::from("erth.tracked_waiting_time[{service}]"),
+            interval: None,
+        }
+    }
+}
+
+impl Default for TelegramConfig {
+    fn default() -> Self {
+        TelegramConfig {
+            token: String::new(),
+            chat_ids: Vec::new(),
+            commands: true,
+            api_url: String::from("https://api.telegram.org"),
+        }
+    }
+}
+
+impl Default for NtfyConfig {
+    fn default() -> Self {
+        NtfyConfig {
+            url: String::from("https://ntfy.sh"),
+            topic: String::new(),
+            priority: 3,
+            title: None,
+            token: None,
+            username: None,
+            password: None,
+        }
+    }
+}
+
+#[cfg(feature = "smtp")]
+impl Default for SmtpConfig {
+    fn default() -> Self {
+        SmtpConfig {
+            host: String::from("localhost"),
+            port: None,
+            security: SmtpSecurity::Starttls,
+            username: None,
+            password: None,
+            from: format!("{} <{}@localhost>", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_NAME")),
+            to: Vec::new(),
+        }
+    }
+}
+
+impl Default for MatrixConfig {
+    fn default() -> Self {
+        MatrixConfig {
+            homeserver: String::from("https://matrix.org"),
+            room_id: String::new(),
+            access_token: String::new(),
+            updates: false,
+            interval: None,
+        }
+    }
+}
+
+impl Default for CaptureConfig {
+    fn default() -> Self {
+        CaptureConfig {
+            dir: PathBuf::from("captures"),
+            keep: 20,
+        }
+    }
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        RetryConfig {
+            attempts: 3,
+            initial_backoff: 0.5,
+            max_backoff: 4.0,
+        }
+    }
+}
+
+impl Default for Module {
+    fn default() -> Self {
+        Module {
+            block_selector: BLOCK_SELECTOR.to_owned(),
+            value_selector: VALUE_SELECTOR.to_owned(),
+            block_content_filter: BLOCK_CONTENT_FILTER.to_owned(),
+            heading_selector: HEADING_SELECTOR.to_owned(),
+            headings: HashMap::from([
+                (String::from("citizen"), String::from("Bürgerservice")),
+                (String::from("drivers_license"), String::from("Fahrerlaubnis")),
+            ]),
+        }
+    }
+}
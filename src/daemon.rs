@@ -0,0 +1,98 @@
+//! Detaching from the terminal for classic init systems, enabled with `--daemonize`
+use std::env;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, PipeReader};
+use std::os::fd::OwnedFd;
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
+
+use daemonize::Daemonize;
+
+
+/// Socket of the local syslog daemon
+const SYSLOG_SOCKET: &str = "/dev/log";
+
+/// Syslog facility of system daemons, shifted for use in the priority
+const SYSLOG_FACILITY: u8 = 3 << 3;
+
+
+/// Destination of the log messages of the daemon
+#[derive(Debug,Clone)]
+pub enum Log {
+    /// Log messages are dropped
+    Discard,
+
+    /// Log messages are appended to a file
+    File(PathBuf),
+
+    /// Log messages are sent to the local syslog daemon
+    Syslog,
+}
+
+
+/// Fork into the background, detaching from the terminal, and exit the original process
+///
+/// The working directory is kept, so that relative paths in the configuration still work.
+/// Standard output and error are redirected according to `log`.
+pub fn daemonize(pid_file: Option<&Path>, log: Log) -> Result<(), String> {
+    let working_directory = env::current_dir()
+        .map_err(|e| format!("cannot determine working directory: {}", e))?;
+    let mut daemon = Daemonize::new()
+        .working_directory(working_directory);
+    if let Some(path) = pid_file {
+        daemon = daemon.pid_file(path);
+    }
+
+    let mut syslog = None;
+    let output = match log {
+        Log::Discard => None,
+        Log::File(path) => {
+            let file = OpenOptions::new().create(true).append(true).open(&path)
+                .map_err(|e| format!("cannot open log file {}: {}", path.display(), e))?;
+            Some(file)
+        },
+        Log::Syslog => {
+            let socket = UnixDatagram::unbound()
+                .and_then(|s| s.connect(SYSLOG_SOCKET).map(|_| s))
+                .map_err(|e| format!("cannot connect to syslog: {}", e))?;
+            let (reader, writer) = io::pipe()
+                .map_err(|e| format!("cannot create pipe: {}", e))?;
+            syslog = Some((reader, socket));
+            Some(File::from(OwnedFd::from(writer)))
+        },
+    };
+    if let Some(output) = output {
+        let stdout = output.try_clone()
+            .map_err(|e| e.to_string())?;
+        daemon = daemon.stdout(stdout).stderr(output);
+    }
+
+    daemon.start()
+        .map_err(|e| format!("cannot daemonize: {}", e))?;
+
+    // threads do not survive forking, so the forwarding can only start in the daemon
+    if let Some((reader, socket)) = syslog {
+        thread::spawn(move || forward_to_syslog(reader, socket));
+    }
+    Ok(())
+}
+
+
+/// Send each line written to the pipe to syslog, with the priority derived from the `Error:` or
+/// `Warning:` prefix of the message
+fn forward_to_syslog(reader: PipeReader, socket: UnixDatagram) {
+    let pid = process::id();
+    for line in BufReader::new(reader).lines().map_while(Result::ok) {
+        let severity = if line.starts_with("Error") {
+            3
+        } else if line.starts_with("Warning") {
+            4
+        } else {
+            6
+        };
+        let message = format!("<{}>{}[{}]: {}", SYSLOG_FACILITY | severity, env!("CARGO_PKG_NAME"), pid, line);
+        let _ = socket.send(message.as_bytes());
+    }
+}
@@ -0,0 +1,276 @@
+//! Long-term persistence of scraped data frames in a SQLite database
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use rusqlite::{params, Connection, OpenFlags};
+use tempfile::NamedTempFile;
+
+use crate::config::RetentionConfig;
+use crate::history::{self, HistoryFrame, Sample};
+use crate::unix_timestamp;
+
+
+/// How long to wait for locks held by other connections to the database
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+/// Length of a day in seconds
+const DAY: f64 = 24.0 * 60.0 * 60.0;
+
+
+/// Database holding one row per queue and scraped data frame
+///
+/// Older rows are [compacted](Database::compact) into aggregates over fixed time spans.
+pub struct Database {
+    connection: Connection,
+}
+
+
+impl Database {
+    /// Open the database at `path`, creating it and its schema if necessary
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let connection = Connection::open(path)
+            .map_err(|e| format!("cannot open database {}: {}", path.display(), e))?;
+        connection.busy_timeout(BUSY_TIMEOUT)
+            .map_err(|e| format!("cannot open database {}: {}", path.display(), e))?;
+        connection.execute_batch("
+            CREATE TABLE IF NOT EXISTS samples (
+                timestamp REAL NOT NULL,
+                service TEXT NOT NULL,
+                people_waiting INTEGER NOT NULL,
+                last_called_ticket TEXT NOT NULL,
+                waiting_time_seconds REAL NOT NULL,
+                tracked_waiting_time_seconds REAL
+            );
+            CREATE INDEX IF NOT EXISTS samples_timestamp ON samples (timestamp);
+            CREATE TABLE IF NOT EXISTS aggregates (
+                timestamp REAL NOT NULL,
+                service TEXT NOT NULL,
+                people_waiting REAL NOT NULL,
+                last_called_ticket TEXT NOT NULL,
+                waiting_time_seconds REAL NOT NULL,
+                tracked_waiting_time_seconds REAL,
+                samples INTEGER NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS aggregates_timestamp ON aggregates (timestamp);
+        ").map_err(|e| format!("cannot initialize database {}: {}", path.display(), e))?;
+
+        Ok(Database { connection })
+    }
+
+    /// Open the existing database at `path` for reading only
+    pub fn open_read_only(path: &Path) -> Result<Self, String> {
+        let connection = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .map_err(|e| format!("cannot open database {}: {}", path.display(), e))?;
+        connection.busy_timeout(BUSY_TIMEOUT)
+            .map_err(|e| format!("cannot open database {}: {}", path.display(), e))?;
+        Ok(Database { connection })
+    }
+
+    /// Store the samples of all queues in a frame
+    pub fn insert(&mut self, frame: &HistoryFrame) -> Result<(), String> {
+        let transaction = self.connection.transaction()
+            .map_err(|e| format!("cannot store data frame: {}", e))?;
+        for (service, sample) in &frame.queues {
+            transaction.execute(
+                "INSERT INTO samples VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![frame.timestamp, service, sample.people_waiting, sample.last_called_ticket,
+                        sample.waiting_time_seconds, sample.tracked_waiting_time_seconds],
+            ).map_err(|e| format!("cannot store data frame: {}", e))?;
+        }
+        transaction.commit()
+            .map_err(|e| format!("cannot store data frame: {}", e))
+    }
+
+    /// Write a consistent snapshot of the database to a new file
+    pub fn backup(&self, path: &Path) -> Result<(), String> {
+        self.connection.execute("VACUUM INTO ?1", params![path.to_string_lossy()])
+            .map(|_| ())
+            .map_err(|e| format!("cannot back up database to {}: {}", path.display(), e))
+    }
+
+    /// Take a consistent snapshot of the database into a new temporary file next to it, which is
+    /// removed when dropped
+    pub fn snapshot(&self) -> Result<NamedTempFile, String> {
+        let path = Path::new(self.connection.path().unwrap_or_default());
+        let dir = path.parent().filter(|d| !d.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        let file = tempfile::Builder::new()
+            .prefix(".erth-exporter-backup-")
+            .suffix(".sqlite")
+            .tempfile_in(dir)
+            .map_err(|e| format!("cannot create snapshot in {}: {}", dir.display(), e))?;
+        // the file may exist as long as it is empty
+        self.backup(file.path())?;
+        Ok(file)
+    }
+
+    /// Replace the database at `path` by a snapshot, after checking that it is a valid database
+    pub fn restore(snapshot: &Path, path: &Path) -> Result<(), String> {
+        let connection = Connection::open_with_flags(snapshot, OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .map_err(|e| format!("cannot open snapshot {}: {}", snapshot.display(), e))?;
+        connection.query_row("SELECT COUNT(*) FROM samples", [], |_| Ok(()))
+            .and_then(|_| connection.query_row("SELECT COUNT(*) FROM aggregates", [], |_| Ok(())))
+            .map_err(|e| format!("invalid snapshot {}: {}", snapshot.display(), e))?;
+        drop(connection);
+
+        let temporary = path.with_extension("tmp");
+        fs::copy(snapshot, &temporary)
+            .and_then(|_| fs::rename(&temporary, path))
+            .map(|_| ())
+            .map_err(|e| format!("cannot restore database {}: {}", path.display(), e))
+    }
+
+    /// Import samples from a file, skipping the ones already stored
+    ///
+    /// The file may be a CSV export (`.csv`), a `/history` dump (`.json`) or the database of
+    /// another instance. Returns the number of imported samples.
+    pub fn import(&mut self, path: &Path) -> Result<usize, String> {
+        let extension = path.extension().and_then(|e| e.to_str());
+        let frames = match extension {
+            Some("csv") | Some("json") => {
+                let content = fs::read_to_string(path)
+                    .map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+                match extension {
+                    Some("csv") => history::from_csv(&content),
+                    _ => serde_json::from_str(&content).map_err(|e| e.to_string()),
+                }.map_err(|e| format!("cannot parse {}: {}", path.display(), e))?
+            },
+            _ => return self.import_database(path),
+        };
+
+        let transaction = self.connection.transaction()
+            .map_err(|e| format!("cannot import samples: {}", e))?;
+        let mut imported = 0;
+        for frame in &frames {
+            for (service, sample) in &frame.queues {
+                imported += transaction.execute("
+                    INSERT INTO samples SELECT ?1, ?2, ?3, ?4, ?5, ?6
+                    WHERE NOT EXISTS (SELECT 1 FROM samples WHERE timestamp = ?1 AND service = ?2)
+                ", params![frame.timestamp, service, sample.people_waiting, sample.last_called_ticket,
+                           sample.waiting_time_seconds, sample.tracked_waiting_time_seconds],
+                ).map_err(|e| format!("cannot import samples: {}", e))?;
+            }
+        }
+        transaction.commit()
+            .map_err(|e| format!("cannot import samples: {}", e))?;
+
+        Ok(imported)
+    }
+
+    /// Import the samples and aggregates of another database
+    fn import_database(&mut self, path: &Path) -> Result<usize, String> {
+        let error = |e: rusqlite::Error| format!("cannot import database {}: {}", path.display(), e);
+        self.connection.execute("ATTACH DATABASE ?1 AS other", params![path.to_string_lossy()])
+            .map_err(error)?;
+        let imported = self.connection.transaction()
+            .and_then(|transaction| {
+                let imported = transaction.execute("
+                    INSERT INTO samples SELECT * FROM other.samples o
+                    WHERE NOT EXISTS (SELECT 1 FROM samples WHERE timestamp = o.timestamp AND service = o.service)
+                ", [])? + transaction.execute("
+                    INSERT INTO aggregates SELECT * FROM other.aggregates o
+                    WHERE NOT EXISTS (SELECT 1 FROM aggregates WHERE timestamp = o.timestamp AND service = o.service)
+                ", [])?;
+                transaction.commit()?;
+                Ok(imported)
+            });
+        self.connection.execute("DETACH DATABASE other", [])
+            .map_err(error)?;
+
+        imported.map_err(error)
+    }
+
+    /// Frames stored between `from` and `to` (inclusive, seconds since the epoch), oldest first
+    ///
+    /// Aggregates are returned as frames at the start of their time span, with average values.
+    pub fn between(&self, from: f64, to: f64) -> Result<Vec<HistoryFrame>, String> {
+        let mut statement = self.connection.prepare("
+            SELECT timestamp, service, CAST(ROUND(people_waiting) AS INTEGER), last_called_ticket,
+                waiting_time_seconds, tracked_waiting_time_seconds
+            FROM aggregates WHERE timestamp BETWEEN ?1 AND ?2
+            UNION ALL
+            SELECT timestamp, service, people_waiting, last_called_ticket,
+                waiting_time_seconds, tracked_waiting_time_seconds
+            FROM samples WHERE timestamp BETWEEN ?1 AND ?2
+            ORDER BY timestamp, service
+        ").map_err(|e| format!("cannot query data frames: {}", e))?;
+        let rows = statement.query_map(params![from, to], |row| {
+            Ok((row.get::<_, f64>(0)?, row.get::<_, String>(1)?, Sample {
+                people_waiting: row.get(2)?,
+                last_called_ticket: row.get(3)?,
+                waiting_time_seconds: row.get(4)?,
+                tracked_waiting_time_seconds: row.get(5)?,
+            }))
+        }).map_err(|e| format!("cannot query data frames: {}", e))?;
+
+        let mut frames: Vec<HistoryFrame> = Vec::new();
+        for row in rows {
+            let (timestamp, service, sample) = row
+                .map_err(|e| format!("cannot read data frame: {}", e))?;
+            match frames.last_mut() {
+                Some(frame) if frame.timestamp == timestamp => {
+                    frame.queues.insert(service, sample);
+                },
+                _ => frames.push(HistoryFrame {
+                    timestamp,
+                    queues: [(service, sample)].into(),
+                }),
+            }
+        }
+
+        Ok(frames)
+    }
+
+    /// Replace data frames older than the retention period by aggregates and delete expired
+    /// aggregates
+    ///
+    /// Samples in time spans that already have an aggregate, e.g. from an
+    /// [import](Database::import), are merged into it.
+    pub fn compact(&mut self, retention: &RetentionConfig) -> Result<(), String> {
+        let now = unix_timestamp().as_secs_f64();
+        let interval = retention.aggregate_interval as f64;
+        // only aggregate complete time spans
+        let raw_cutoff = ((now - retention.raw_days as f64 * DAY) / interval).floor() * interval;
+        let aggregate_cutoff = now - retention.aggregate_days as f64 * DAY;
+
+        let transaction = self.connection.transaction()
+            .map_err(|e| format!("cannot compact database: {}", e))?;
+        transaction.execute("
+            CREATE TEMP TABLE compacted AS
+            SELECT CAST(timestamp / ?1 AS INTEGER) * ?1 AS timestamp, service, AVG(people_waiting) AS people_waiting,
+                (SELECT l.last_called_ticket FROM samples l
+                    WHERE l.service = s.service AND l.timestamp < CAST(s.timestamp / ?1 AS INTEGER) * ?1 + ?1
+                    ORDER BY l.timestamp DESC LIMIT 1) AS last_called_ticket,
+                AVG(waiting_time_seconds) AS waiting_time_seconds,
+                AVG(tracked_waiting_time_seconds) AS tracked_waiting_time_seconds, COUNT(*) AS samples
+            FROM samples s WHERE timestamp < ?2
+            GROUP BY 1, service
+        ", params![interval, raw_cutoff]).map_err(|e| format!("cannot compact database: {}", e))?;
+        // samples imported into time spans that were already compacted are merged into their
+        // aggregates, weighted by the number of samples
+        transaction.execute_batch("
+            UPDATE aggregates SET
+                people_waiting = (aggregates.people_waiting * aggregates.samples + c.people_waiting * c.samples)
+                    / (aggregates.samples + c.samples),
+                waiting_time_seconds = (aggregates.waiting_time_seconds * aggregates.samples
+                    + c.waiting_time_seconds * c.samples) / (aggregates.samples + c.samples),
+                tracked_waiting_time_seconds = CASE
+                    WHEN aggregates.tracked_waiting_time_seconds IS NULL THEN c.tracked_waiting_time_seconds
+                    WHEN c.tracked_waiting_time_seconds IS NULL THEN aggregates.tracked_waiting_time_seconds
+                    ELSE (aggregates.tracked_waiting_time_seconds * aggregates.samples
+                        + c.tracked_waiting_time_seconds * c.samples) / (aggregates.samples + c.samples)
+                END,
+                samples = aggregates.samples + c.samples
+            FROM compacted c WHERE aggregates.timestamp = c.timestamp AND aggregates.service = c.service;
+            INSERT INTO aggregates
+            SELECT * FROM compacted c
+            WHERE NOT EXISTS (SELECT 1 FROM aggregates WHERE timestamp = c.timestamp AND service = c.service);
+            DROP TABLE compacted;
+        ").map_err(|e| format!("cannot compact database: {}", e))?;
+        transaction.execute("DELETE FROM samples WHERE timestamp < ?1", params![raw_cutoff])
+            .map_err(|e| format!("cannot compact database: {}", e))?;
+        transaction.execute("DELETE FROM aggregates WHERE timestamp < ?1", params![aggregate_cutoff])
+            .map_err(|e| format!("cannot compact database: {}", e))?;
+        transaction.commit()
+            .map_err(|e| format!("cannot compact database: {}", e))
+    }
+}
@@ -0,0 +1,312 @@
+//! Live updates of scraped data frames for connected clients
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use base64::Engine;
+use prometheus::proto::MetricFamily;
+use sha1::{Digest, Sha1};
+
+use crate::history::HistoryFrame;
+use crate::push::Sink;
+use crate::HTTP_VERSION;
+
+
+/// Timeout for writing an event to a client, slower clients are disconnected
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// GUID appended to the key of a WebSocket handshake, see RFC 6455
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Limit for unprocessed data sent by a WebSocket client, larger messages disconnect the client
+const MAX_CLIENT_BUFFER: usize = 64 * 1024;
+
+/// WebSocket opcodes, see RFC 6455 section 5.2
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xa;
+
+
+/// Sends every new data frame as JSON to all subscribed clients, either as
+/// [server-sent event](https://html.spec.whatwg.org/multipage/server-sent-events.html) via
+/// `/events` or as WebSocket text message via `/ws`
+#[derive(Clone)]
+pub struct EventStream {
+    clients: Arc<Mutex<Vec<Client>>>,
+}
+
+/// Connection of a subscribed client
+struct Client {
+    stream: TcpStream,
+    protocol: Protocol,
+
+    /// Timestamp of the last frame sent to the client
+    last_sent: Option<f64>,
+
+    /// Data received from a WebSocket client that does not form a complete frame yet
+    received: Vec<u8>,
+}
+
+/// How frames are sent to a client
+#[derive(Debug,Clone,Copy,PartialEq)]
+enum Protocol {
+    ServerSentEvents,
+    WebSocket,
+}
+
+
+impl EventStream {
+    pub fn new() -> Self {
+        EventStream {
+            clients: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Start the event stream on a connection, sending the current frame right away if there is one
+    pub fn subscribe(&self, mut stream: TcpStream, current: Option<&HistoryFrame>) -> io::Result<()> {
+        stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+        write!(stream, "{HTTP_VERSION} 200 OK\r\n")?;
+        write!(stream, "Content-Type: text/event-stream\r\n")?;
+        write!(stream, "Cache-Control: no-cache\r\n")?;
+        write!(stream, "Access-Control-Allow-Origin: *\r\n\r\n")?;
+        self.add(stream, Protocol::ServerSentEvents, current)
+    }
+
+    /// Complete a WebSocket handshake with the `Sec-WebSocket-Key` sent by the client and
+    /// subscribe the connection, sending the current frame right away if there is one
+    pub fn subscribe_websocket(&self, mut stream: TcpStream, key: &str, current: Option<&HistoryFrame>) -> io::Result<()> {
+        let accept = websocket_accept(key);
+        stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+        write!(stream, "{HTTP_VERSION} 101 SWITCHING PROTOCOLS\r\n")?;
+        write!(stream, "Upgrade: websocket\r\n")?;
+        write!(stream, "Connection: Upgrade\r\n")?;
+        write!(stream, "Sec-WebSocket-Accept: {accept}\r\n\r\n")?;
+        self.add(stream, Protocol::WebSocket, current)
+    }
+
+    fn add(&self, mut stream: TcpStream, protocol: Protocol, current: Option<&HistoryFrame>) -> io::Result<()> {
+        if let Some(frame) = current {
+            stream.write_all(&encode(frame, protocol)?)?;
+        }
+        stream.flush()?;
+
+        self.clients.lock().unwrap().push(Client {
+            stream,
+            protocol,
+            last_sent: current.map(|f| f.timestamp),
+            received: Vec::new(),
+        });
+        Ok(())
+    }
+}
+
+impl Sink for EventStream {
+    fn name(&self) -> &str {
+        "event stream"
+    }
+
+    fn push(&mut self, frame: &HistoryFrame, _families: &[MetricFamily]) -> Result<(), String> {
+        let event = encode(frame, Protocol::ServerSentEvents)
+            .map_err(|e| e.to_string())?;
+        let message = encode(frame, Protocol::WebSocket)
+            .map_err(|e| e.to_string())?;
+        self.clients.lock().unwrap()
+            .retain_mut(|client| {
+                if client.protocol == Protocol::WebSocket && !client.poll().unwrap_or(false) {
+                    return false;
+                }
+                if client.last_sent == Some(frame.timestamp) {
+                    return true;
+                }
+                client.last_sent = Some(frame.timestamp);
+                let data = match client.protocol {
+                    Protocol::ServerSentEvents => &event,
+                    Protocol::WebSocket => &message,
+                };
+                client.stream.write_all(data).and_then(|_| client.stream.flush()).is_ok()
+            });
+        Ok(())
+    }
+}
+
+impl Client {
+    /// Read and answer the frames sent by a WebSocket client without blocking, returns
+    /// whether the client is still connected
+    fn poll(&mut self) -> io::Result<bool> {
+        self.stream.set_nonblocking(true)?;
+        let mut buffer = [0; 4096];
+        let read = loop {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => break Ok(false),
+                Ok(n) => self.received.extend_from_slice(&buffer[..n]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break Ok(true),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => break Err(e),
+            }
+            if self.received.len() > MAX_CLIENT_BUFFER {
+                break Ok(false);
+            }
+        };
+        self.stream.set_nonblocking(false)?;
+        if !read? {
+            return Ok(false);
+        }
+
+        while let Some((opcode, payload, length)) = decode(&self.received) {
+            self.received.drain(..length);
+            match opcode {
+                OPCODE_PING => self.stream.write_all(&websocket_frame(OPCODE_PONG, &payload))?,
+                OPCODE_CLOSE => {
+                    // echo the status code, the connection is closed afterwards
+                    let status = payload.get(..2).unwrap_or_default();
+                    self.stream.write_all(&websocket_frame(OPCODE_CLOSE, status))?;
+                    self.stream.flush()?;
+                    return Ok(false);
+                },
+                _ => (),
+            }
+        }
+        self.stream.flush()?;
+        Ok(true)
+    }
+}
+
+
+/// Encode a frame as JSON, either in a `frame` event or a WebSocket text message
+fn encode(frame: &HistoryFrame, protocol: Protocol) -> io::Result<Vec<u8>> {
+    let data = serde_json::to_string(frame)
+        .map_err(io::Error::other)?;
+    match protocol {
+        Protocol::ServerSentEvents => Ok(format!("event: frame\ndata: {}\n\n", data).into_bytes()),
+        Protocol::WebSocket => Ok(websocket_frame(OPCODE_TEXT, data.as_bytes())),
+    }
+}
+
+/// Final WebSocket frame with the given opcode, unmasked as sent by the server
+fn websocket_frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mut message = vec![0x80 | opcode];
+    match payload.len() {
+        len @ 0..=125 => message.push(len as u8),
+        len @ 126..=0xffff => {
+            message.push(126);
+            message.extend_from_slice(&(len as u16).to_be_bytes());
+        },
+        len => {
+            message.push(127);
+            message.extend_from_slice(&(len as u64).to_be_bytes());
+        },
+    }
+    message.extend_from_slice(payload);
+    message
+}
+
+/// Decode the first WebSocket frame sent by a client, returns the opcode, the unmasked
+/// payload and the length of the frame or `None` if the frame is not complete yet
+fn decode(data: &[u8]) -> Option<(u8, Vec<u8>, usize)> {
+    let opcode = data.first()? & 0x0f;
+    let masked = data.get(1)? & 0x80 != 0;
+    let (length, mut offset) = match data[1] & 0x7f {
+        126 => (u16::from_be_bytes(data.get(2..4)?.try_into().ok()?) as usize, 4),
+        127 => (usize::try_from(u64::from_be_bytes(data.get(2..10)?.try_into().ok()?)).ok()?, 10),
+        len => (len as usize, 2),
+    };
+    let mask = if masked {
+        offset += 4;
+        data.get(offset - 4..offset)?
+    } else {
+        &[0; 4]
+    };
+    let payload = data.get(offset..offset.checked_add(length)?)?
+        .iter()
+        .enumerate()
+        .map(|(i, b)| b ^ mask[i % 4])
+        .collect();
+    Some((opcode, payload, offset + length))
+}
+
+/// `Sec-WebSocket-Accept` answering the `Sec-WebSocket-Key` of a handshake
+fn websocket_accept(key: &str) -> String {
+    base64::engine::general_purpose::STANDARD
+        .encode(Sha1::digest(format!("{}{}", key.trim(), WEBSOCKET_GUID)))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::history::Sample;
+
+    use super::*;
+
+    fn frame(service: &str) -> HistoryFrame {
+        HistoryFrame {
+            timestamp: 1.5,
+            queues: [(service.to_owned(), Sample {
+                people_waiting: 3,
+                last_called_ticket: String::from("B012"),
+                waiting_time_seconds: 600.0,
+                tracked_waiting_time_seconds: None,
+            })].into(),
+        }
+    }
+
+    #[test]
+    fn accept_key() {
+        // example handshake of RFC 6455
+        assert_eq!(websocket_accept("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+
+    #[test]
+    fn server_sent_event() {
+        let frame = frame("citizen");
+        let event = String::from_utf8(encode(&frame, Protocol::ServerSentEvents).unwrap()).unwrap();
+        let data = serde_json::to_string(&frame).unwrap();
+        assert_eq!(event, format!("event: frame\ndata: {}\n\n", data));
+    }
+
+    #[test]
+    fn websocket_message() {
+        for service in [String::from("citizen"), "x".repeat(200), "x".repeat(70_000)] {
+            let frame = frame(&service);
+            let data = serde_json::to_vec(&frame).unwrap();
+            let message = encode(&frame, Protocol::WebSocket).unwrap();
+            assert_eq!(message[0], 0x81);
+            let payload = match data.len() {
+                len @ 0..=125 => {
+                    assert_eq!(message[1] as usize, len);
+                    &message[2..]
+                },
+                len @ 126..=0xffff => {
+                    assert_eq!(message[1], 126);
+                    assert_eq!(u16::from_be_bytes([message[2], message[3]]) as usize, len);
+                    &message[4..]
+                },
+                len => {
+                    assert_eq!(message[1], 127);
+                    assert_eq!(u64::from_be_bytes(message[2..10].try_into().unwrap()) as usize, len);
+                    &message[10..]
+                },
+            };
+            assert_eq!(payload, data);
+            assert_eq!(decode(&message), Some((OPCODE_TEXT, data, message.len())));
+        }
+    }
+
+    #[test]
+    fn client_frames() {
+        // masked ping with payload "Hello" and close with status 1000 of RFC 6455
+        let ping = [0x89, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58];
+        let close = [0x88, 0x82, 0x01, 0x02, 0x03, 0x04, 0x02, 0xea];
+        let data = [&ping[..], &close[..]].concat();
+
+        assert_eq!(decode(&data), Some((OPCODE_PING, b"Hello".to_vec(), ping.len())));
+        assert_eq!(decode(&data[ping.len()..]), Some((OPCODE_CLOSE, 1000u16.to_be_bytes().to_vec(), close.len())));
+        for len in 0..ping.len() {
+            assert_eq!(decode(&ping[..len]), None);
+        }
+
+        assert_eq!(websocket_frame(OPCODE_PONG, b"Hello"), [0x8a, 0x05, b'H', b'e', b'l', b'l', b'o']);
+        assert_eq!(websocket_frame(OPCODE_CLOSE, &[]), [0x88, 0x00]);
+    }
+}
@@ -0,0 +1,131 @@
+//! Atom feed of significant changes of the queues, served on `/feed.xml`
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use chrono_tz::Tz;
+
+use crate::notify::{Event, Notifier};
+use crate::opening_hours::OpeningHours;
+use crate::status::escape;
+use crate::unix_timestamp;
+
+
+/// Number of entries kept in the feed
+const FEED_SIZE: usize = 50;
+
+/// Interval at which the opening hours are checked for the town hall opening or closing
+const OPENING_HOURS_INTERVAL: Duration = Duration::from_secs(60);
+
+
+/// Most recent significant changes, i.e. the town hall opening or closing and crossed
+/// [rules](crate::config::Config::rules)
+///
+/// Receives crossed thresholds as [Notifier].
+#[derive(Clone)]
+pub struct Feed {
+    /// Entries of the feed, newest first
+    entries: Arc<Mutex<VecDeque<Entry>>>,
+}
+
+/// Single change
+#[derive(Debug,Clone)]
+struct Entry {
+    /// Time of the change in seconds since the epoch
+    timestamp: f64,
+
+    title: String,
+    content: String,
+}
+
+
+impl Feed {
+    pub fn new() -> Self {
+        Feed {
+            entries: Arc::new(Mutex::new(VecDeque::with_capacity(FEED_SIZE))),
+        }
+    }
+
+    /// Add entries whenever the town hall opens or closes according to its opening hours in a
+    /// background thread
+    pub fn watch_opening_hours(&self, opening_hours: OpeningHours, timezone: Tz) {
+        let feed = self.clone();
+        thread::spawn(move || {
+            let mut was_open = opening_hours.is_open(&Utc::now().with_timezone(&timezone));
+            loop {
+                thread::sleep(OPENING_HOURS_INTERVAL);
+                let open = opening_hours.is_open(&Utc::now().with_timezone(&timezone));
+                if open != was_open {
+                    let (title, content) = match open {
+                        true => ("Town hall opened", "The town hall opened according to its opening hours"),
+                        false => ("Town hall closed", "The town hall closed according to its opening hours"),
+                    };
+                    feed.add(Entry {
+                        timestamp: unix_timestamp().as_secs_f64(),
+                        title: title.to_owned(),
+                        content: content.to_owned(),
+                    });
+                }
+                was_open = open;
+            }
+        });
+    }
+
+    /// Render the feed in Atom format
+    pub fn render(&self) -> String {
+        let entries = self.entries.lock().unwrap();
+        let updated = entries.front().map_or(0.0, |e| e.timestamp);
+
+        let mut feed = String::new();
+        feed.push_str("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+        let _ = writeln!(feed, "<id>urn:{}:feed</id>", env!("CARGO_PKG_NAME"));
+        feed.push_str("<title>Bürgeramt queue status</title>\n");
+        let _ = writeln!(feed, "<updated>{}</updated>", format_time(updated));
+        let _ = writeln!(feed, "<author><name>{}</name></author>", env!("CARGO_PKG_NAME"));
+        for entry in entries.iter() {
+            let id: String = entry.title.chars()
+                .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '-' })
+                .collect();
+            let _ = writeln!(feed, "<entry>\n<id>urn:{}:feed:{}:{}</id>\n<title>{}</title>\n<updated>{}</updated>\n\
+                <content type=\"text\">{}</content>\n</entry>",
+                env!("CARGO_PKG_NAME"), (entry.timestamp * 1000.0) as i64, id,
+                escape(&entry.title), format_time(entry.timestamp), escape(&entry.content));
+        }
+        feed.push_str("</feed>\n");
+        feed
+    }
+
+    fn add(&self, entry: Entry) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() == FEED_SIZE {
+            entries.pop_back();
+        }
+        entries.push_front(entry);
+    }
+}
+
+impl Notifier for Feed {
+    fn name(&self) -> &str {
+        "feed"
+    }
+
+    fn notify(&mut self, event: &Event) -> Result<(), String> {
+        self.add(Entry {
+            timestamp: event.timestamp,
+            title: event.title(),
+            content: event.message(),
+        });
+        Ok(())
+    }
+}
+
+
+/// Format seconds since the epoch as RFC 3339 timestamp in UTC
+fn format_time(timestamp: f64) -> String {
+    DateTime::from_timestamp_millis((timestamp * 1000.0) as i64)
+        .unwrap_or_default()
+        .to_rfc3339_opts(SecondsFormat::Secs, true)
+}
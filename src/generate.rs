@@ -0,0 +1,262 @@
+//! Configuration snippets for the monitoring stack, printed by the `generate` command
+use std::fmt::Write;
+use std::net::SocketAddr;
+
+use serde_json::{json, Value};
+
+use crate::config::Config;
+
+
+/// Path the metrics are served on
+const METRICS_PATH: &str = "/metrics";
+
+/// Path the metrics of other pages are served on
+const PROBE_PATH: &str = "/probe";
+
+/// Width of the dashboard grid in Grafana
+const GRID_WIDTH: u64 = 24;
+
+/// Height of a dashboard panel in grid units
+const PANEL_HEIGHT: u64 = 8;
+
+/// Minutes without called tickets after which a queue is considered stuck
+const STUCK_QUEUE_MINUTES: u64 = 30;
+
+
+/// Prometheus `scrape_configs` for the exporter, including a multi-target job for the
+/// [probe targets](Config::probe_targets) if there are any
+pub fn scrape_config(config: &Config) -> String {
+    let name = env!("CARGO_PKG_NAME");
+    let target = target(&config.listen);
+    let mut yaml = String::new();
+    let _ = writeln!(yaml, "scrape_configs:");
+    let _ = writeln!(yaml, "  - job_name: {}", name);
+    let _ = writeln!(yaml, "    metrics_path: {}", METRICS_PATH);
+    let _ = writeln!(yaml, "    scrape_interval: {}s", config.scrape_interval);
+    let _ = writeln!(yaml, "    static_configs:");
+    let _ = writeln!(yaml, "      - targets: [\"{}\"]", target);
+
+    if !config.probe_targets.is_empty() {
+        let _ = writeln!(yaml, "  - job_name: {}-probe", name);
+        let _ = writeln!(yaml, "    metrics_path: {}", PROBE_PATH);
+        let _ = writeln!(yaml, "    params:");
+        let _ = writeln!(yaml, "      module: [\"{}\"]", config.module);
+        let _ = writeln!(yaml, "    static_configs:");
+        let _ = writeln!(yaml, "      - targets:");
+        for probe_target in &config.probe_targets {
+            let _ = writeln!(yaml, "          - \"{}\"", probe_target);
+        }
+        let _ = writeln!(yaml, "    relabel_configs:");
+        let _ = writeln!(yaml, "      - source_labels: [__address__]");
+        let _ = writeln!(yaml, "        target_label: __param_target");
+        let _ = writeln!(yaml, "      - source_labels: [__param_target]");
+        let _ = writeln!(yaml, "        target_label: instance");
+        let _ = writeln!(yaml, "      - target_label: __address__");
+        let _ = writeln!(yaml, "        replacement: \"{}\"", target);
+    }
+    yaml
+}
+
+/// ServiceMonitor of the Prometheus Operator, selecting services labeled with
+/// `app.kubernetes.io/name: erth-exporter` and scraping the configured listen port
+pub fn service_monitor(config: &Config) -> String {
+    let name = env!("CARGO_PKG_NAME");
+    let port = config.listen.rsplit_once(':')
+        .map_or(config.listen.as_str(), |(_, port)| port);
+    let mut yaml = String::new();
+    let _ = writeln!(yaml, "apiVersion: monitoring.coreos.com/v1");
+    let _ = writeln!(yaml, "kind: ServiceMonitor");
+    let _ = writeln!(yaml, "metadata:");
+    let _ = writeln!(yaml, "  name: {}", name);
+    let _ = writeln!(yaml, "  labels:");
+    let _ = writeln!(yaml, "    app.kubernetes.io/name: {}", name);
+    let _ = writeln!(yaml, "spec:");
+    let _ = writeln!(yaml, "  selector:");
+    let _ = writeln!(yaml, "    matchLabels:");
+    let _ = writeln!(yaml, "      app.kubernetes.io/name: {}", name);
+    let _ = writeln!(yaml, "  endpoints:");
+    let _ = writeln!(yaml, "    - targetPort: {}", port);
+    let _ = writeln!(yaml, "      path: {}", METRICS_PATH);
+    let _ = writeln!(yaml, "      interval: {}s", config.scrape_interval);
+    yaml
+}
+
+/// Prometheus alerting rules for failing scrapes, stale data and queues that do not advance while
+/// the town hall is open
+///
+/// Without [opening hours](Config::opening_hours), queues are considered stuck whenever people are
+/// waiting.
+pub fn alerts(config: &Config) -> String {
+    let name = env!("CARGO_PKG_NAME");
+    let open = match config.opening_hours {
+        Some(_) => " and on() erth_office_open == 1",
+        None => "",
+    };
+    // name, expression, duration, severity, summary and description of each alert
+    let rules = [
+        ("ErthExporterDown", format!("up{{job=\"{}\"}} == 0", name), "5m", "critical",
+            "Bürgeramt exporter is down",
+            String::from("Prometheus cannot scrape {{ $labels.instance }}.")),
+        ("ErthScrapeFailing", String::from("erth_up == 0"), "15m", "warning",
+            "Bürgeramt page cannot be scraped",
+            String::from("The last scrapes of the town-hall website failed.")),
+        ("ErthDataStale", String::from("erth_data_stale == 1"), "5m", "warning",
+            "Bürgeramt data is stale",
+            String::from("Expired data is served because scraping fails.")),
+        ("ErthQueueParseFailing", String::from("erth_queue_scrape_success == 0"), "15m", "warning",
+            "Bürgeramt queue cannot be parsed",
+            String::from("The queue {{ $labels.service }} cannot be parsed, the page layout may have changed.")),
+        ("ErthQueueStuck",
+            format!("increase(erth_tickets_called_total[{}m]) == 0 and erth_people_waiting > 0{}", STUCK_QUEUE_MINUTES, open),
+            "5m", "warning",
+            "Bürgeramt queue is not advancing",
+            format!("No ticket of {{{{ $labels.service }}}} was called for {} minutes although people are waiting.", STUCK_QUEUE_MINUTES)),
+    ];
+
+    let mut yaml = String::new();
+    let _ = writeln!(yaml, "groups:");
+    let _ = writeln!(yaml, "  - name: {}", name);
+    let _ = writeln!(yaml, "    rules:");
+    for (alert, expr, duration, severity, summary, description) in rules {
+        let _ = writeln!(yaml, "      - alert: {}", alert);
+        let _ = writeln!(yaml, "        expr: '{}'", expr);
+        let _ = writeln!(yaml, "        for: {}", duration);
+        let _ = writeln!(yaml, "        labels:");
+        let _ = writeln!(yaml, "          severity: {}", severity);
+        let _ = writeln!(yaml, "        annotations:");
+        let _ = writeln!(yaml, "          summary: \"{}\"", summary);
+        let _ = writeln!(yaml, "          description: \"{}\"", description);
+    }
+    yaml
+}
+
+/// Grafana dashboard with panels on the queues, waiting times, throughput and the health of the
+/// exporter, with variables for the data source, the job and the services
+pub fn dashboard(config: &Config) -> String {
+    let selector = "job=~\"$job\", service=~\"$service\"";
+    let tracked_quantile = match config.waiting_time_summary {
+        true => format!("erth_tracked_waiting_time_seconds{{{}, quantile=\"0.9\"}}", selector),
+        false => format!("histogram_quantile(0.9, sum by (service, le) (rate(erth_tracked_waiting_time_seconds_bucket{{{}}}[1h])))",
+            selector),
+    };
+
+    let mut panels = vec![
+        panel("People waiting", "timeseries", "short", &[
+            (format!("erth_people_waiting{{{}}}", selector), "{{service}}"),
+        ]),
+        panel("Waiting time estimation", "timeseries", "s", &[
+            (format!("erth_waiting_time_seconds{{{}}}", selector), "{{service}}"),
+        ]),
+        panel("Tracked waiting time", "timeseries", "s", &[
+            (format!("erth_smoothed_waiting_time_seconds{{{}}}", selector), "{{service}} smoothed"),
+            (tracked_quantile, "{{service}} p90"),
+        ]),
+        panel("Expected wait for a new ticket", "timeseries", "s", &[
+            (format!("erth_new_ticket_wait_estimate_seconds{{{}}}", selector), "{{service}}"),
+        ]),
+        panel("Tickets called", "timeseries", "short", &[
+            (format!("erth_service_rate_per_minute{{{}}}", selector), "{{service}} called / min"),
+            (format!("erth_arrival_rate_per_minute{{{}}}", selector), "{{service}} arriving / min"),
+        ]),
+        panel("Last called ticket", "stat", "none", &[
+            (format!("erth_last_called_ticket{{{}}}", selector), "{{service}} {{type}}"),
+        ]),
+        panel("Scrape status", "timeseries", "short", &[
+            (String::from("erth_up{job=~\"$job\"}"), "up"),
+            (String::from("erth_data_stale{job=~\"$job\"}"), "stale"),
+            (String::from("erth_circuit_open{job=~\"$job\"}"), "circuit open"),
+            (String::from("erth_upstream_throttled{job=~\"$job\"}"), "throttled"),
+            (String::from("min by (instance) (erth_queue_scrape_success{job=~\"$job\"})"), "all queues parsed"),
+        ]),
+        panel("Scrapes", "timeseries", "s", &[
+            (String::from("erth_scrape_duration_seconds{job=~\"$job\"}"), "duration"),
+            (String::from("erth_cache_age_seconds{job=~\"$job\"}"), "data age"),
+        ]),
+        panel("Scrape errors", "timeseries", "short", &[
+            (String::from("sum by (reason) (increase(erth_scrape_errors_total{job=~\"$job\"}[$__rate_interval]))"), "{{reason}}"),
+        ]),
+    ];
+    if config.opening_hours.is_some() {
+        panels.push(panel("Town hall open", "timeseries", "short", &[
+            (String::from("erth_office_open{job=~\"$job\"}"), "open"),
+        ]));
+    }
+
+    // two panels per row
+    let width = GRID_WIDTH / 2;
+    for (id, panel) in panels.iter_mut().enumerate() {
+        panel["id"] = json!(id + 1);
+        panel["gridPos"] = json!({
+            "x": (id as u64 % 2) * width,
+            "y": (id as u64 / 2) * PANEL_HEIGHT,
+            "w": width,
+            "h": PANEL_HEIGHT,
+        });
+    }
+
+    let variable = |name: &str, label: &str, query: &str| json!({
+        "name": name,
+        "label": label,
+        "type": "query",
+        "datasource": { "type": "prometheus", "uid": "${datasource}" },
+        "query": { "query": query, "refId": name },
+        "definition": query,
+        "refresh": 2,
+        "includeAll": true,
+        "multi": true,
+        "current": { "text": "All", "value": "$__all" },
+    });
+    let dashboard = json!({
+        "title": "Bürgeramt",
+        "uid": env!("CARGO_PKG_NAME"),
+        "tags": [env!("CARGO_PKG_NAME")],
+        "timezone": config.timezone.name(),
+        "refresh": format!("{}s", config.scrape_interval),
+        "time": { "from": "now-24h", "to": "now" },
+        "schemaVersion": 39,
+        "templating": {
+            "list": [
+                {
+                    "name": "datasource",
+                    "label": "Data source",
+                    "type": "datasource",
+                    "query": "prometheus",
+                },
+                variable("job", "Job", "label_values(erth_up, job)"),
+                variable("service", "Service", "label_values(erth_people_waiting{job=~\"$job\"}, service)"),
+            ],
+        },
+        "panels": panels,
+    });
+    serde_json::to_string_pretty(&dashboard).unwrap_or_default() + "\n"
+}
+
+
+/// Panel of the [dashboard] showing the given queries with their legends
+fn panel(title: &str, kind: &str, unit: &str, targets: &[(String, &str)]) -> Value {
+    let targets: Vec<_> = targets.iter()
+        .zip('A'..)
+        .map(|((expr, legend), ref_id)| json!({
+            "datasource": { "type": "prometheus", "uid": "${datasource}" },
+            "expr": expr,
+            "legendFormat": legend,
+            "refId": ref_id.to_string(),
+        }))
+        .collect();
+    json!({
+        "title": title,
+        "type": kind,
+        "datasource": { "type": "prometheus", "uid": "${datasource}" },
+        "fieldConfig": { "defaults": { "unit": unit }, "overrides": [] },
+        "targets": targets,
+    })
+}
+
+/// Address Prometheus can reach the exporter on, replacing unspecified addresses like `0.0.0.0`
+/// by `localhost`
+fn target(listen: &str) -> String {
+    match listen.parse::<SocketAddr>() {
+        Ok(address) if address.ip().is_unspecified() => format!("localhost:{}", address.port()),
+        _ => listen.to_owned(),
+    }
+}
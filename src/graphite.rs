@@ -0,0 +1,124 @@
+//! Pushing metrics to Graphite in plaintext protocol
+use std::fmt::Write as _;
+use std::io::Write as _;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use prometheus::proto::{Metric, MetricFamily, MetricType};
+
+use crate::config::GraphiteConfig;
+use crate::history::HistoryFrame;
+use crate::push::{self, Sink};
+
+
+/// Timeout for connecting to and writing to the carbon server
+const CARBON_TIMEOUT: Duration = Duration::from_secs(10);
+
+
+/// Sends the metrics to a carbon server
+///
+/// Labels are appended to the metric path as pairs of name and value, e.g.
+/// `erth.erth_people_waiting.service.citizen`.
+pub struct Graphite {
+    config: GraphiteConfig,
+}
+
+
+impl Graphite {
+    pub fn new(config: &GraphiteConfig) -> Self {
+        Graphite { config: config.clone() }
+    }
+
+    /// Render the metrics in plaintext protocol
+    fn lines(&self, families: &[MetricFamily], timestamp: u64) -> String {
+        let mut lines = String::new();
+        for family in families {
+            for metric in family.get_metric() {
+                let path = self.path(family.name(), metric);
+                let values = match family.get_field_type() {
+                    MetricType::COUNTER => vec![(String::new(), metric.get_counter().value())],
+                    MetricType::GAUGE => vec![(String::new(), metric.get_gauge().value())],
+                    MetricType::UNTYPED => vec![(String::new(), metric.untyped.value())],
+                    MetricType::SUMMARY => vec![
+                        (String::from("_sum"), metric.get_summary().sample_sum()),
+                        (String::from("_count"), metric.get_summary().sample_count() as f64),
+                    ],
+                    MetricType::HISTOGRAM => vec![
+                        (String::from("_sum"), metric.get_histogram().sample_sum()),
+                        (String::from("_count"), metric.get_histogram().sample_count() as f64),
+                    ],
+                };
+                for (suffix, value) in values {
+                    if value.is_finite() {
+                        let _ = writeln!(lines, "{}{} {} {}", path, suffix, value, timestamp);
+                    }
+                }
+            }
+        }
+        lines
+    }
+
+    /// Metric path of a series, including its labels
+    fn path(&self, name: &str, metric: &Metric) -> String {
+        let mut path = match self.config.prefix.is_empty() {
+            true => push::sanitize(name, &[]),
+            false => format!("{}.{}", self.config.prefix, push::sanitize(name, &[])),
+        };
+        for label in metric.get_label() {
+            let _ = write!(path, ".{}.{}", push::sanitize(label.name(), &[]), push::sanitize(label.value(), &[]));
+        }
+        path
+    }
+}
+
+impl Sink for Graphite {
+    fn name(&self) -> &str {
+        "Graphite"
+    }
+
+    fn push(&mut self, frame: &HistoryFrame, families: &[MetricFamily]) -> Result<(), String> {
+        let address = (self.config.host.as_str(), self.config.port).to_socket_addrs()
+            .map_err(|e| e.to_string())?
+            .next()
+            .ok_or_else(|| format!("cannot resolve {}", self.config.host))?;
+        let mut stream = TcpStream::connect_timeout(&address, CARBON_TIMEOUT)
+            .map_err(|e| e.to_string())?;
+        stream.set_write_timeout(Some(CARBON_TIMEOUT))
+            .map_err(|e| e.to_string())?;
+        stream.write_all(self.lines(families, frame.timestamp as u64).as_bytes())
+            .map_err(|e| e.to_string())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use prometheus::core::Collector;
+    use prometheus::{GaugeVec, Histogram, HistogramOpts, Opts};
+
+    use super::*;
+
+    fn graphite(prefix: &str) -> Graphite {
+        Graphite::new(&GraphiteConfig { prefix: prefix.to_owned(), ..GraphiteConfig::default() })
+    }
+
+    #[test]
+    fn paths() {
+        let gauge = GaugeVec::new(Opts::new("erth_people_waiting", "help"), &["service", "queue"]).unwrap();
+        gauge.with_label_values(&["citizen", "a.b c"]).set(12.0);
+        gauge.with_label_values(&["drivers_license", "x"]).set(f64::NAN);
+        let families = gauge.collect();
+
+        assert_eq!(graphite("erth").lines(&families, 1704096000),
+            "erth.erth_people_waiting.queue.a_b_c.service.citizen 12 1704096000\n");
+        assert_eq!(graphite("").lines(&families, 0), "erth_people_waiting.queue.a_b_c.service.citizen 12 0\n");
+    }
+
+    #[test]
+    fn histograms() {
+        let histogram = Histogram::with_opts(HistogramOpts::new("erth_wait", "help")).unwrap();
+        histogram.observe(2.5);
+        histogram.observe(0.5);
+        assert_eq!(graphite("erth").lines(&histogram.collect(), 60), "erth.erth_wait_sum 3 60\nerth.erth_wait_count 2 60\n");
+    }
+}
@@ -0,0 +1,262 @@
+//! GraphQL schema of queues, history and statistics, served on `/graphql`
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono_tz::Tz;
+use juniper::{graphql_object, EmptyMutation, EmptySubscription, FieldResult, GraphQLObject, RootNode};
+
+use crate::api::{self, Aggregate};
+use crate::history::HistoryFrame;
+use crate::{stats, unix_timestamp, FrameStore, Scraper, DEFAULT_STATS_WINDOW};
+
+
+/// Time span of the stored history returned by `history` if `from` is not given
+const DEFAULT_HISTORY_WINDOW: Duration = Duration::from_secs(24 * 60 * 60);
+
+
+/// Schema served on `/graphql`
+pub type Schema = RootNode<Query, EmptyMutation<Context>, EmptySubscription<Context>>;
+
+/// Data available to the resolvers
+pub struct Context {
+    pub scraper: Arc<Mutex<Scraper>>,
+    pub frames: FrameStore,
+    pub timezone: Tz,
+}
+
+/// Root of all queries
+pub struct Query;
+
+/// Current state of the queues
+#[derive(Debug,Clone,GraphQLObject)]
+#[graphql(context = Context)]
+struct Queues {
+    /// Time the data was scraped in seconds since the epoch
+    timestamp: f64,
+
+    /// Time the data was scraped (RFC 3339)
+    scraped_at: String,
+
+    /// Age of the data in seconds
+    age_seconds: f64,
+
+    /// Whether the data has expired and is only served because scraping fails
+    stale: bool,
+
+    /// Queues that could be parsed
+    queues: Vec<Queue>,
+
+    /// Queues that could not be parsed
+    errors: Vec<QueueError>,
+}
+
+/// Current state of a single queue
+#[derive(Debug,Clone,GraphQLObject)]
+#[graphql(context = Context)]
+struct Queue {
+    service: String,
+    people_waiting: i32,
+    last_called_ticket: String,
+
+    /// Waiting time estimation of the town hall
+    waiting_time_seconds: f64,
+
+    /// Waiting time of the last called ticket as tracked by the exporter
+    tracked_waiting_time_seconds: Option<f64>,
+
+    /// Exponentially weighted moving average of the tracked waiting times
+    smoothed_waiting_time_seconds: Option<f64>,
+
+    /// Expected waiting time of a ticket drawn now
+    new_ticket_wait_estimate_seconds: Option<f64>,
+
+    /// Tickets called per minute over the service rate window
+    service_rate_per_minute: f64,
+
+    /// People joining the queue per minute over the service rate window
+    arrival_rate_per_minute: f64,
+}
+
+/// Reason a queue could not be parsed
+#[derive(Debug,Clone,GraphQLObject)]
+#[graphql(context = Context)]
+struct QueueError {
+    service: String,
+    error: String,
+}
+
+/// Stored data frame
+#[derive(Debug,Clone,GraphQLObject)]
+#[graphql(context = Context)]
+struct Frame {
+    /// Time the frame was scraped in seconds since the epoch
+    timestamp: f64,
+
+    /// Samples of all queues that could be parsed
+    samples: Vec<Sample>,
+}
+
+/// State of a single queue at one point in time
+#[derive(Debug,Clone,GraphQLObject)]
+#[graphql(context = Context)]
+struct Sample {
+    service: String,
+    people_waiting: i32,
+    last_called_ticket: String,
+
+    /// Waiting time estimation of the town hall in seconds
+    waiting_time_seconds: f64,
+
+    /// Waiting time of the last called ticket as tracked by the exporter in seconds
+    tracked_waiting_time_seconds: Option<f64>,
+}
+
+/// Statistics of a service computed from the stored history
+#[derive(Debug,Clone,GraphQLObject)]
+#[graphql(context = Context)]
+struct Statistics {
+    service: String,
+
+    /// Statistics of each day, by date in the configured time zone
+    days: Vec<Period>,
+
+    /// Statistics of each day of the week
+    weekdays: Vec<Period>,
+}
+
+/// Waiting time statistics of a day or weekday
+#[derive(Debug,Clone,GraphQLObject)]
+#[graphql(context = Context)]
+struct Period {
+    /// Date or name of the weekday
+    name: String,
+
+    /// Number of samples the statistics are based on
+    samples: i32,
+
+    /// Average waiting time estimation of the town hall
+    average_wait_seconds: f64,
+
+    /// Median waiting time estimation of the town hall
+    median_wait_seconds: f64,
+
+    /// Maximum waiting time estimation of the town hall
+    max_wait_seconds: f64,
+
+    /// Hour of the day with the longest queue on average
+    busiest_hour: i32,
+}
+
+
+impl juniper::Context for Context {}
+
+#[graphql_object]
+#[graphql(context = Context)]
+impl Query {
+    /// Current state of the queues, if any data was scraped yet
+    fn queues(context: &Context, service: Option<String>) -> Option<Queues> {
+        let queues = context.scraper.lock().unwrap().queues(&context.timezone)?;
+        Some(Queues {
+            timestamp: queues.timestamp,
+            scraped_at: queues.scraped_at,
+            age_seconds: queues.age_seconds,
+            stale: queues.stale,
+            queues: queues.queues.into_iter()
+                .filter(|(s, _)| selected(s, &service))
+                .map(|(service, queue)| Queue::new(service, queue))
+                .collect(),
+            errors: queues.errors.into_iter()
+                .filter(|(s, _)| selected(s, &service))
+                .map(|(service, error)| QueueError { service, error })
+                .collect(),
+        })
+    }
+
+    /// Stored data frames scraped between `from` and `to` in seconds since the epoch, by default
+    /// of the last 24 hours
+    fn history(context: &Context, from: Option<f64>, to: Option<f64>, service: Option<String>) -> FieldResult<Vec<Frame>> {
+        let frames = stored_frames(context, from, to, DEFAULT_HISTORY_WINDOW)?;
+        Ok(frames.into_iter()
+            .map(|frame| Frame {
+                timestamp: frame.timestamp,
+                samples: frame.queues.into_iter()
+                    .filter(|(s, _)| selected(s, &service))
+                    .map(|(service, sample)| Sample {
+                        service,
+                        people_waiting: to_int(sample.people_waiting),
+                        last_called_ticket: sample.last_called_ticket,
+                        waiting_time_seconds: sample.waiting_time_seconds,
+                        tracked_waiting_time_seconds: sample.tracked_waiting_time_seconds,
+                    })
+                    .collect(),
+            })
+            .collect())
+    }
+
+    /// Statistics of the data frames stored between `from` and `to` in seconds since the epoch, by
+    /// default of the last 28 days
+    fn statistics(context: &Context, from: Option<f64>, to: Option<f64>, service: Option<String>) -> FieldResult<Vec<Statistics>> {
+        let frames = stored_frames(context, from, to, DEFAULT_STATS_WINDOW)?;
+        Ok(stats::compute(&frames, &context.timezone).into_iter()
+            .filter(|(s, _)| selected(s, &service))
+            .map(|(service, stats)| Statistics {
+                service,
+                days: stats.days.into_iter().map(Period::new).collect(),
+                weekdays: stats.weekdays.into_iter().map(Period::new).collect(),
+            })
+            .collect())
+    }
+}
+
+impl Queue {
+    fn new(service: String, queue: api::Queue) -> Self {
+        Queue {
+            service,
+            people_waiting: to_int(queue.people_waiting),
+            last_called_ticket: queue.last_called_ticket,
+            waiting_time_seconds: queue.waiting_time_seconds,
+            tracked_waiting_time_seconds: queue.tracked_waiting_time_seconds,
+            smoothed_waiting_time_seconds: queue.smoothed_waiting_time_seconds,
+            new_ticket_wait_estimate_seconds: queue.new_ticket_wait_estimate_seconds,
+            service_rate_per_minute: queue.service_rate_per_minute,
+            arrival_rate_per_minute: queue.arrival_rate_per_minute,
+        }
+    }
+}
+
+impl Period {
+    fn new((name, aggregate): (String, Aggregate)) -> Self {
+        Period {
+            name,
+            samples: to_int(aggregate.samples),
+            average_wait_seconds: aggregate.average_wait_seconds,
+            median_wait_seconds: aggregate.median_wait_seconds,
+            max_wait_seconds: aggregate.max_wait_seconds,
+            busiest_hour: aggregate.busiest_hour as i32,
+        }
+    }
+}
+
+
+/// Create the schema
+pub fn schema() -> Schema {
+    Schema::new(Query, EmptyMutation::new(), EmptySubscription::new())
+}
+
+/// Stored frames in the given range, starting `window` ago and unbounded towards the future if
+/// unset
+fn stored_frames(context: &Context, from: Option<f64>, to: Option<f64>, window: Duration)
+        -> Result<Vec<HistoryFrame>, String> {
+    let from = from.unwrap_or_else(|| unix_timestamp().saturating_sub(window).as_secs_f64());
+    context.frames.between(from, to.unwrap_or(f64::INFINITY))
+}
+
+/// Whether a service matches the optional `service` argument
+fn selected(service: &str, filter: &Option<String>) -> bool {
+    filter.as_ref().is_none_or(|f| f == service)
+}
+
+/// Convert a count to a GraphQL integer, saturating at its maximum
+fn to_int(count: usize) -> i32 {
+    i32::try_from(count).unwrap_or(i32::MAX)
+}
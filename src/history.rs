@@ -0,0 +1,207 @@
+//! Short-term history of scraped data frames
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::Write;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::DataFrame;
+
+
+/// Ring buffer of the most recent data frames, served on `/history`
+#[derive(Debug,Clone)]
+pub struct History {
+    /// Stored frames, oldest first
+    frames: VecDeque<HistoryFrame>,
+
+    /// Maximum number of stored frames
+    capacity: usize,
+}
+
+/// Header of the [CSV export](to_csv)
+const CSV_HEADER: &str = "timestamp,service,people_waiting,last_called_ticket,waiting_time_seconds,tracked_waiting_time_seconds";
+
+
+/// Condensed [DataFrame] as stored in the [History]
+#[derive(Debug,Clone,Serialize,Deserialize,JsonSchema)]
+pub struct HistoryFrame {
+    /// Time the frame was scraped in seconds since the epoch
+    pub timestamp: f64,
+
+    /// Samples of all queues that could be parsed, by service name
+    pub queues: BTreeMap<String, Sample>,
+}
+
+/// State of a single queue at one point in time
+#[derive(Debug,Clone,Serialize,Deserialize,JsonSchema)]
+pub struct Sample {
+    pub people_waiting: usize,
+    pub last_called_ticket: String,
+
+    /// Waiting time estimation of the town hall in seconds
+    pub waiting_time_seconds: f64,
+
+    /// Waiting time of the last called ticket as tracked by the exporter in seconds
+    pub tracked_waiting_time_seconds: Option<f64>,
+}
+
+
+impl History {
+    pub fn new(capacity: usize) -> Self {
+        History {
+            frames: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Append a frame, dropping the oldest one if the buffer is full
+    pub fn push(&mut self, frame: HistoryFrame) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.frames.len() == self.capacity {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(frame);
+    }
+
+    /// Frames scraped between `from` and `to` (inclusive, seconds since the epoch)
+    pub fn between(&self, from: f64, to: f64) -> Vec<&HistoryFrame> {
+        self.frames.iter()
+            .filter(|f| from <= f.timestamp && f.timestamp <= to)
+            .collect()
+    }
+}
+
+impl From<&DataFrame> for HistoryFrame {
+    fn from(data: &DataFrame) -> Self {
+        let queues = data.queues.iter()
+            .filter_map(|(service, queue)| {
+                let queue = queue.as_ref().ok()?;
+                Some((service.clone(), Sample {
+                    people_waiting: queue.people_waiting,
+                    last_called_ticket: queue.last_called_ticket.to_string(),
+                    waiting_time_seconds: (queue.waiting_time_estimation * 60) as f64,
+                    tracked_waiting_time_seconds: queue.tracked_waiting_time.map(|t| t.as_secs_f64()),
+                }))
+            })
+            .collect();
+
+        HistoryFrame {
+            timestamp: data.created_timestamp.as_secs_f64(),
+            queues,
+        }
+    }
+}
+
+
+/// Render frames as CSV with one row per queue and frame
+pub fn to_csv<'a>(frames: impl IntoIterator<Item = &'a HistoryFrame>) -> String {
+    let mut csv = format!("{}\n", CSV_HEADER);
+    for frame in frames {
+        for (service, sample) in &frame.queues {
+            let tracked = sample.tracked_waiting_time_seconds
+                .map(|t| t.to_string())
+                .unwrap_or_default();
+            let _ = writeln!(csv, "{},{},{},{},{},{}", frame.timestamp, service, sample.people_waiting,
+                sample.last_called_ticket, sample.waiting_time_seconds, tracked);
+        }
+    }
+    csv
+}
+
+/// Parse frames from CSV as rendered by [to_csv]
+#[cfg(feature = "sqlite")]
+pub fn from_csv(csv: &str) -> Result<Vec<HistoryFrame>, String> {
+    let mut lines = csv.lines();
+    if lines.next().map(str::trim) != Some(CSV_HEADER) {
+        return Err(String::from("unexpected CSV header"));
+    }
+
+    let mut frames: Vec<HistoryFrame> = Vec::new();
+    for (i, line) in lines.enumerate().filter(|(_, l)| !l.trim().is_empty()) {
+        let invalid = |field: &str| format!("invalid {} in line {}", field, i + 2);
+        let fields: Vec<_> = line.trim().split(',').collect();
+        let [timestamp, service, people_waiting, last_called_ticket, waiting_time, tracked] = fields[..] else {
+            return Err(format!("expected 6 fields in line {}", i + 2));
+        };
+        let timestamp: f64 = timestamp.parse().map_err(|_| invalid("timestamp"))?;
+        let sample = Sample {
+            people_waiting: people_waiting.parse().map_err(|_| invalid("people_waiting"))?,
+            last_called_ticket: last_called_ticket.to_owned(),
+            waiting_time_seconds: waiting_time.parse().map_err(|_| invalid("waiting_time_seconds"))?,
+            tracked_waiting_time_seconds: match tracked {
+                "" => None,
+                t => Some(t.parse().map_err(|_| invalid("tracked_waiting_time_seconds"))?),
+            },
+        };
+
+        match frames.last_mut() {
+            Some(frame) if frame.timestamp == timestamp => {
+                frame.queues.insert(service.to_owned(), sample);
+            },
+            _ => frames.push(HistoryFrame {
+                timestamp,
+                queues: [(service.to_owned(), sample)].into(),
+            }),
+        }
+    }
+
+    Ok(frames)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(people_waiting: usize, tracked: Option<f64>) -> Sample {
+        Sample {
+            people_waiting,
+            last_called_ticket: String::from("B012"),
+            waiting_time_seconds: 900.0,
+            tracked_waiting_time_seconds: tracked,
+        }
+    }
+
+    #[test]
+    fn csv() {
+        let frame = HistoryFrame {
+            timestamp: 1700000000.25,
+            queues: [
+                (String::from("citizen"), sample(12, Some(1234.5))),
+                (String::from("drivers_license"), sample(0, None)),
+            ].into(),
+        };
+        assert_eq!(to_csv([&frame]), format!("{}\n{}\n{}\n", CSV_HEADER,
+            "1700000000.25,citizen,12,B012,900,1234.5",
+            "1700000000.25,drivers_license,0,B012,900,"));
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn csv_round_trip() {
+        let frames = [
+            HistoryFrame { timestamp: 60.0, queues: [(String::from("citizen"), sample(3, None))].into() },
+            HistoryFrame {
+                timestamp: 120.0,
+                queues: [
+                    (String::from("citizen"), sample(4, Some(60.0))),
+                    (String::from("drivers_license"), sample(1, None)),
+                ].into(),
+            },
+        ];
+        let csv = to_csv(&frames);
+        assert_eq!(to_csv(&from_csv(&csv).unwrap()), csv);
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn csv_errors() {
+        assert!(from_csv("time,service\n").is_err());
+        let header = format!("{}\n", CSV_HEADER);
+        assert_eq!(from_csv(&header).unwrap().len(), 0);
+        assert_eq!(from_csv(&(header.clone() + "60,citizen,3,B012\n")).unwrap_err(), "expected 6 fields in line 2");
+        assert_eq!(from_csv(&(header + "60,citizen,many,B012,900,\n")).unwrap_err(), "invalid people_waiting in line 2");
+    }
+}
@@ -0,0 +1,96 @@
+//! Pushing data frames to InfluxDB in line protocol
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use prometheus::proto::MetricFamily;
+
+use crate::config::{Config, InfluxDbConfig};
+use crate::history::HistoryFrame;
+use crate::push::{self, Sink};
+
+
+/// Writes one point per queue and data frame to InfluxDB 1.x or 2.x
+pub struct InfluxDb {
+    config: InfluxDbConfig,
+
+    /// Constant [labels](Config::labels), added as tags to every point
+    tags: HashMap<String, String>,
+
+    client: reqwest::blocking::Client,
+}
+
+
+impl InfluxDb {
+    pub fn new(config: &Config, influxdb: &InfluxDbConfig) -> Self {
+        InfluxDb {
+            config: influxdb.clone(),
+            tags: config.labels.clone(),
+            client: push::client(config),
+        }
+    }
+
+    /// Render the queues of a frame in line protocol
+    fn lines(&self, frame: &HistoryFrame) -> String {
+        let timestamp = (frame.timestamp * 1e9) as i64;
+        let mut tags: Vec<_> = self.tags.iter().collect();
+        tags.sort();
+
+        let mut lines = String::new();
+        for (service, sample) in &frame.queues {
+            let _ = write!(lines, "{},service={}", escape(&self.config.measurement), escape(service));
+            for (key, value) in &tags {
+                let _ = write!(lines, ",{}={}", escape(key), escape(value));
+            }
+            let (ticket_type, ticket_number) = sample.last_called_ticket
+                .split_at(sample.last_called_ticket.find(|c: char| c.is_ascii_digit()).unwrap_or(0));
+            let _ = write!(lines, " people_waiting={}i,waiting_time_seconds={}",
+                sample.people_waiting, sample.waiting_time_seconds);
+            if let Ok(number) = ticket_number.parse::<u64>() {
+                let _ = write!(lines, ",last_called_ticket={}i,ticket_type=\"{}\"", number, ticket_type);
+            }
+            if let Some(tracked) = sample.tracked_waiting_time_seconds {
+                let _ = write!(lines, ",tracked_waiting_time_seconds={}", tracked);
+            }
+            let _ = writeln!(lines, " {}", timestamp);
+        }
+        lines
+    }
+}
+
+impl Sink for InfluxDb {
+    fn name(&self) -> &str {
+        "InfluxDB"
+    }
+
+    fn push(&mut self, frame: &HistoryFrame, _families: &[MetricFamily]) -> Result<(), String> {
+        let base = self.config.url.trim_end_matches('/');
+        let request = match &self.config.token {
+            // InfluxDB 2.x
+            Some(token) => self.client.post(format!("{}/api/v2/write", base))
+                .query(&[("org", &self.config.org), ("bucket", &self.config.database), ("precision", &String::from("ns"))])
+                .header("Authorization", format!("Token {}", token)),
+            // InfluxDB 1.x
+            None => {
+                let request = self.client.post(format!("{}/write", base))
+                    .query(&[("db", &self.config.database), ("precision", &String::from("ns"))]);
+                match &self.config.username {
+                    Some(username) => request.basic_auth(username, self.config.password.as_ref()),
+                    None => request,
+                }
+            },
+        };
+
+        let response = request.body(self.lines(frame))
+            .send()
+            .map_err(|e| e.to_string())?;
+        push::check_response(response)
+    }
+}
+
+
+/// Escape commas, equal signs and spaces in measurements, tag keys and tag values
+fn escape(s: &str) -> String {
+    s.replace(',', "\\,")
+        .replace('=', "\\=")
+        .replace(' ', "\\ ")
+}
@@ -1,18 +1,110 @@
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::Display;
 use std::io::BufReader;
 use std::io::prelude::*;
 use std::io;
 use std::net::{TcpListener, TcpStream};
-use std::time;
+use std::path::PathBuf;
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::thread;
 use std::time::Duration;
 use std::time::Instant;
 use std::time::SystemTime;
 use std::time::UNIX_EPOCH;
 
+use chrono::Utc;
+use chrono_tz::Tz;
+use clap::{Parser, Subcommand};
+use juniper::http::GraphQLRequest;
+use prometheus::proto::MetricFamily;
+use prometheus::{PROTOBUF_FORMAT, TEXT_FORMAT};
 
-/// URL to be scraped
+use api::{Encoding, Queue, Queues, WaitEstimate};
+use config::{AnomalyConfig, CircuitBreakerConfig, Config};
+#[cfg(feature = "sqlite")]
+use database::Database;
+use events::EventStream;
+use feed::Feed;
+use graphite::Graphite;
+use history::{History, HistoryFrame};
+use influxdb::InfluxDb;
+use matrix::Matrix;
+use metrics::{ScraperMetrics, ServerMetrics};
+use mqtt::Mqtt;
+use notify::{Notifications, Notifier};
+use ntfy::Ntfy;
+use opening_hours::OpeningHours;
+use push::Sink;
+use pushgateway::Pushgateway;
+use rate::RateWindow;
+use remote_write::RemoteWrite;
+#[cfg(feature = "redis")]
+use shared::SharedCache;
+#[cfg(feature = "smtp")]
+use smtp::Smtp;
+use source::{HtmlSource, ScrapeSource};
+use state::State;
+use statsd::Statsd;
+use telegram::Telegram;
+use webhook::Webhook;
+use widget::Waybar;
+use zabbix::Zabbix;
+
+mod api;
+mod badge;
+mod capture;
+mod check;
+mod collectd;
+mod config;
+#[cfg(unix)]
+mod daemon;
+#[cfg(feature = "sqlite")]
+mod database;
+mod events;
+mod feed;
+mod generate;
+mod graphite;
+mod graphql;
+mod history;
+mod influxdb;
+mod matrix;
+mod metrics;
+mod mqtt;
+mod munin;
+mod notify;
+mod ntfy;
+mod opening_hours;
+mod openapi;
+mod push;
+mod pushgateway;
+mod rate;
+mod remote_write;
+mod selftest;
+#[cfg(feature = "redis")]
+mod shared;
+#[cfg(feature = "smtp")]
+mod smtp;
+mod source;
+mod state;
+mod statsd;
+mod stats;
+mod status;
+#[cfg(target_os = "linux")]
+mod systemd;
+mod telegram;
+mod textfile;
+mod watch;
+mod webhook;
+mod widget;
+mod zabbix;
+
+/// Address the server is bound to by default
+const DEFAULT_LISTEN_ADDR: &str = "localhost:12080";
+/// Name of the builtin parser [Module]
+const DEFAULT_MODULE: &str = "erlangen";
+/// URL to be scraped by default
 const URL: &str = "https://erlangen.de/themenseite/service/buerger/aktuelle-wartezeit";
 /// CSS selector for the queue blocks
 const BLOCK_SELECTOR: &str = ".fr-view";
@@ -20,21 +112,51 @@ const BLOCK_SELECTOR: &str = ".fr-view";
 const VALUE_SELECTOR: &str = ".flex>span";
 /// Filter for queue blocks
 const BLOCK_CONTENT_FILTER: &str = "Wartende Personen";
+/// Selector for the heading identifying the queue of a block
+const HEADING_SELECTOR: &str = "h1, h2, h3, h4, h5, h6";
 /// Supported HTTP version
 const HTTP_VERSION: &str = "HTTP/1.1";
-/// Time-to-live for [cached](CACHED_FRAME) data frames
+/// Maximum size of request bodies, which are only accepted by `/graphql`
+const MAX_REQUEST_BODY_SIZE: usize = 64 * 1024;
+/// Default interval between scrapes and time-to-live for [cached](Scraper::cache) data frames
 const CACHE_EXPIRATION: Duration = Duration::from_secs(30);
+/// How long idle connections to the upstream server are kept open
+const UPSTREAM_KEEPALIVE: Duration = Duration::from_secs(120);
+/// Time span of the stored history `/api/v1/stats` is computed from if not requested otherwise
+const DEFAULT_STATS_WINDOW: Duration = Duration::from_secs(28 * 24 * 60 * 60);
+/// Number of hours forecast on `/api/v1/forecast` by default
+const DEFAULT_FORECAST_HOURS: u32 = 3;
+/// Maximum number of hours forecast on `/api/v1/forecast`
+const MAX_FORECAST_HOURS: u32 = 7 * 24;
+/// Time span of the stored history the weekly baselines of `/api/v1/forecast` are computed from
+const FORECAST_WINDOW: Duration = Duration::from_secs(4 * 7 * 24 * 60 * 60);
+/// Interval at which the listener is polled for connections while a systemd watchdog is enabled
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(20);
+/// Interval at which the [event stream](Server::events) checks for new data frames
+const EVENTS_POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// Time span over which the [served tickets](QueueState::served_last_hour) are counted
+const SERVED_TICKETS_WINDOW: Duration = Duration::from_secs(60 * 60);
 
 
-/// Specifies the type of a ticket, which may be either for citizens services, drivers-license
-/// services or an invalid amount used during off-hours
+/// Specifies the type of a ticket by its letter (e.g. `B` for citizens services or `F` for
+/// drivers-license services), or an invalid amount used during off-hours
 #[derive(Debug,Clone,Copy,Eq,PartialEq,Hash)]
-enum TicketType { B, F, None }
+enum TicketType { Letter(char), None }
 
 /// Represents a ticket in the town hall
 #[derive(Debug,Clone,Copy,Eq,PartialEq,Hash)]
 struct Ticket(TicketType, usize);
 
+/// Ticket whose waiting time is being tracked
+#[derive(Debug,Clone,Copy)]
+struct TrackedTicket {
+    /// When the ticket was first expected to be drawn
+    since: Instant,
+
+    /// Waiting time estimation of the town hall in minutes at that time
+    waiting_time_estimation: usize,
+}
+
 /// Data frame capturing the queue information for one specific queue in the town hall
 #[derive(Debug,Clone)]
 struct QueueDataFrame {
@@ -47,20 +169,18 @@ struct QueueDataFrame {
     /// Waiting time estimation in minutes ("Durchschnittliche Wartezeit").
     waiting_time_estimation: usize,
 
-    /// Waiting time as tracked by the scraper (see [Scraper::ticket_tracker])
+    /// Waiting time as tracked by the scraper (see [QueueState::ticket_tracker])
     tracked_waiting_time: Option<Duration>
 }
 
 /// Data frame containing all information at a specific point in time
 #[derive(Debug,Clone)]
 struct DataFrame {
-    /// Data frame for citizen services ("Bürgerservice").
-    citizen_services: QueueDataFrame,
-
-    /// Data frame for drivers-license services ("Fahrerlaubnisangelegenheiten").
-    drivers_license_services: QueueDataFrame,
+    /// Data frames of all queues found on the page by their service name, or why they could not
+    /// be parsed.
+    queues: BTreeMap<String, Result<QueueDataFrame, ScrapeError>>,
 
-    /// Whether this data frame is part of the [cache](CACHED_FRAME).
+    /// Whether this data frame is part of the [cache](Scraper::cache).
     cached: bool,
 
     /// How long it took to scrape the data.
@@ -75,23 +195,352 @@ struct DataFrame {
 
 /// Carries the state of the scraper
 struct Scraper {
+    /// Provides new data frames
+    ///
+    /// Taken by the [background scraper](Server::spawn_background_scraper), which fetches without
+    /// holding the lock on the scraper.
+    source: Option<Box<dyn ScrapeSource>>,
+
+    /// Cache shared with other replicas, taken by the
+    /// [background scraper](Server::spawn_background_scraper) as well
+    #[cfg(feature = "redis")]
+    shared: Option<SharedCache>,
+
+    /// Age after which [cached](Scraper::cache) frames are considered expired
+    cache_expiration: Duration,
+
+    /// Maximum age of an expired [cached](Scraper::cache) frame that is still served if scraping fails
+    max_staleness: Duration,
+
     /// Cache the last successful request
     ///
-    /// The cache expiration behavior is specified by [`CACHE_EXPIRATION`] and is calculated based on
-    /// the field [`DataFrame::created_instant`].
+    /// The cache expiration behavior is specified by [`Scraper::cache_expiration`] and is calculated
+    /// based on the field [`DataFrame::created_instant`].
     cache: Option<DataFrame>,
 
+    /// Circuit breaker settings
+    circuit_breaker: CircuitBreakerConfig,
+
+    /// Number of scrapes that failed in a row
+    consecutive_failures: u32,
+
+    /// End of the cooldown of the [circuit breaker](Config::circuit_breaker), if it was opened
+    circuit_open_until: Option<Instant>,
+
+    /// Tracking state of every queue seen so far, by service name
+    queues: HashMap<String, QueueState>,
+
+    /// Time span over which the rates of a queue are calculated
+    service_rate_window: Duration,
+
+    /// File the tracking state is persisted to after every scrape
+    state_file: Option<PathBuf>,
+
+    /// Age after which tracked tickets are assumed to be abandoned
+    max_ticket_age: Duration,
+
+    /// Maximum number of tracked tickets per queue
+    max_tracked_tickets: usize,
+
+    /// Weight of the latest tracked waiting time in the [smoothed waiting time](QueueState::smoothed_waiting_time)
+    smoothing_factor: f64,
+
+    /// Thresholds for detecting [anomalies](QueueState::anomalies)
+    anomaly_thresholds: AnomalyConfig,
+
+    /// Time zone of the [opening hours](Scraper::opening_hours)
+    timezone: Tz,
+
+    /// Opening hours of the town hall, queues are only considered stalled while it is open
+    opening_hours: Option<OpeningHours>,
+
+    /// Recently scraped data frames
+    history: History,
+
+    /// Database every scraped data frame is stored in
+    #[cfg(feature = "sqlite")]
+    database: Option<Database>,
+
+    /// Metrics describing the state of the scraper
+    metrics: ScraperMetrics,
+}
+
+/// Tracking state of a single queue, kept across scrapes
+#[derive(Debug,Clone)]
+struct QueueState {
+    /// Type of the tickets called in this queue, learned from the first called ticket
+    ticket_type: Option<TicketType>,
+
     /// Tracks currently open tickets to determine their waiting time
-    ticket_tracker: HashMap<Ticket, Instant>,
+    ticket_tracker: HashMap<Ticket, TrackedTicket>,
 
     /// Remembers the last tracked waiting time to reproduce on [Scraper::metrics]
-    last_tracked_waiting_time: [Option<Duration>; 2],
+    last_tracked_waiting_time: Option<Duration>,
+
+    /// Exponentially weighted moving average of the tracked waiting times in seconds
+    smoothed_waiting_time: Option<f64>,
+
+    /// Last called ticket number, reference for counting called tickets
+    last_called_number: Option<usize>,
+
+    /// Last observed queue length, reference for counting arrivals
+    last_queue_length: Option<usize>,
+
+    /// Tickets called during the [service rate window](Config::service_rate_window), which are the
+    /// departures from the queue exported as the [service rate](QueueState::service_rate)
+    departures: RateWindow,
+
+    /// People arriving during the [service rate window](Config::service_rate_window)
+    arrivals: RateWindow,
+
+    /// Tickets called during the last hour
+    served_last_hour: RateWindow,
+
+    /// Extremes observed since the ticket numbers were last reset
+    daily: DailyExtremes,
+
+    /// Last time a ticket was called, or the queue was first observed
+    last_progress: Option<Instant>,
+
+    /// Anomalies detected during the last observation
+    anomalies: Anomalies,
+}
+
+/// Anomalies of a queue detected when observing it, exported as `erth_anomaly`
+#[derive(Debug,Clone,Copy,Default)]
+struct Anomalies {
+    /// The queue grew unusually fast
+    queue_spike: bool,
+
+    /// Implausibly many tickets were called at once
+    implausible_jump: bool,
+}
+
+/// Extreme waiting times and queue lengths of a queue, reset together with the ticket numbers
+#[derive(Debug,Clone,Default)]
+struct DailyExtremes {
+    max_wait: Option<Duration>,
+    min_wait: Option<Duration>,
+    max_queue_length: Option<usize>,
+    min_queue_length: Option<usize>,
 }
 
 /// Serves queue data over http
 struct Server {
+    config: Config,
     listener: TcpListener,
-    scraper: RefCell<Scraper>,
+
+    /// Scraper for the `/metrics` endpoint, refreshed in the background
+    scraper: Arc<Mutex<Scraper>>,
+
+    /// Scrapers for the `/probe` endpoint, indexed by target and module name
+    probes: RefCell<HashMap<(String, String), Scraper>>,
+
+    /// Metrics about the exporter itself
+    metrics: ServerMetrics,
+
+    /// Clients subscribed to new data frames via `/events`
+    events: EventStream,
+
+    /// Significant changes served on `/feed.xml`
+    feed: Feed,
+
+    /// Schema served on `/graphql`
+    graphql: graphql::Schema,
+
+    /// Stored data frames served on the history endpoints
+    frames: FrameStore,
+}
+
+/// Read access to the stored data frames, without holding the lock of the scraper while reading
+/// the [database](Config::database)
+#[derive(Clone)]
+struct FrameStore {
+    /// Scraper whose in-memory [history](Scraper::history) is served without a database
+    scraper: Arc<Mutex<Scraper>>,
+
+    /// Read-only connection to the database, separate from the one of the scraper
+    #[cfg(feature = "sqlite")]
+    database: Option<Arc<Mutex<Database>>>,
+}
+
+/// Prometheus exporter for the waiting times at the Erlangen town hall
+#[derive(Debug,Parser)]
+#[command(version, about)]
+struct Args {
+    /// Path to a TOML configuration file
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+
+    /// Page to be scraped instead of the configured one, e.g. `file://page.html` for a local copy
+    #[arg(short, long)]
+    source: Option<String>,
+
+    /// Detach from the terminal and keep running in the background
+    #[cfg(unix)]
+    #[arg(long)]
+    daemonize: bool,
+
+    /// File the process ID of the daemon is written to
+    #[cfg(unix)]
+    #[arg(long, requires = "daemonize")]
+    pid_file: Option<PathBuf>,
+
+    /// File the log messages of the daemon are appended to, they are discarded if neither this
+    /// nor `--syslog` is given
+    #[cfg(unix)]
+    #[arg(long, requires = "daemonize", conflicts_with = "syslog")]
+    log_file: Option<PathBuf>,
+
+    /// Send the log messages of the daemon to syslog
+    #[cfg(unix)]
+    #[arg(long, requires = "daemonize")]
+    syslog: bool,
+
+    /// Scrape once, print the metrics to stdout and push them to the Pushgateway if configured,
+    /// exiting with 0 if scraping and pushing succeeded and 1 otherwise, instead of serving them
+    #[arg(long, conflicts_with = "textfile_dir")]
+    once: bool,
+
+    /// Scrape once and write the metrics to `erth-exporter.prom` in this directory for the
+    /// textfile collector of node_exporter, instead of serving them
+    #[arg(long)]
+    textfile_dir: Option<PathBuf>,
+
+    /// Run a command instead of the exporter
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+/// Commands run instead of the exporter
+#[derive(Debug,Subcommand)]
+enum Command {
+    /// Scrape once and report the waiting time as Nagios/Icinga plugin, exiting with 0 (OK),
+    /// 1 (WARNING), 2 (CRITICAL) or 3 (UNKNOWN), or as Checkmk local check
+    Check {
+        /// Only check this service instead of all services
+        #[arg(long)]
+        service: Option<String>,
+
+        /// Waiting time in minutes above which the status is WARNING
+        #[arg(long)]
+        warn: f64,
+
+        /// Waiting time in minutes above which the status is CRITICAL
+        #[arg(long)]
+        crit: f64,
+
+        /// Output format
+        #[arg(long, value_enum, default_value_t)]
+        format: check::Format,
+    },
+
+    /// Print configuration for the monitoring stack matching the configuration of the exporter
+    Generate {
+        #[command(subcommand)]
+        output: Generate,
+    },
+
+    /// Fetch and parse the page once and print the queues as JSON, without touching the state
+    /// file or the database
+    Scrape,
+
+    /// Fetch the page once and store it along with the parsed queues, to collect fixtures for
+    /// tests and bug reports
+    Capture {
+        /// Directory the page and its parsed queues are stored in
+        #[arg(default_value = "fixtures")]
+        dir: PathBuf,
+    },
+
+    /// Scrape on the scrape interval and show a live table of the queues in the terminal
+    Watch,
+
+    /// Check that the configured page and its fallbacks can be fetched and all queues parse,
+    /// printing a report and exiting with 1 on failure
+    Selftest,
+
+    /// Scrape once and print the values as `PUTVAL` commands for the exec plugin of collectd
+    Collectd,
+
+    /// Scrape once and print the values as Munin plugin, also used when invoked through a symlink
+    /// named `erth_...` with the configuration file given in `ERTH_EXPORTER_CONFIG`
+    Munin {
+        /// Request of munin-node, the current values are printed if omitted
+        #[arg(value_enum)]
+        mode: Option<munin::Mode>,
+    },
+
+    /// Write a consistent snapshot of the configured database to a file
+    #[cfg(feature = "sqlite")]
+    Backup {
+        /// File the snapshot is written to, must not exist yet
+        file: PathBuf,
+    },
+
+    /// Replace the configured database by a snapshot, while the exporter is stopped
+    #[cfg(feature = "sqlite")]
+    Restore {
+        /// Snapshot created by the `backup` command or the `/admin/backup` endpoint
+        file: PathBuf,
+    },
+
+    /// Backfill the configured database from CSV exports, `/history` dumps or other databases
+    #[cfg(feature = "sqlite")]
+    Import {
+        /// Files to import, the format is derived from the extension (`.csv`, `.json`, or SQLite)
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+}
+
+/// Outputs of the `generate` command
+#[derive(Debug,Subcommand)]
+enum Generate {
+    /// Prometheus `scrape_configs` for the configured listen address
+    ScrapeConfig {
+        /// Print a ServiceMonitor of the Prometheus Operator instead
+        #[arg(long)]
+        service_monitor: bool,
+    },
+
+    /// Prometheus alerting rules for failing scrapes, stale data and stuck queues
+    Alerts,
+
+    /// Grafana dashboard of the queues, waiting times, throughput and exporter health
+    Dashboard,
+}
+
+/// Errors that may occur while scraping the town-hall website
+#[derive(Debug,Clone)]
+enum ScrapeError {
+    /// Fetching the page failed
+    Http(String),
+
+    /// The content of the page could not be parsed
+    Parse(ParseError),
+
+    /// The page does not contain any queue blocks
+    NotEnoughBlocks,
+
+    /// The upstream server is rate limiting requests for the given time
+    Throttled(Duration),
+}
+
+/// Details on content of the town-hall website that could not be parsed
+#[derive(Debug,Clone)]
+struct ParseError {
+    /// Index of the queue block the error occurred in, if it is specific to one
+    block: Option<usize>,
+
+    /// CSS selector used to locate the offending content
+    selector: String,
+
+    /// Description of what went wrong
+    message: String,
+
+    /// Raw text that could not be parsed, if any
+    raw: Option<String>,
 }
 
 /// Http responses
@@ -99,290 +548,1517 @@ enum ResponseType {
     Ok,
     BadRequest,
     NotFound,
+    #[cfg(feature = "sqlite")]
+    Unauthorized,
+    InternalServerError,
 }
 
 
 impl Ticket {
     fn parse(s: &str) -> Result<Self, ()> {
-        use TicketType::*;
-        let t = match s.chars().next() {
-            Some('B') => B,
-            Some('F') => F,
-            _ => TicketType::None,
-        };
+        let mut chars = s.chars();
+        match chars.next() {
+            Some(c) if c.is_ascii_uppercase() && chars.as_str().starts_with(|d: char| d.is_ascii_digit()) => {
+                Ok(Ticket(TicketType::Letter(c), str::parse(chars.as_str()).map_err(|_| ())?))
+            },
+            _ => Ok(Ticket(TicketType::None, 0)),
+        }
+    }
+}
 
-        match t {
-            B | F => Ok(Ticket(t, str::parse(&s[1..]).map_err(|_| ())?)),
-            None => Ok(Ticket(None, 0)),
+impl ResponseType {
+    fn code_and_reason(&self) -> &'static str {
+        match self {
+            ResponseType::Ok => "200 OK",
+            ResponseType::BadRequest => "400 BAD REQUEST",
+            ResponseType::NotFound => "404 NOT FOUND",
+            #[cfg(feature = "sqlite")]
+            ResponseType::Unauthorized => "401 UNAUTHORIZED",
+            ResponseType::InternalServerError => "500 INTERNAL SERVER ERROR",
+        }
+    }
+}
+
+impl Command {
+    /// Run the command with the given configuration
+    fn run(self, config: &Config) -> Result<(), String> {
+        match self {
+            Command::Check { service, warn, crit, format } => {
+                process::exit(check::run(config, service.as_deref(), warn, crit, format))
+            },
+            Command::Generate { output } => {
+                let output = match output {
+                    Generate::ScrapeConfig { service_monitor: false } => generate::scrape_config(config),
+                    Generate::ScrapeConfig { service_monitor: true } => generate::service_monitor(config),
+                    Generate::Alerts => generate::alerts(config),
+                    Generate::Dashboard => generate::dashboard(config),
+                };
+                print!("{}", output);
+                Ok(())
+            },
+            Command::Scrape => {
+                let mut source = HtmlSource::configured(config);
+                let start = Instant::now();
+                let data = source.fetch()
+                    .map_err(|e| e.to_string())?;
+                let mut scraper = Scraper::new(Box::new(source), config);
+                scraper.update(Ok(data), start);
+                let queues = scraper.queues(&config.timezone)
+                    .ok_or_else(|| String::from("no data received"))?;
+                let json = serde_json::to_string_pretty(&queues)
+                    .map_err(|e| e.to_string())?;
+                println!("{}", json);
+                Ok(())
+            },
+            Command::Capture { dir } => capture::run(config, &dir),
+            Command::Watch => watch::run(config),
+            Command::Selftest => selftest::run(config),
+            Command::Collectd => collectd::run(config),
+            Command::Munin { mode } => munin::run(config, mode),
+            #[cfg(feature = "sqlite")]
+            Command::Import { files } => {
+                let path = config.database.as_ref()
+                    .ok_or_else(|| String::from("no database configured"))?;
+                let mut database = Database::open(path)?;
+                for file in files {
+                    let imported = database.import(&file)?;
+                    println!("Imported {} samples from {}", imported, file.display());
+                }
+                Ok(())
+            },
+            #[cfg(feature = "sqlite")]
+            Command::Backup { file } => {
+                let path = config.database.as_ref()
+                    .ok_or_else(|| String::from("no database configured"))?;
+                Database::open(path)?.backup(&file)
+            },
+            #[cfg(feature = "sqlite")]
+            Command::Restore { file } => {
+                let path = config.database.as_ref()
+                    .ok_or_else(|| String::from("no database configured"))?;
+                Database::restore(&file, path)
+            },
         }
     }
 }
 
 impl Server {
-    /// Bind the server on a specific address
-    pub fn init(addr: &str) -> io::Result<Self> {
+    /// Endpoints distinguished in `erth_http_requests_total`, other paths are counted as `other`
+    const ENDPOINTS: [&'static str; 18] = ["/metrics", "/probe", "/status", "/now", "/badge", "/widget/waybar",
+        "/feed.xml", "/wait", "/history", "/export.csv", "/events", "/ws", "/graphql", "/api/openapi.json",
+        "/api/v1/queues", "/api/v1/stats", "/api/v1/forecast", "/admin/backup"];
+
+    /// Bind the server on the configured address
+    pub fn init(config: Config) -> io::Result<Self> {
+        #[cfg_attr(not(feature = "redis"), allow(unused_mut))]
+        let mut scraper = Scraper::open(&config)
+            .map_err(io::Error::other)?;
+        #[cfg(feature = "redis")]
+        if let Some(redis) = &config.redis {
+            scraper.shared = Some(SharedCache::open(redis).map_err(io::Error::other)?);
+        }
+
+        let scraper = Arc::new(Mutex::new(scraper));
+        let frames = FrameStore::open(&config, Arc::clone(&scraper))
+            .map_err(io::Error::other)?;
+
         Ok(Server {
-            listener: TcpListener::bind(addr)?,
-            scraper: RefCell::new(Scraper::new()),
+            listener: TcpListener::bind(&config.listen)?,
+            scraper, frames,
+            probes: RefCell::new(HashMap::new()),
+            metrics: ServerMetrics::new(&config),
+            events: EventStream::new(),
+            feed: Feed::new(),
+            graphql: graphql::schema(),
+            config,
         })
     }
 
     /// Game-loop for the server
     pub fn run(&mut self) {
-        for stream in self.listener.incoming() {
-            if let Ok(stream) = stream {
-                stream.set_read_timeout(Some(Duration::from_millis(500)))
-                    .expect("Read timeout may not be zero");
-                let _ = self.handle_connection(stream);
+        self.spawn_background_scraper();
+        self.spawn_pushers();
+        push::spawn(Box::new(self.events.clone()), Arc::clone(&self.scraper), EVENTS_POLL_INTERVAL);
+        if let Some(opening_hours) = &self.config.opening_hours {
+            self.feed.watch_opening_hours(opening_hours.clone(), self.config.timezone);
+        }
+        #[cfg(feature = "sqlite")]
+        self.spawn_compaction();
+
+        #[cfg(target_os = "linux")]
+        systemd::notify_ready(Arc::clone(&self.scraper));
+
+        // with a watchdog, the listener is polled so that it can be pinged while idle
+        #[cfg(target_os = "linux")]
+        let mut watchdog = systemd::Watchdog::from_env();
+        #[cfg(target_os = "linux")]
+        if watchdog.is_some() {
+            self.listener.set_nonblocking(true)
+                .expect("Listener must support non-blocking mode");
+        }
+
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    let _ = stream.set_nonblocking(false);
+                    stream.set_read_timeout(Some(Duration::from_millis(500)))
+                        .expect("Read timeout may not be zero");
+                    let _ = self.handle_connection(stream);
+                },
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => thread::sleep(ACCEPT_POLL_INTERVAL),
+                Err(_) => (),
+            }
+            #[cfg(target_os = "linux")]
+            if let Some(watchdog) = &mut watchdog {
+                watchdog.ping_if_due();
+            }
+        }
+    }
+
+    /// Periodically refresh the [main scraper](Server::scraper) in a background thread
+    ///
+    /// While the town hall is closed according to its [opening hours](Config::opening_hours), the
+    /// page is only scraped every [closed scrape interval](Config::closed_scrape_interval).
+    fn spawn_background_scraper(&self) {
+        let scraper = Arc::clone(&self.scraper);
+        let Some(mut source) = scraper.lock().unwrap().source.take() else {
+            return;
+        };
+        #[cfg(feature = "redis")]
+        let mut shared = scraper.lock().unwrap().shared.take();
+        let min_interval = Duration::from_secs(self.config.min_scrape_interval);
+        let interval = Duration::from_secs(self.config.scrape_interval).max(min_interval);
+        let closed_interval = self.config.closed_scrape_interval
+            .map(|i| Duration::from_secs(i).max(min_interval));
+        let config = self.config.clone();
+
+        thread::spawn(move || {
+            let mut last_scrape: Option<Instant> = None;
+            loop {
+                let start = Instant::now();
+                let current_interval = match config.office_open() {
+                    Some(false) => closed_interval,
+                    _ => Some(interval),
+                };
+                let due = current_interval
+                    .is_some_and(|i| last_scrape.is_none_or(|t| t.elapsed() >= i.saturating_sub(interval / 2)))
+                    && !scraper.lock().unwrap().circuit_open();
+
+                if let (true, Some(current_interval)) = (due, current_interval) {
+                    last_scrape = Some(start);
+                    // frames only expire once a refresh is missed
+                    let expiration = 2 * current_interval;
+
+                    #[cfg(feature = "redis")]
+                    if let Some(shared) = &mut shared {
+                        if Self::follow(shared, &scraper, expiration) {
+                            thread::sleep(interval.saturating_sub(start.elapsed()));
+                            continue;
+                        }
+                    }
+
+                    let data = source.fetch();
+                    let mut scraper = scraper.lock().unwrap();
+                    scraper.update(data, start);
+                    scraper.cache_expiration = expiration;
+
+                    #[cfg(feature = "redis")]
+                    if let (Some(shared), Some(data)) = (&mut shared, &scraper.cache) {
+                        if let Err(e) = shared.publish(data, &State::capture(&scraper.queues), expiration) {
+                            eprintln!("Warning: {}", e);
+                        }
+                    }
+                }
+                thread::sleep(interval.saturating_sub(start.elapsed()));
             }
+        });
+    }
+
+    /// Push new data frames of the [main scraper](Server::scraper) to the configured sinks
+    fn spawn_pushers(&self) {
+        let scrape_interval = Duration::from_secs(self.config.scrape_interval);
+        if let Some(influxdb) = &self.config.influxdb {
+            let interval = influxdb.interval.map_or(scrape_interval, Duration::from_secs);
+            push::spawn(Box::new(InfluxDb::new(&self.config, influxdb)), Arc::clone(&self.scraper), interval);
+        }
+        if let Some(graphite) = &self.config.graphite {
+            let interval = graphite.interval.map_or(scrape_interval, Duration::from_secs);
+            push::spawn(Box::new(Graphite::new(graphite)), Arc::clone(&self.scraper), interval);
+        }
+        if let Some(statsd) = &self.config.statsd {
+            let interval = statsd.interval.map_or(scrape_interval, Duration::from_secs);
+            push::spawn(Box::new(Statsd::new(statsd)), Arc::clone(&self.scraper), interval);
+        }
+        if let Some(remote_write) = &self.config.remote_write {
+            let interval = remote_write.interval.map_or(scrape_interval, Duration::from_secs);
+            push::spawn(Box::new(RemoteWrite::new(&self.config, remote_write)), Arc::clone(&self.scraper), interval);
+        }
+        if let Some(pushgateway) = &self.config.pushgateway {
+            let interval = pushgateway.interval.map_or(scrape_interval, Duration::from_secs);
+            push::spawn(Box::new(Pushgateway::new(&self.config, pushgateway)), Arc::clone(&self.scraper), interval);
+        }
+        if let Some(mqtt) = &self.config.mqtt {
+            let interval = mqtt.interval.map_or(scrape_interval, Duration::from_secs);
+            push::spawn(Box::new(Mqtt::new(mqtt)), Arc::clone(&self.scraper), interval);
+        }
+        if let Some(zabbix) = &self.config.zabbix {
+            let interval = zabbix.interval.map_or(scrape_interval, Duration::from_secs);
+            push::spawn(Box::new(Zabbix::new(zabbix)), Arc::clone(&self.scraper), interval);
+        }
+
+        let mut notifiers: Vec<Box<dyn Notifier>> = self.config.webhooks.iter()
+            .map(|w| Box::new(Webhook::new(&self.config, w)) as Box<dyn Notifier>)
+            .collect();
+        notifiers.push(Box::new(self.feed.clone()));
+        if let Some(config) = &self.config.telegram {
+            let telegram = Telegram::new(&self.config, config);
+            if config.commands {
+                telegram.spawn_commands(Arc::clone(&self.scraper));
+            }
+            notifiers.push(Box::new(telegram));
+        }
+        if let Some(ntfy) = &self.config.ntfy {
+            notifiers.push(Box::new(Ntfy::new(&self.config, ntfy)));
+        }
+        if let Some(config) = &self.config.matrix {
+            let matrix = Matrix::new(&self.config, config);
+            if config.updates {
+                let interval = config.interval.map_or(scrape_interval, Duration::from_secs);
+                push::spawn(Box::new(matrix.clone()), Arc::clone(&self.scraper), interval);
+            }
+            notifiers.push(Box::new(matrix));
+        }
+        #[cfg(feature = "smtp")]
+        if let Some(smtp) = &self.config.smtp {
+            notifiers.push(Box::new(Smtp::new(smtp).expect("SMTP settings are validated with the config")));
+        }
+        if !self.config.rules.is_empty() {
+            let notifications = Notifications::new(&self.config.rules, notifiers);
+            push::spawn(Box::new(notifications), Arc::clone(&self.scraper), scrape_interval);
         }
     }
 
+    /// Adopt the data published by another replica, unless this one may scrape the page itself
+    ///
+    /// Returns whether another replica is scraping the page. If Redis is unreachable, the page
+    /// is scraped locally.
+    #[cfg(feature = "redis")]
+    fn follow(shared: &mut SharedCache, scraper: &Mutex<Scraper>, expiration: Duration) -> bool {
+        match shared.acquire(expiration) {
+            Ok(true) => false,
+            Ok(false) => {
+                match shared.fetch() {
+                    Ok(Some((data, state))) => {
+                        let mut scraper = scraper.lock().unwrap();
+                        scraper.adopt(data, state);
+                        scraper.cache_expiration = expiration;
+                    },
+                    Ok(None) => (),
+                    Err(e) => eprintln!("Warning: {}", e),
+                }
+                true
+            },
+            Err(e) => {
+                eprintln!("Warning: {}", e);
+                false
+            },
+        }
+    }
+
+    /// Periodically compact the [database](Config::database) in a background thread, using a
+    /// separate connection
+    #[cfg(feature = "sqlite")]
+    fn spawn_compaction(&self) {
+        let Some(path) = self.config.database.clone() else {
+            return;
+        };
+        let retention = self.config.retention.clone();
+
+        thread::spawn(move || {
+            let mut database = match Database::open(&path) {
+                Ok(database) => database,
+                Err(e) => return eprintln!("Error: {}", e),
+            };
+            loop {
+                if let Err(e) = database.compact(&retention) {
+                    eprintln!("Error: {}", e);
+                }
+                thread::sleep(Duration::from_secs(retention.compaction_interval));
+            }
+        });
+    }
+
     /// Serve a request
     fn handle_connection(&self, stream: TcpStream) -> io::Result<()> {
-        let reader = BufReader::new(&stream);
-        let request_line = match reader.lines().next() {
+        let mut reader = BufReader::new(&stream);
+        let mut lines = (&mut reader).lines();
+        let request_line = match lines.next() {
             Some(line) => line?,
             None => return Ok(()),
         };
+        let request_headers: HashMap<_, _> = lines.map_while(Result::ok)
+            .take_while(|line| !line.is_empty())
+            .filter_map(|line| {
+                let (key, value) = line.split_once(':')?;
+                Some((key.trim().to_ascii_lowercase(), value.trim().to_owned()))
+            })
+            .collect();
+
+        let length = request_headers.get("content-length")
+            .map_or(Ok(0), |l| l.parse::<usize>());
+        let body = match length {
+            Ok(length) if length <= MAX_REQUEST_BODY_SIZE => {
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body)?;
+                body
+            },
+            _ => return Self::send_response(stream, ResponseType::BadRequest, HashMap::new(), None),
+        };
 
         let request_tokens: Vec<_> = request_line.split(' ').collect();
 
 
         if request_tokens.len() != 3 {
             Self::send_response(stream, ResponseType::BadRequest, HashMap::new(), None)
+        } else if request_tokens[0] == "POST" && request_tokens[1] == "/graphql" {
+            self.metrics.http_requests.with_label_values(&["/graphql"]).inc();
+            self.send_graphql(stream, serde_json::from_slice(&body).ok())
         } else if request_tokens[0] != "GET" {
             Self::send_response(stream, ResponseType::NotFound, HashMap::new(), None)
         } else {
-            let path = request_tokens[1];
+            let (path, query) = request_tokens[1].split_once('?')
+                .unwrap_or((request_tokens[1], ""));
+
+            let names: Vec<_> = form_urlencoded::parse(query.as_bytes())
+                .filter(|(key, _)| key == "name[]")
+                .map(|(_, value)| value.into_owned())
+                .collect();
+
+            let endpoint = Self::ENDPOINTS.into_iter()
+                .find(|e| *e == path || (*e == "/badge" && path.starts_with("/badge/")))
+                .unwrap_or("other");
+            self.metrics.http_requests.with_label_values(&[endpoint]).inc();
+
+            #[cfg(feature = "sqlite")]
+            if path == "/admin/backup" {
+                return self.send_backup(stream, &request_headers);
+            }
 
             if path == "/metrics" {
-                match self.scraper.borrow_mut().metrics() {
-                    Ok(response) => Self::send_response(stream, ResponseType::Ok, HashMap::new(), Some(&response)),
+                if let (Some(office_open), Some(open)) = (&self.metrics.office_open, self.config.office_open()) {
+                    office_open.set(open as i64);
+                }
+
+                let mut families = self.scraper.lock().unwrap().metrics(false);
+                families.extend(self.metrics.gather());
+                Self::send_metrics(stream, families, &names, &request_headers)
+            } else if path == "/probe" {
+                let params: HashMap<_, _> = form_urlencoded::parse(query.as_bytes())
+                    .into_owned()
+                    .collect();
+                let target = match params.get("target") {
+                    Some(target) if self.config.probe_targets.contains(target) => target,
+                    _ => return Self::send_response(stream, ResponseType::BadRequest, HashMap::new(), None),
+                };
+                let module_name = params.get("module").unwrap_or(&self.config.module);
+                let module = match self.config.find_module(module_name) {
+                    Some(module) => module,
+                    None => return Self::send_response(stream, ResponseType::BadRequest, HashMap::new(), None),
+                };
+
+                let families = self.probes.borrow_mut()
+                    .entry((target.clone(), module_name.clone()))
+                    .or_insert_with(|| {
+                        let source = HtmlSource::new(vec![target.clone()], module, &self.config);
+                        Scraper::new(Box::new(source), &self.config)
+                    })
+                    .metrics(true);
+                Self::send_metrics(stream, families, &names, &request_headers)
+            } else if path == "/status" {
+                let queues = self.scraper.lock().unwrap().queues(&self.config.timezone);
+                let page = status::render(queues.as_ref(), self.config.scrape_interval);
+                let headers = HashMap::from([("Content-Type", "text/html; charset=utf-8")]);
+                Self::send_response(stream, ResponseType::Ok, headers, Some(page.as_bytes()))
+            } else if path == "/now" {
+                let queues = self.scraper.lock().unwrap().queues(&self.config.timezone);
+                let line = format!("{}\n", status::line(queues.as_ref()));
+                let headers = HashMap::from([("Content-Type", "text/plain; charset=utf-8")]);
+                Self::send_response(stream, ResponseType::Ok, headers, Some(line.as_bytes()))
+            } else if path == "/widget/waybar" {
+                let service = form_urlencoded::parse(query.as_bytes())
+                    .find(|(key, _)| key == "service")
+                    .map(|(_, value)| value.into_owned())
+                    .or_else(|| self.config.widget.service.clone());
+                let queues = self.scraper.lock().unwrap().queues(&self.config.timezone);
+                let widget = Waybar::new(queues.as_ref(), service.as_deref(), self.config.office_open(), &self.config.widget);
+                Self::send_json(stream, &widget)
+            } else if let Some(service) = path.strip_prefix("/badge/").and_then(|p| p.strip_suffix(".svg")) {
+                let queues = self.scraper.lock().unwrap().queues(&self.config.timezone);
+                let Some(queue) = queues.as_ref().and_then(|q| q.queues.get(service)) else {
+                    return Self::send_response(stream, ResponseType::NotFound, HashMap::new(), None);
+                };
+                let svg = match self.config.office_open() {
+                    Some(false) => badge::render("closed", "#9f9f9f"),
+                    _ => badge::render(&format!("{} min", (queue.waiting_time_seconds / 60.0).round()),
+                        badge::color(queue.waiting_time_seconds)),
+                };
+                let headers = HashMap::from([("Content-Type", "image/svg+xml"), ("Cache-Control", "no-cache")]);
+                Self::send_response(stream, ResponseType::Ok, headers, Some(svg.as_bytes()))
+            } else if path == "/feed.xml" {
+                let headers = HashMap::from([("Content-Type", "application/atom+xml; charset=utf-8")]);
+                Self::send_response(stream, ResponseType::Ok, headers, Some(self.feed.render().as_bytes()))
+            } else if path == "/history" {
+                let minutes = form_urlencoded::parse(query.as_bytes())
+                    .find(|(key, _)| key == "minutes")
+                    .map(|(_, value)| value.parse::<u64>());
+                let span = match minutes {
+                    Some(Ok(minutes)) => Some(Duration::from_secs(minutes.saturating_mul(60))),
+                    Some(Err(_)) => return Self::send_response(stream, ResponseType::BadRequest, HashMap::new(), None),
+                    None => None,
+                };
+
+                let from = span.map_or(f64::NEG_INFINITY, |s| unix_timestamp().saturating_sub(s).as_secs_f64());
+                let frames: Vec<HistoryFrame> = self.scraper.lock().unwrap()
+                    .history.between(from, f64::INFINITY)
+                    .into_iter().cloned().collect();
+                Self::send_json(stream, &frames)
+            } else if path == "/export.csv" {
+                let Some((from, to)) = time_range(query, f64::NEG_INFINITY) else {
+                    return Self::send_response(stream, ResponseType::BadRequest, HashMap::new(), None);
+                };
+                let frames = self.frames.between(from, to);
+                match frames {
+                    Ok(frames) => {
+                        let headers = HashMap::from([("Content-Type", "text/csv")]);
+                        Self::send_response(stream, ResponseType::Ok, headers, Some(history::to_csv(&frames).as_bytes()))
+                    },
                     Err(e) => {
                         eprintln!("Error: {}", e);
-                        Self::send_response(stream, ResponseType::NotFound, HashMap::new(), None)
+                        Self::send_response(stream, ResponseType::InternalServerError, HashMap::new(), None)
                     },
                 }
+            } else if path == "/events" {
+                let current = self.scraper.lock().unwrap().cache.as_ref().map(HistoryFrame::from);
+                self.events.subscribe(stream, current.as_ref())
+            } else if path == "/ws" {
+                let upgrade = request_headers.get("upgrade")
+                    .is_some_and(|u| u.eq_ignore_ascii_case("websocket"));
+                let Some(key) = request_headers.get("sec-websocket-key").filter(|_| upgrade) else {
+                    return Self::send_response(stream, ResponseType::BadRequest, HashMap::new(), None);
+                };
+                let current = self.scraper.lock().unwrap().cache.as_ref().map(HistoryFrame::from);
+                self.events.subscribe_websocket(stream, key, current.as_ref())
+            } else if path == "/graphql" {
+                let params: HashMap<_, _> = form_urlencoded::parse(query.as_bytes())
+                    .into_owned()
+                    .collect();
+                let variables = match params.get("variables").map(|v| serde_json::from_str(v)) {
+                    Some(Ok(variables)) => Some(variables),
+                    Some(Err(_)) => return Self::send_response(stream, ResponseType::BadRequest, HashMap::new(), None),
+                    None => None,
+                };
+                let request = params.get("query")
+                    .map(|q| GraphQLRequest::new(q.clone(), params.get("operationName").cloned(), variables));
+                self.send_graphql(stream, request)
+            } else if path == "/api/openapi.json" {
+                Self::send_json(stream, &openapi::document())
+            } else if path == "/api/v1/queues" {
+                let queues = self.scraper.lock().unwrap().queues(&self.config.timezone);
+                match queues {
+                    Some(queues) => Self::send_api(stream, &queues, &request_headers),
+                    None => Self::send_response(stream, ResponseType::NotFound, HashMap::new(), None),
+                }
+            } else if path == "/api/v1/stats" {
+                let default_from = unix_timestamp().saturating_sub(DEFAULT_STATS_WINDOW).as_secs_f64();
+                let Some((from, to)) = time_range(query, default_from) else {
+                    return Self::send_response(stream, ResponseType::BadRequest, HashMap::new(), None);
+                };
+                let frames = self.frames.between(from, to);
+                match frames {
+                    Ok(frames) => Self::send_api(stream, &stats::compute(&frames, &self.config.timezone), &request_headers),
+                    Err(e) => {
+                        eprintln!("Error: {}", e);
+                        Self::send_response(stream, ResponseType::InternalServerError, HashMap::new(), None)
+                    },
+                }
+            } else if path == "/api/v1/forecast" {
+                let hours = form_urlencoded::parse(query.as_bytes())
+                    .find(|(key, _)| key == "hours")
+                    .map(|(_, value)| value.parse::<u32>());
+                let hours = match hours {
+                    Some(Ok(hours)) if (1..=MAX_FORECAST_HOURS).contains(&hours) => hours,
+                    None => DEFAULT_FORECAST_HOURS,
+                    _ => return Self::send_response(stream, ResponseType::BadRequest, HashMap::new(), None),
+                };
+
+                let from = unix_timestamp().saturating_sub(FORECAST_WINDOW).as_secs_f64();
+                let frames = self.frames.between(from, f64::INFINITY);
+                match frames {
+                    Ok(frames) => Self::send_api(stream, &stats::forecast(&frames, &self.config.timezone, hours), &request_headers),
+                    Err(e) => {
+                        eprintln!("Error: {}", e);
+                        Self::send_response(stream, ResponseType::InternalServerError, HashMap::new(), None)
+                    },
+                }
+            } else if path == "/wait" {
+                let ticket = form_urlencoded::parse(query.as_bytes())
+                    .find(|(key, _)| key == "ticket")
+                    .and_then(|(_, value)| Ticket::parse(value.trim().to_uppercase().as_str()).ok());
+                let ticket = match ticket {
+                    Some(ticket) if ticket.0 != TicketType::None => ticket,
+                    _ => return Self::send_response(stream, ResponseType::BadRequest, HashMap::new(), None),
+                };
+
+                let estimate = self.scraper.lock().unwrap().wait_estimate(ticket, &self.config.timezone);
+                match estimate {
+                    Some(estimate) => Self::send_json(stream, &estimate),
+                    None => Self::send_response(stream, ResponseType::NotFound, HashMap::new(), None),
+                }
             } else {
                 Self::send_response(stream, ResponseType::NotFound, HashMap::new(), None)
             }
         }
     }
 
-    /// Send a response to the client
-    fn send_response(mut stream: TcpStream, response_type: ResponseType,
-                        headers: HashMap<&str, &str>, content: Option<&str>) -> io::Result<()> {
-        use ResponseType::*;
+    /// Send metric families to the client, only including the ones listed in `names`
+    ///
+    /// The protobuf exposition format is used if the client prefers it according to its `Accept`
+    /// header, the text format otherwise.
+    fn send_metrics(stream: TcpStream, families: Vec<MetricFamily>, names: &[String],
+                    request_headers: &HashMap<String, String>) -> io::Result<()> {
+        let protobuf = request_headers.get("accept")
+            .is_some_and(|accept| metrics::accepts_protobuf(accept));
+        if protobuf {
+            let response = metrics::encode_protobuf(families, names);
+            let headers = HashMap::from([("Content-Type", PROTOBUF_FORMAT)]);
+            Self::send_response(stream, ResponseType::Ok, headers, Some(&response))
+        } else {
+            let response = metrics::encode(families, names);
+            let headers = HashMap::from([("Content-Type", TEXT_FORMAT)]);
+            Self::send_response(stream, ResponseType::Ok, headers, Some(response.as_bytes()))
+        }
+    }
+
+    /// Send a snapshot of the [database](Config::database) to the client, if it authenticated with
+    /// the [admin token](Config::admin_token)
+    #[cfg(feature = "sqlite")]
+    fn send_backup(&self, mut stream: TcpStream, request_headers: &HashMap<String, String>) -> io::Result<()> {
+        let Some(token) = &self.config.admin_token else {
+            return Self::send_response(stream, ResponseType::NotFound, HashMap::new(), None);
+        };
+        let authorized = request_headers.get("authorization")
+            .and_then(|h| h.strip_prefix("Bearer "))
+            .is_some_and(|t| constant_time_eq(t.as_bytes(), token.as_bytes()));
+        if !authorized {
+            let headers = HashMap::from([("WWW-Authenticate", "Bearer")]);
+            return Self::send_response(stream, ResponseType::Unauthorized, headers, None);
+        }
+
+        // taken on the read-only connection, so scraping goes on meanwhile
+        let snapshot = match &self.frames.database {
+            Some(database) => database.lock().unwrap().snapshot(),
+            None => return Self::send_response(stream, ResponseType::NotFound, HashMap::new(), None),
+        };
+        let snapshot = snapshot.and_then(|file| {
+            let length = file.as_file().metadata()
+                .map_err(|e| format!("cannot read snapshot: {}", e))?
+                .len();
+            Ok((file, length))
+        });
+        match snapshot {
+            Ok((mut file, length)) => {
+                let headers = HashMap::from([
+                    ("Content-Type", "application/vnd.sqlite3"),
+                    ("Content-Disposition", "attachment; filename=\"erth-exporter.sqlite\""),
+                ]);
+                Self::write_head(&mut stream, ResponseType::Ok, &headers, length)?;
+                io::copy(&mut file, &mut stream)?;
+                stream.flush()
+            },
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                Self::send_response(stream, ResponseType::InternalServerError, HashMap::new(), None)
+            },
+        }
+    }
 
-        let code_and_reason = match response_type {
-            Ok => "200 OK",
-            BadRequest => "400 BAD REQUEST",
-            NotFound => "404 NOT FOUND",
+    /// Execute a GraphQL request against the [scraper](Server::scraper) and send the result to
+    /// the client
+    fn send_graphql(&self, stream: TcpStream, request: Option<GraphQLRequest>) -> io::Result<()> {
+        let Some(request) = request else {
+            return Self::send_response(stream, ResponseType::BadRequest, HashMap::new(), None);
         };
+        let context = graphql::Context {
+            scraper: Arc::clone(&self.scraper),
+            frames: self.frames.clone(),
+            timezone: self.config.timezone,
+        };
+        Self::send_json(stream, &request.execute_sync(&self.graphql, &context))
+    }
 
+    /// Send a value serialized as JSON to the client
+    fn send_json<T: serde::Serialize>(stream: TcpStream, value: &T) -> io::Result<()> {
+        let response = serde_json::to_string(value)
+            .map_err(io::Error::other)?;
+        let headers = HashMap::from([("Content-Type", "application/json")]);
+        Self::send_response(stream, ResponseType::Ok, headers, Some(response.as_bytes()))
+    }
+
+    /// Send a response of the `/api/v1/*` endpoints to the client, encoded as JSON, MessagePack or
+    /// CBOR according to the `Accept` header
+    fn send_api<T: serde::Serialize>(stream: TcpStream, value: &T, request_headers: &HashMap<String, String>) -> io::Result<()> {
+        let encoding = Encoding::negotiate(request_headers.get("accept").map(String::as_str));
+        let response = encoding.encode(value)
+            .map_err(io::Error::other)?;
+        let headers = HashMap::from([("Content-Type", encoding.content_type()), ("Vary", "Accept")]);
+        Self::send_response(stream, ResponseType::Ok, headers, Some(&response))
+    }
+
+    /// Send a response to the client
+    fn send_response(mut stream: TcpStream, response_type: ResponseType,
+                        headers: HashMap<&str, &str>, content: Option<&[u8]>) -> io::Result<()> {
         let content = match content {
             Some(content) => content,
-            None => code_and_reason,
+            None => response_type.code_and_reason().as_bytes(),
         };
-        let length = content.len();
+        Self::write_head(&mut stream, response_type, &headers, content.len() as u64)?;
+
+        // Content
+        stream.write_all(content)?;
 
+        stream.flush()
+    }
+
+    /// Write the status line and headers of a response with `length` bytes of content
+    fn write_head(stream: &mut TcpStream, response_type: ResponseType, headers: &HashMap<&str, &str>,
+                    length: u64) -> io::Result<()> {
         // Status line
-        write!(stream, "{HTTP_VERSION} {code_and_reason}\r\n")?;
+        write!(stream, "{HTTP_VERSION} {}\r\n", response_type.code_and_reason())?;
 
         // Headers
-        for (key, value) in &headers {
+        for (key, value) in headers {
             write!(stream, "{key}: {value}\r\n")?;
         }
-        write!(stream, "Content-Length: {length}\r\n\r\n")?;
+        write!(stream, "Content-Length: {length}\r\n\r\n")
+    }
+}
 
-        // Content
-        write!(stream, "{content}")?;
+impl FrameStore {
+    /// Open a separate connection to the [database](Config::database) if configured
+    fn open(#[cfg_attr(not(feature = "sqlite"), allow(unused_variables))] config: &Config, scraper: Arc<Mutex<Scraper>>)
+            -> Result<Self, String> {
+        Ok(FrameStore {
+            scraper,
+            #[cfg(feature = "sqlite")]
+            database: match &config.database {
+                Some(path) => Some(Arc::new(Mutex::new(Database::open_read_only(path)?))),
+                None => None,
+            },
+        })
+    }
 
-        stream.flush()
+    /// Data frames scraped between `from` and `to` (seconds since the epoch), from the
+    /// [database](Config::database) if configured or the in-memory [history](Scraper::history)
+    fn between(&self, from: f64, to: f64) -> Result<Vec<HistoryFrame>, String> {
+        #[cfg(feature = "sqlite")]
+        if let Some(database) = &self.database {
+            return database.lock().unwrap().between(from, to);
+        }
+        // the in-memory history is bounded, so the lock is only held briefly
+        Ok(self.scraper.lock().unwrap().history.between(from, to).into_iter().cloned().collect())
     }
 }
 
 impl Scraper {
-    fn new() -> Self {
-        Scraper {
+    /// Scraper of the configured page, restoring the [state file](Config::state_file) and storing
+    /// frames in the [database](Config::database) if configured
+    fn open(config: &Config) -> Result<Self, String> {
+        let mut scraper = Scraper::new(Box::new(HtmlSource::configured(config)), config);
+        if let Some(path) = &config.state_file {
+            scraper.persist(path.clone())?;
+        }
+        #[cfg(feature = "sqlite")]
+        if let Some(path) = &config.database {
+            scraper.database = Some(Database::open(path)?);
+        }
+        Ok(scraper)
+    }
+
+    fn new(source: Box<dyn ScrapeSource>, config: &Config) -> Self {
+        let services = source.services();
+        let scraper = Scraper {
+            source: Some(source),
+            #[cfg(feature = "redis")]
+            shared: None,
+            cache_expiration: Duration::from_secs(config.scrape_interval),
+            max_staleness: Duration::from_secs(config.max_staleness),
             cache: None,
-            ticket_tracker: HashMap::new(),
-            last_tracked_waiting_time: [None; 2],
+            circuit_breaker: config.circuit_breaker.clone(),
+            consecutive_failures: 0,
+            circuit_open_until: None,
+            queues: HashMap::new(),
+            service_rate_window: Duration::from_secs(config.service_rate_window),
+            state_file: None,
+            max_ticket_age: Duration::from_secs(config.max_ticket_age),
+            max_tracked_tickets: config.max_tracked_tickets,
+            smoothing_factor: config.smoothing_factor,
+            anomaly_thresholds: config.anomalies.clone(),
+            timezone: config.timezone,
+            opening_hours: config.opening_hours.clone(),
+            history: History::new(config.history_size),
+            #[cfg(feature = "sqlite")]
+            database: None,
+            metrics: ScraperMetrics::new(config),
+        };
+
+        for reason in ScrapeError::REASONS {
+            scraper.metrics.scrape_errors.with_label_values(&[reason]);
+        }
+        for service in services {
+            scraper.metrics.tickets_called.with_label_values(&[&service]);
+            scraper.metrics.ticket_resets.with_label_values(&[&service]);
         }
+
+        scraper
     }
 
-    /// Create a metrics string in the [Prometheus data format](https://prometheus.io/docs/instrumenting/writing_exporters/).
+    /// Collect the metrics in the [Prometheus data format](https://prometheus.io/docs/instrumenting/writing_exporters/).
     ///
-    /// Metrics are taken either from [cache](CACHED_FRAME) or are [freshly scraped](scrape).
-    fn metrics(&mut self) -> Result<String, String> {
-        use TicketType::*;
-        let data = if self.cache.is_some() && self.cache.as_ref().unwrap().created_instant > Instant::now() - CACHE_EXPIRATION {
-            self.cache.clone().unwrap()
+    /// Metrics are taken from [cache](Scraper::cache), which is [refreshed](Scraper::refresh) first
+    /// if it has expired and `scrape_if_expired` is set. If no data is available the data frame
+    /// metrics are left out.
+    fn metrics(&mut self, scrape_if_expired: bool) -> Vec<MetricFamily> {
+        if self.is_expired() {
+            self.metrics.cache_misses.inc();
+            if scrape_if_expired {
+                self.refresh();
+            }
         } else {
-            let data = self.scrape()?;
-            self.cache.insert(data.clone())
-                .cached = true;
-            data
+            self.metrics.cache_hits.inc();
+        }
+
+        let families = self.snapshot();
+        // later requests are served the same frame from cache
+        if let Some(data) = self.servable_frame() {
+            data.cached = true;
+        }
+        families
+    }
+
+    /// Render the [cached](Scraper::cache) frame into metric families, without counting a cache
+    /// hit or miss and without marking the frame as served from cache
+    fn snapshot(&mut self) -> Vec<MetricFamily> {
+        self.metrics.circuit_open.set(self.circuit_open() as i64);
+        let stale = self.is_expired();
+        let Some(data) = self.servable_frame().map(|data| data.clone()) else {
+            return self.metrics.gather(None);
         };
 
-        let mut response = String::new();
+        let metrics = &self.metrics;
+        metrics.data_stale.set(stale as i64);
 
-        response.push_str("# Information on the citizen service\n");
-        response.push_str(&format!("erth_people_waiting{{service=\"citizen\"}}\t\t{}\n", data.citizen_services.people_waiting));
-        match data.citizen_services.last_called_ticket.0 {
-            B |F => response.push_str(&format!(
-                "erth_last_called_ticket{{service=\"citizen\",type=\"{}\"}}\t{}\n",
-                data.citizen_services.last_called_ticket.0,
-                data.citizen_services.last_called_ticket.1)),
-            None => (),
-        }
-        response.push_str(&format!("erth_waiting_time{{service=\"citizen\"}}\t\t{}\n", data.citizen_services.waiting_time_estimation));
-        if let Some(tracked_waiting_time) = data.citizen_services.tracked_waiting_time {
-            response.push_str(&format!("erth_tracked_waiting_time{{service=\"citizen\"}}\t\t{}\n", tracked_waiting_time.as_secs() / 60));
-        }
+        metrics.last_called_ticket.reset();
+        metrics.last_tracked_waiting_time_seconds.reset();
+        metrics.smoothed_waiting_time_seconds.reset();
+        metrics.tracked_waiting_time.reset();
+        metrics.people_waiting.reset();
+        metrics.service_rate.reset();
+        metrics.arrival_rate.reset();
+        metrics.tickets_served_per_hour.reset();
+        metrics.anomaly.reset();
+        metrics.daily_max_wait_seconds.reset();
+        metrics.daily_min_wait_seconds.reset();
+        metrics.daily_max_queue_length.reset();
+        metrics.daily_min_queue_length.reset();
+        metrics.estimated_wait_littles_law_seconds.reset();
+        metrics.new_ticket_wait_estimate_seconds.reset();
+        metrics.waiting_time_seconds.reset();
+        metrics.waiting_time.reset();
+        metrics.queue_scrape_success.reset();
+        for (service, queue) in &data.queues {
+            let service = service.as_str();
+            metrics.queue_scrape_success.with_label_values(&[service]).set(queue.is_ok() as i64);
+            let (Ok(queue), Some(state)) = (queue, self.queues.get(service)) else {
+                continue;
+            };
 
-        response.push_str("\n# Information on the drivers-license service\n");
-        response.push_str(&format!("erth_people_waiting{{service=\"drivers_license\"}}\t\t{}\n", data.drivers_license_services.people_waiting));
-        match data.drivers_license_services.last_called_ticket.0 {
-            B |F => response.push_str(&format!(
-                "erth_last_called_ticket{{service=\"drivers_license\",type=\"{}\"}}\t{}\n",
-                data.drivers_license_services.last_called_ticket.0,
-                data.drivers_license_services.last_called_ticket.1)),
-            None => (),
+            metrics.people_waiting.with_label_values(&[service]).set(queue.people_waiting as i64);
+            metrics.service_rate.with_label_values(&[service]).set(state.service_rate());
+            if let Some(smoothed) = state.smoothed_waiting_time {
+                metrics.smoothed_waiting_time_seconds.with_label_values(&[service]).set(smoothed);
+            }
+            metrics.arrival_rate.with_label_values(&[service]).set(state.arrivals.per_minute());
+            metrics.tickets_served_per_hour.with_label_values(&[service])
+                .set(state.served_last_hour.count() as i64);
+            // a stalled queue is not necessarily observed again, as the page does not change
+            let stalled = self.office_open() && queue.people_waiting > 0 && state.last_progress
+                .is_some_and(|t| t.elapsed() > Duration::from_secs(self.anomaly_thresholds.stall_after));
+            let anomalies = [
+                ("queue_spike", state.anomalies.queue_spike),
+                ("stalled", stalled),
+                ("implausible_jump", state.anomalies.implausible_jump),
+            ];
+            for (anomaly, detected) in anomalies {
+                metrics.anomaly.with_label_values(&[service, anomaly]).set(detected as i64);
+            }
+            if let (Some(max), Some(min)) = (state.daily.max_wait, state.daily.min_wait) {
+                metrics.daily_max_wait_seconds.with_label_values(&[service]).set(max.as_secs_f64());
+                metrics.daily_min_wait_seconds.with_label_values(&[service]).set(min.as_secs_f64());
+            }
+            if let (Some(max), Some(min)) = (state.daily.max_queue_length, state.daily.min_queue_length) {
+                metrics.daily_max_queue_length.with_label_values(&[service]).set(max as i64);
+                metrics.daily_min_queue_length.with_label_values(&[service]).set(min as i64);
+            }
+            if let Some(estimate) = state.littles_law_estimate(queue.people_waiting) {
+                metrics.estimated_wait_littles_law_seconds.with_label_values(&[service])
+                    .set(estimate.as_secs_f64());
+            }
+            if let Some(estimate) = state.new_ticket_estimate(queue.people_waiting) {
+                metrics.new_ticket_wait_estimate_seconds.with_label_values(&[service])
+                    .set(estimate.as_secs_f64());
+            }
+            if queue.last_called_ticket.0 != TicketType::None {
+                metrics.last_called_ticket
+                    .with_label_values(&[service, &queue.last_called_ticket.0.to_string()])
+                    .set(queue.last_called_ticket.1 as i64);
+            }
+            metrics.waiting_time_seconds.with_label_values(&[service])
+                .set((queue.waiting_time_estimation * 60) as f64);
+            metrics.waiting_time.with_label_values(&[service]).set(queue.waiting_time_estimation as i64);
+            if let Some(tracked_waiting_time) = queue.tracked_waiting_time {
+                metrics.last_tracked_waiting_time_seconds.with_label_values(&[service])
+                    .set(tracked_waiting_time.as_secs_f64());
+                metrics.tracked_waiting_time.with_label_values(&[service])
+                    .set((tracked_waiting_time.as_secs() / 60) as i64);
+            }
         }
-        response.push_str(&format!("erth_waiting_time{{service=\"drivers_license\"}}\t\t{}\n", data.drivers_license_services.waiting_time_estimation));
-        if let Some(tracked_waiting_time) = data.drivers_license_services.tracked_waiting_time {
-            response.push_str(&format!("erth_tracked_waiting_time{{service=\"drivers_license\"}}\t\t{}\n", tracked_waiting_time.as_secs() / 60));
+
+        metrics.cached.set(data.cached as i64);
+        metrics.cache_age_seconds.set(data.created_instant.elapsed().as_secs_f64());
+        metrics.tracked_tickets.set(self.queues.values().map(|q| q.ticket_tracker.len()).sum::<usize>() as i64);
+        metrics.scrape_duration_seconds.set(data.scrape_duration.as_secs_f64());
+        metrics.scrape_timestamp_seconds.set(data.created_timestamp.as_secs_f64());
+        metrics.scrape_duration.set(data.scrape_duration.as_millis() as i64);
+        metrics.scrape_timestamp.set(data.created_timestamp.as_millis() as i64);
+
+        metrics.gather(Some(data.created_timestamp))
+    }
+
+    /// [Cached](Scraper::cache) frame, unless it has expired for longer than the
+    /// [maximum staleness](Config::max_staleness)
+    ///
+    /// Expired frames are served rather than leaving a gap.
+    fn servable_frame(&mut self) -> Option<&mut DataFrame> {
+        let stale = self.is_expired();
+        self.cache.as_mut()
+            .filter(|data| data.created_instant.elapsed() <= self.max_staleness || !stale)
+    }
+
+    /// Estimate when a ticket will be called, from the [cached](Scraper::cache) frame and the
+    /// service rate of its queue
+    ///
+    /// Returns [None] if no queue currently calls tickets of its type.
+    fn wait_estimate(&self, ticket: Ticket, timezone: &Tz) -> Option<WaitEstimate> {
+        let data = self.cache.as_ref()?;
+        let (service, queue, state) = data.queues.iter()
+            .filter_map(|(service, queue)| Some((service, queue.as_ref().ok()?, self.queues.get(service)?)))
+            .find(|(_, queue, _)| queue.last_called_ticket.0 == ticket.0)?;
+
+        let position = ticket.1.saturating_sub(queue.last_called_ticket.1);
+        let service_rate = state.service_rate();
+        let expected_wait = match position {
+            0 => Some(Duration::ZERO),
+            _ => (service_rate > 0.0)
+                .then(|| Duration::from_secs_f64(position as f64 / service_rate * 60.0)),
+        };
+        let expected_call_time = expected_wait
+            .and_then(|wait| chrono::Duration::from_std(wait).ok())
+            .map(|wait| (Utc::now() + wait).with_timezone(timezone).to_rfc3339());
+
+        Some(WaitEstimate {
+            service: service.clone(),
+            ticket: ticket.to_string(),
+            last_called_ticket: queue.last_called_ticket.to_string(),
+            position,
+            service_rate_per_minute: service_rate,
+            expected_wait_seconds: expected_wait.map(|w| w.as_secs_f64()),
+            expected_call_time,
+        })
+    }
+
+    /// Current state of all queues as served on `/api/v1/queues`, if any data was scraped yet
+    fn queues(&self, timezone: &Tz) -> Option<Queues> {
+        let data = self.cache.as_ref()?;
+        let mut queues = BTreeMap::new();
+        let mut errors = BTreeMap::new();
+        for (service, queue) in &data.queues {
+            let queue = match queue {
+                Ok(queue) => queue,
+                Err(e) => {
+                    errors.insert(service.clone(), e.to_string());
+                    continue;
+                },
+            };
+            let state = self.queues.get(service);
+            queues.insert(service.clone(), Queue {
+                people_waiting: queue.people_waiting,
+                last_called_ticket: queue.last_called_ticket.to_string(),
+                waiting_time_seconds: (queue.waiting_time_estimation * 60) as f64,
+                tracked_waiting_time_seconds: queue.tracked_waiting_time.map(|t| t.as_secs_f64()),
+                smoothed_waiting_time_seconds: state.and_then(|s| s.smoothed_waiting_time),
+                new_ticket_wait_estimate_seconds: state
+                    .and_then(|s| s.new_ticket_estimate(queue.people_waiting))
+                    .map(|d| d.as_secs_f64()),
+                service_rate_per_minute: state.map_or(0.0, |s| s.service_rate()),
+                arrival_rate_per_minute: state.map_or(0.0, |s| s.arrivals.per_minute()),
+            });
         }
 
-        response.push_str("\n# Meta information\n");
-        response.push_str(&format!("erth_cached\t\t{}\n", data.cached as i64));
-        response.push_str(&format!("erth_tracked_tickets\t{}\n", self.ticket_tracker.len()));
-        response.push_str(&format!("erth_scrape_duration\t{}\n", data.scrape_duration.as_millis()));
-        response.push_str(&format!("erth_scrape_timestamp\t{}\n", data.created_timestamp.as_millis()));
+        let scraped_at = chrono::DateTime::from_timestamp(data.created_timestamp.as_secs() as i64,
+            data.created_timestamp.subsec_nanos())
+            .unwrap_or_default()
+            .with_timezone(timezone);
+        Some(Queues {
+            timestamp: data.created_timestamp.as_secs_f64(),
+            scraped_at: scraped_at.to_rfc3339(),
+            age_seconds: data.created_instant.elapsed().as_secs_f64(),
+            stale: self.is_expired(),
+            queues,
+            errors,
+        })
+    }
 
-        Ok(response)
+    /// Whether the [cached](Scraper::cache) frame is missing or has expired
+    fn is_expired(&self) -> bool {
+        self.cache.as_ref()
+            .is_none_or(|c| c.created_instant.elapsed() > self.cache_expiration)
     }
 
-    /// Scrape new information from the town-hall website
-    fn scrape(&mut self) -> Result<DataFrame, String> {
+    /// Scrape new information from the town-hall website and store it in the [cache](Scraper::cache)
+    ///
+    /// Nothing is fetched if the [minimum scrape interval](Config::min_scrape_interval) has not
+    /// passed yet or the [circuit is open](Scraper::circuit_open), the cache is kept as is in that case.
+    fn refresh(&mut self) {
+        if self.circuit_open() {
+            return;
+        }
+        let Some(source) = self.source.as_mut().filter(|s| s.ready()) else {
+            return;
+        };
+
         let start = Instant::now();
-        let response = reqwest::blocking::get(URL)
-            .map_err(|e| e.to_string())?
-            .text()
-            .map_err(|e| e.to_string())?;
-        let document = scraper::Html::parse_document(&response);
-
-        let block_selector = scraper::Selector::parse(BLOCK_SELECTOR)
-            .map_err(|e| e.to_string())?;
-        let line_selector = scraper::Selector::parse(VALUE_SELECTOR)
-            .map_err(|e| e.to_string())?;
-
-        let blocks = document.select(&block_selector)
-            .filter(|b| b.inner_html().contains(BLOCK_CONTENT_FILTER));
-
-        let mut data_frames = Vec::new();
-        for block in blocks {
-            let values: Vec<_> = block.select(&line_selector)
-                .map(|e| e.inner_html())
-                .collect();
-            if values.len() < 3 {
-                return Err(String::from("not enough lines"));
+        let data = source.fetch();
+        self.update(data, start);
+    }
+
+    /// Process a data frame, that was requested at `start`
+    fn update(&mut self, data: Result<Option<DataFrame>, ScrapeError>, start: Instant) {
+        self.metrics.upstream_throttled.set(matches!(data, Err(ScrapeError::Throttled(_))) as i64);
+        let data = data.map(|data| match data {
+            Some(mut data) => {
+                self.track(&mut data);
+                Some(data)
+            },
+            // an unchanged frame does not need to be tracked again
+            None => {
+                self.metrics.upstream_not_modified.inc();
+                self.cache.take().map(|data| DataFrame {
+                    scrape_duration: start.elapsed(),
+                    cached: false,
+                    created_instant: Instant::now(),
+                    created_timestamp: unix_timestamp(),
+                    ..data
+                })
+            },
+        });
+
+        match data {
+            Ok(Some(data)) => self.store(data),
+            // the unchanged data could not be parsed before either
+            Ok(None) => (),
+            Err(e) => {
+                self.metrics.up.set(0);
+                self.record_error(&e);
+                self.register_failure();
+            },
+        }
+    }
+
+    /// Store a successfully scraped data frame in the [cache](Scraper::cache) and history
+    fn store(&mut self, data: DataFrame) {
+        self.metrics.up.set(1);
+        let frame = HistoryFrame::from(&data);
+        #[cfg(feature = "sqlite")]
+        if let Some(database) = &mut self.database {
+            if let Err(e) = database.insert(&frame) {
+                eprintln!("Warning: {}", e);
             }
+        }
+        self.history.push(frame);
+        self.cache = Some(data);
+        self.consecutive_failures = 0;
+    }
 
-            let people_waiting = str::parse(&values[0])
-                .map_err(|_| String::from("cannot parse waiting persons"))?;
-            let last_called_ticket = Ticket::parse(&values[1])
-                .map_err(|_| String::from("cannot parse current ticket"))?;
-            let waiting_time_estimation = str::parse(&values[2].strip_suffix(" Minuten").unwrap_or(&values[2]))
-                .map_err(|_| String::from("cannot parse waiting-time estimation"))?;
+    /// Take over a data frame and the tracking state published by another replica
+    #[cfg(feature = "redis")]
+    fn adopt(&mut self, data: DataFrame, state: State) {
+        state.restore(&mut self.queues, self.service_rate_window);
+        self.store(data);
+    }
 
-            data_frames.push(QueueDataFrame {
-                people_waiting, last_called_ticket, waiting_time_estimation,
-                tracked_waiting_time: None,
-            });
+    /// Whether the town hall is open according to its [opening hours](Scraper::opening_hours),
+    /// assumed to be open if they are not configured
+    fn office_open(&self) -> bool {
+        self.opening_hours.as_ref()
+            .is_none_or(|hours| hours.is_open(&Utc::now().with_timezone(&self.timezone)))
+    }
+
+    /// Whether the [circuit breaker](Config::circuit_breaker) currently prevents scraping
+    fn circuit_open(&self) -> bool {
+        self.circuit_open_until.is_some_and(|t| t > Instant::now())
+    }
+
+    /// Count a failed scrape and open the circuit if there have been too many in a row
+    ///
+    /// The counter is only reset by a successful scrape, so a single failure after the cooldown
+    /// opens the circuit again.
+    fn register_failure(&mut self) {
+        self.consecutive_failures += 1;
+        let threshold = self.circuit_breaker.failures;
+        if threshold > 0 && self.consecutive_failures >= threshold {
+            if !self.circuit_open() {
+                eprintln!("Warning: {} scrapes failed in a row, pausing for {}s",
+                    self.consecutive_failures, self.circuit_breaker.cooldown);
+            }
+            self.circuit_open_until = Some(Instant::now() + Duration::from_secs(self.circuit_breaker.cooldown));
         }
+    }
 
-        if data_frames.len() < 2 {
-            return Err(String::from("not enough data blocks"));
-        }
-
-        self.update_tracker(
-            data_frames[0].last_called_ticket,
-            data_frames[0].people_waiting,
-            TicketType::B);
-        self.update_tracker(
-            data_frames[1].last_called_ticket,
-            data_frames[1].people_waiting,
-            TicketType::F);
-        data_frames[0].tracked_waiting_time = self.last_tracked_waiting_time[0];
-        data_frames[1].tracked_waiting_time = self.last_tracked_waiting_time[1];
-
-        Ok(DataFrame {
-            citizen_services: data_frames[0].clone(),
-            drivers_license_services: data_frames[1].clone(),
-            scrape_duration: time::Instant::now() - start,
-            cached: false,
-            created_instant: Instant::now(),
-            created_timestamp: SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap_or(Duration::new(0, 0)),
-        })
+    /// Restore the tracking state from a file, which it is saved to after every scrape from now on
+    fn persist(&mut self, path: PathBuf) -> Result<(), String> {
+        State::load(&path)?.restore(&mut self.queues, self.service_rate_window);
+        self.state_file = Some(path);
+        Ok(())
     }
 
-    // Update the integrated ticket waiting time tracker and return the latest waiting time
-    fn update_tracker(&mut self, ticket: Ticket, queue_length: usize, expected_ticket_type: TicketType) {
-        let last_tracked_waiting_time = {
-            match expected_ticket_type {
-                TicketType::B => &mut self.last_tracked_waiting_time[0],
-                TicketType::F => &mut self.last_tracked_waiting_time[1],
-                TicketType::None => return,
+    /// Feed the queues of a new data frame into the ticket tracker
+    fn track(&mut self, data: &mut DataFrame) {
+        for (service, queue) in data.queues.iter_mut() {
+            match queue {
+                Ok(queue) => queue.tracked_waiting_time = self.update_tracker(service, queue.last_called_ticket,
+                    queue.people_waiting, queue.waiting_time_estimation),
+                // a single broken queue should not blank out the other ones
+                Err(e) => self.record_error(e),
+            }
+        }
+
+        if let Some(path) = &self.state_file {
+            if let Err(e) = State::capture(&self.queues).save(path) {
+                eprintln!("Warning: {}", e);
             }
+        }
+    }
+
+    /// Log a scrape error and account for it in the metrics
+    fn record_error(&self, e: &ScrapeError) {
+        eprintln!("Error: {}", e);
+        self.metrics.scrape_errors.with_label_values(&[e.reason()]).inc();
+        self.metrics.last_scrape_error_info.reset();
+        self.metrics.last_scrape_error_info.with_label_values(&[e.reason(), &e.to_string()]).set(1);
+
+        if let ScrapeError::Parse(e) = e {
+            let block = e.block.map(|b| b.to_string()).unwrap_or_default();
+            self.metrics.last_parse_error_info.reset();
+            self.metrics.last_parse_error_info
+                .with_label_values(&[&block, &e.selector, e.raw.as_deref().unwrap_or_default()])
+                .set(1);
+        }
+    }
+
+    /// Update the integrated ticket waiting time tracker of a queue and return the latest waiting time
+    fn update_tracker(&mut self, service: &str, ticket: Ticket, queue_length: usize,
+                      waiting_time_estimation: usize) -> Option<Duration> {
+        let state = self.queues.entry(service.to_owned())
+            .or_insert_with(|| QueueState::new(self.service_rate_window));
+
+        let numbers_reset = match state.last_called_number {
+            Some(_) if ticket.0 == TicketType::None => true,
+            Some(last_called_number) => Some(ticket.0) == state.ticket_type && ticket.1 < last_called_number,
+            None => false,
         };
+        if numbers_reset {
+            self.metrics.last_reset_timestamp_seconds.with_label_values(&[service])
+                .set(unix_timestamp().as_secs_f64());
+            self.metrics.ticket_resets.with_label_values(&[service]).inc();
+
+            // tickets tracked before a renumbering or wraparound would later be matched by unrelated
+            // tickets with the same number, the next observation only serves as reference again
+            state.ticket_tracker.clear();
+            state.last_called_number = None;
+            state.last_queue_length = None;
+            state.daily = DailyExtremes::default();
+        }
 
         if ticket.0 == TicketType::None {
             // clean up ticket tracker after the numbers have reset
-            self.ticket_tracker.retain(|k, _| k.0 != expected_ticket_type);
-            *last_tracked_waiting_time = None;
-            return;
+            state.anomalies = Anomalies::default();
+            state.last_progress = None;
+            state.ticket_tracker.clear();
+            state.last_tracked_waiting_time = None;
+            state.last_called_number = None;
+            state.last_queue_length = None;
+            return None;
         }
-        if ticket.0 != expected_ticket_type {
+        if *state.ticket_type.get_or_insert(ticket.0) != ticket.0 {
             // ignore foreign tickets
-            return;
+            return state.last_tracked_waiting_time;
         }
 
+        // count called tickets and arrivals, the first observation after startup or a reset only
+        // serves as reference
+        let called = match state.last_called_number {
+            Some(last_called_number) if ticket.1 >= last_called_number => Some(ticket.1 - last_called_number),
+            _ => None,
+        };
+        if let Some(called) = called {
+            self.metrics.tickets_called.with_label_values(&[service])
+                .inc_by(called as u64);
+            state.departures.record(called);
+            state.served_last_hour.record(called);
+        }
+        if let (Some(called), Some(last_queue_length)) = (called, state.last_queue_length) {
+            state.arrivals.record((queue_length + called).saturating_sub(last_queue_length));
+        }
+
+        if called.is_none_or(|c| c > 0) || state.last_progress.is_none() {
+            state.last_progress = Some(Instant::now());
+        }
+        state.anomalies = Anomalies {
+            queue_spike: state.last_queue_length
+                .is_some_and(|l| queue_length > l + self.anomaly_thresholds.queue_spike),
+            implausible_jump: called.is_some_and(|c| c > self.anomaly_thresholds.max_jump),
+        };
+        state.last_called_number = Some(ticket.1);
+        state.last_queue_length = Some(queue_length);
+        state.daily.max_queue_length = state.daily.max_queue_length.max(Some(queue_length));
+        state.daily.min_queue_length = Some(state.daily.min_queue_length.map_or(queue_length, |l| l.min(queue_length)));
+
         // get time for current ticket if applicable
-        let current_tracked = self.ticket_tracker.remove(&ticket)
-            .map(|i| Instant::now() - i);
+        let current_tracked = state.ticket_tracker.remove(&ticket);
 
-        if current_tracked.is_some() {
-            *last_tracked_waiting_time = current_tracked;
+        if let Some(tracked) = current_tracked {
+            let waiting_time = tracked.since.elapsed();
+            state.last_tracked_waiting_time = Some(waiting_time);
+            self.metrics.observe_waiting_time(service, waiting_time);
+            state.daily.max_wait = state.daily.max_wait.max(Some(waiting_time));
+            state.daily.min_wait = Some(state.daily.min_wait.map_or(waiting_time, |w| w.min(waiting_time)));
+            let secs = waiting_time.as_secs_f64();
+            state.smoothed_waiting_time = Some(match state.smoothed_waiting_time {
+                Some(smoothed) => self.smoothing_factor * secs + (1.0 - self.smoothing_factor) * smoothed,
+                None => secs,
+            });
+            self.metrics.estimate_error_seconds.with_label_values(&[service])
+                .set((tracked.waiting_time_estimation * 60) as f64 - waiting_time.as_secs_f64());
         } else if queue_length == 0 {
-            *last_tracked_waiting_time = Some(Duration::new(0, 0))
+            state.last_tracked_waiting_time = Some(Duration::new(0, 0))
         }
 
         if queue_length > 0 {
             let new_ticket = Ticket(ticket.0, ticket.1 + queue_length);
-            self.ticket_tracker.entry(new_ticket).or_insert_with(|| Instant::now());
+            state.ticket_tracker.entry(new_ticket).or_insert_with(|| TrackedTicket {
+                since: Instant::now(),
+                waiting_time_estimation,
+            });
+        }
+
+        let evicted = state.evict(self.max_ticket_age, self.max_tracked_tickets);
+        if evicted > 0 {
+            self.metrics.evicted_tickets.with_label_values(&[service]).inc_by(evicted as u64);
+        }
+
+        state.last_tracked_waiting_time
+    }
+}
+
+impl QueueState {
+    fn new(service_rate_window: Duration) -> Self {
+        QueueState {
+            ticket_type: None,
+            ticket_tracker: HashMap::new(),
+            last_tracked_waiting_time: None,
+            smoothed_waiting_time: None,
+            last_called_number: None,
+            last_queue_length: None,
+            departures: RateWindow::new(service_rate_window),
+            arrivals: RateWindow::new(service_rate_window),
+            served_last_hour: RateWindow::new(SERVED_TICKETS_WINDOW),
+            daily: DailyExtremes::default(),
+            last_progress: None,
+            anomalies: Anomalies::default(),
+        }
+    }
+
+    /// Drop tracked tickets older than `max_age`, then the oldest ones beyond `max_tickets`
+    ///
+    /// Returns the number of evicted tickets.
+    fn evict(&mut self, max_age: Duration, max_tickets: usize) -> usize {
+        let before = self.ticket_tracker.len();
+        self.ticket_tracker.retain(|_, t| t.since.elapsed() <= max_age);
+
+        let excess = self.ticket_tracker.len().saturating_sub(max_tickets);
+        if excess > 0 {
+            let mut tickets: Vec<_> = self.ticket_tracker.iter()
+                .map(|(ticket, t)| (t.since, *ticket))
+                .collect();
+            tickets.sort_unstable_by_key(|(since, _)| *since);
+            for (_, ticket) in tickets.into_iter().take(excess) {
+                self.ticket_tracker.remove(&ticket);
+            }
+        }
+
+        before - self.ticket_tracker.len()
+    }
+
+    /// Tickets called per minute over the [service rate window](Config::service_rate_window)
+    fn service_rate(&self) -> f64 {
+        self.departures.per_minute()
+    }
+
+    /// Estimate the waiting time via Little's law, from the queue length and the service rate
+    ///
+    /// No estimate is possible if no tickets have been called recently.
+    fn littles_law_estimate(&self, queue_length: usize) -> Option<Duration> {
+        let service_rate = self.service_rate();
+        (service_rate > 0.0)
+            .then(|| Duration::from_secs_f64(queue_length as f64 / service_rate * 60.0))
+    }
+
+    /// Estimate the waiting time for a ticket drawn right now
+    ///
+    /// Averages the [Little's law estimate](QueueState::littles_law_estimate) with the
+    /// [smoothed waiting time](QueueState::smoothed_waiting_time) of recently called tickets,
+    /// using whichever is available if only one is.
+    fn new_ticket_estimate(&self, queue_length: usize) -> Option<Duration> {
+        if queue_length == 0 {
+            return Some(Duration::ZERO);
+        }
+        let littles_law = self.littles_law_estimate(queue_length).map(|d| d.as_secs_f64());
+        let secs = match (littles_law, self.smoothed_waiting_time) {
+            (Some(l), Some(s)) => (l + s) / 2.0,
+            (Some(estimate), None) | (None, Some(estimate)) => estimate,
+            (None, None) => return None,
+        };
+        Some(Duration::from_secs_f64(secs))
+    }
+}
+
+impl ScrapeError {
+    /// All values of [ScrapeError::reason]
+    const REASONS: [&'static str; 4] = ["http", "parse", "not_enough_blocks", "throttled"];
+
+    /// Category of the error as used in the `reason` label
+    fn reason(&self) -> &'static str {
+        match self {
+            ScrapeError::Http(_) => Self::REASONS[0],
+            ScrapeError::Parse(_) => Self::REASONS[1],
+            ScrapeError::NotEnoughBlocks => Self::REASONS[2],
+            ScrapeError::Throttled(_) => Self::REASONS[3],
+        }
+    }
+}
+
+impl Display for ScrapeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScrapeError::Http(e) => write!(f, "cannot fetch page: {}", e),
+            ScrapeError::Parse(e) => write!(f, "cannot parse page: {}", e),
+            ScrapeError::NotEnoughBlocks => write!(f, "no data blocks found"),
+            ScrapeError::Throttled(d) => write!(f, "upstream is rate limiting requests for {}s", d.as_secs()),
+        }
+    }
+}
+
+impl ParseError {
+    fn new(block: Option<usize>, selector: &str, message: String, raw: Option<String>) -> Self {
+        ParseError { block, selector: selector.to_owned(), message, raw }
+    }
+}
+
+impl From<ParseError> for ScrapeError {
+    fn from(e: ParseError) -> Self {
+        ScrapeError::Parse(e)
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)?;
+        if let Some(block) = self.block {
+            write!(f, " in block {}", block)?;
+        }
+        write!(f, " (selector '{}')", self.selector)?;
+        if let Some(raw) = &self.raw {
+            write!(f, ": '{}'", raw)?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Ticket {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0 {
+            TicketType::Letter(_) => write!(f, "{}{:03}", self.0, self.1),
+            TicketType::None => write!(f, "{}", self.0),
         }
     }
 }
 
 impl Display for TicketType {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        use TicketType::*;
         match self {
-            B => write!(f, "B"),
-            F => write!(f, "F"),
-            None => write!(f, "N/A"),
+            TicketType::Letter(c) => write!(f, "{}", c),
+            TicketType::None => write!(f, "N/A"),
         }
     }
 }
 
 
+/// Current time relative to [UNIX_EPOCH]
+fn unix_timestamp() -> Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::new(0, 0))
+}
+
+/// Compare two byte strings in constant time, to not leak secrets through timing
+#[cfg(feature = "sqlite")]
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Parse a point in time given either in seconds since the epoch or in RFC 3339 format
+fn parse_timestamp(s: &str) -> Option<f64> {
+    s.parse().ok()
+        .or_else(|| chrono::DateTime::parse_from_rfc3339(s).ok().map(|t| t.timestamp_millis() as f64 / 1000.0))
+}
+
+/// Range of time given by the `from` and `to` parameters of a query string, starting at
+/// `default_from` and unbounded towards the future if unset
+///
+/// Returns [None] if one of the parameters cannot be parsed.
+fn time_range(query: &str, default_from: f64) -> Option<(f64, f64)> {
+    let params: HashMap<_, _> = form_urlencoded::parse(query.as_bytes())
+        .into_owned()
+        .collect();
+    let from = params.get("from").map(|t| parse_timestamp(t)).unwrap_or(Some(default_from))?;
+    let to = params.get("to").map(|t| parse_timestamp(t)).unwrap_or(Some(f64::INFINITY))?;
+    Some((from, to))
+}
+
+/// Scrape once and print the metrics in text format to stdout, also pushing them to the
+/// [Pushgateway](Config::pushgateway) if configured
+///
+/// Returns whether scraping and pushing succeeded.
+fn print_once(config: &Config) -> Result<bool, String> {
+    let mut scraper = Scraper::open(config)?;
+    let families = scraper.metrics(true);
+    print!("{}", metrics::encode(families.clone(), &[]));
+    let mut success = scraper.cache.is_some();
+
+    // failed scrapes are pushed as well, so that `erth_up` reflects them
+    if let Some(pushgateway) = &config.pushgateway {
+        let frame = scraper.cache.as_ref()
+            .map(HistoryFrame::from)
+            .unwrap_or_else(|| HistoryFrame { timestamp: unix_timestamp().as_secs_f64(), queues: BTreeMap::new() });
+        let mut pushgateway = Pushgateway::new(config, pushgateway);
+        if let Err(e) = pushgateway.push(&frame, &families) {
+            eprintln!("Error: cannot push to {}: {}", pushgateway.name(), e);
+            success = false;
+        }
+    }
+    Ok(success)
+}
 
 fn main() {
-    let mut server = Server::init("localhost:12080").unwrap();
+    let args = Args::parse_from(munin::plugin_args(std::env::args_os().collect()));
+    let mut config = match &args.config {
+        Some(path) => Config::load(path).unwrap_or_else(|e| {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }),
+        None => Config::default(),
+    };
+    if let Some(source) = args.source {
+        config.url = source;
+    }
+
+    if let Some(command) = args.command {
+        if let Err(e) = command.run(&config) {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+        return;
+    }
+
+    if args.once {
+        match print_once(&config) {
+            Ok(success) => process::exit(if success { 0 } else { 1 }),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                process::exit(1);
+            },
+        }
+    }
+
+    if let Some(dir) = args.textfile_dir {
+        if let Err(e) = textfile::write(&config, &dir) {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+        return;
+    }
+
+    // the daemon has to be started before any threads, including those of the HTTP clients
+    #[cfg(unix)]
+    if args.daemonize {
+        let log = match (args.log_file, args.syslog) {
+            (Some(path), _) => daemon::Log::File(path),
+            (None, true) => daemon::Log::Syslog,
+            (None, false) => daemon::Log::Discard,
+        };
+        if let Err(e) = daemon::daemonize(args.pid_file.as_deref(), log) {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+    }
+
+    let mut server = Server::init(config).unwrap_or_else(|e| {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    });
     server.run();
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tickets() {
+        assert_eq!(Ticket::parse("B123"), Ok(Ticket(TicketType::Letter('B'), 123)));
+        assert_eq!(Ticket::parse("F007"), Ok(Ticket(TicketType::Letter('F'), 7)));
+        assert_eq!(Ticket::parse("---"), Ok(Ticket(TicketType::None, 0)));
+        assert_eq!(Ticket::parse(""), Ok(Ticket(TicketType::None, 0)));
+        assert_eq!(Ticket::parse("B12x"), Err(()));
+        assert_eq!(Ticket(TicketType::Letter('F'), 7).to_string(), "F007");
+    }
+
+    #[test]
+    fn time_ranges() {
+        assert_eq!(time_range("", 60.0), Some((60.0, f64::INFINITY)));
+        assert_eq!(time_range("from=120&to=2023-11-14T22:13:20Z", 60.0), Some((120.0, 1700000000.0)));
+        assert_eq!(time_range("to=120.5", 60.0), Some((60.0, 120.5)));
+        assert_eq!(time_range("from=yesterday", 60.0), None);
+    }
+}
@@ -0,0 +1,90 @@
+//! Queue updates and notifications in a Matrix room
+use prometheus::proto::MetricFamily;
+use reqwest::Url;
+use serde::Serialize;
+
+use crate::config::{Config, MatrixConfig};
+use crate::history::HistoryFrame;
+use crate::notify::{self, Event, Notifier};
+use crate::push::{self, Sink};
+use crate::unix_timestamp;
+
+
+/// Posts messages to a Matrix room
+///
+/// As [Notifier] it posts crossed thresholds, as [Sink] it posts the state of the queues whenever
+/// it changed.
+#[derive(Clone)]
+pub struct Matrix {
+    config: MatrixConfig,
+    client: reqwest::blocking::Client,
+
+    /// Last posted state of the queues
+    last_status: Option<String>,
+}
+
+/// Content of an `m.room.message` event
+#[derive(Serialize)]
+struct RoomMessage<'a> {
+    msgtype: &'static str,
+    body: &'a str,
+}
+
+
+impl Matrix {
+    pub fn new(config: &Config, matrix: &MatrixConfig) -> Self {
+        Matrix {
+            config: matrix.clone(),
+            client: push::client(config),
+            last_status: None,
+        }
+    }
+
+    /// Post a notice to the room
+    fn send(&self, text: &str) -> Result<(), String> {
+        let transaction = format!("{}-{:08x}", unix_timestamp().as_millis(), rand::random::<u32>());
+
+        let mut url = Url::parse(&self.config.homeserver)
+            .map_err(|e| e.to_string())?;
+        url.path_segments_mut()
+            .map_err(|_| String::from("invalid homeserver url"))?
+            .pop_if_empty()
+            .extend(["_matrix", "client", "v3", "rooms", &self.config.room_id, "send", "m.room.message", &transaction]);
+        let body = serde_json::to_vec(&RoomMessage { msgtype: "m.notice", body: text })
+            .map_err(|e| e.to_string())?;
+
+        let response = self.client.put(url)
+            .bearer_auth(&self.config.access_token)
+            .header("Content-Type", "application/json")
+            .body(body)
+            .send()
+            .map_err(|e| e.to_string())?;
+        push::check_response(response)
+    }
+}
+
+impl Notifier for Matrix {
+    fn name(&self) -> &str {
+        "Matrix"
+    }
+
+    fn notify(&mut self, event: &Event) -> Result<(), String> {
+        self.send(&format!("{}\n{}", event.title(), event.message()))
+    }
+}
+
+impl Sink for Matrix {
+    fn name(&self) -> &str {
+        "Matrix"
+    }
+
+    fn push(&mut self, frame: &HistoryFrame, _families: &[MetricFamily]) -> Result<(), String> {
+        let status = notify::status(frame);
+        if self.last_status.as_ref() == Some(&status) {
+            return Ok(());
+        }
+        self.send(&status)?;
+        self.last_status = Some(status);
+        Ok(())
+    }
+}
@@ -0,0 +1,555 @@
+//! Metric families exported by the [Server](crate::Server) and its [scrapers](crate::Scraper)
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use prometheus::core::{Collector, Desc};
+use prometheus::proto::{LabelPair, Metric, MetricFamily, MetricType, Quantile, Summary};
+use prometheus::{Encoder, Gauge, GaugeVec, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts,
+    ProtobufEncoder, Registry, TextEncoder};
+
+use crate::config::Config;
+
+
+/// Bucket boundaries for tracked waiting times in seconds
+const WAITING_TIME_BUCKETS: [f64; 11] = [
+    60.0, 300.0, 600.0, 900.0, 1200.0, 1800.0, 2700.0, 3600.0, 5400.0, 7200.0, 10800.0,
+];
+
+/// Name of the tracked waiting times, exported either as histogram or summary
+const TRACKED_WAITING_TIME_NAME: &str = "erth_tracked_waiting_time_seconds";
+/// Help text of the tracked waiting times
+const TRACKED_WAITING_TIME_HELP: &str = "Waiting times of called tickets as tracked by the exporter";
+/// Quantiles of the [waiting time summary](WaitingTimeSummary)
+const WAITING_TIME_QUANTILES: [f64; 3] = [0.5, 0.9, 0.99];
+
+
+/// Metrics describing the state of a single [Scraper](crate::Scraper)
+pub struct ScraperMetrics {
+    /// Metrics that are always exported
+    registry: Registry,
+
+    /// Metrics derived from the current [DataFrame](crate::DataFrame), only exported if it is available
+    frame_registry: Registry,
+
+    /// Frame metrics using the names of previous versions, which are not in base units
+    legacy_registry: Option<Registry>,
+
+    /// Attach the creation time of the data frame to its samples
+    sample_timestamps: bool,
+
+    pub up: IntGauge,
+    pub scrape_errors: IntCounterVec,
+    pub last_scrape_error_info: IntGaugeVec,
+    pub last_parse_error_info: IntGaugeVec,
+    pub cache_hits: IntCounter,
+    pub cache_misses: IntCounter,
+    pub upstream_not_modified: IntCounter,
+    pub upstream_throttled: IntGauge,
+    pub circuit_open: IntGauge,
+    /// Only observed and exported if the [summary](ScraperMetrics::tracked_waiting_time_summary)
+    /// is not configured
+    tracked_waiting_time_seconds: HistogramVec,
+
+    /// Exported instead of the [histogram](ScraperMetrics::tracked_waiting_time_seconds) if
+    /// [configured](Config::waiting_time_summary)
+    tracked_waiting_time_summary: Option<WaitingTimeSummary>,
+    pub tickets_called: IntCounterVec,
+    pub evicted_tickets: IntCounterVec,
+    pub ticket_resets: IntCounterVec,
+    pub estimate_error_seconds: GaugeVec,
+    pub last_reset_timestamp_seconds: GaugeVec,
+
+    pub queue_scrape_success: IntGaugeVec,
+    pub people_waiting: IntGaugeVec,
+    pub last_called_ticket: IntGaugeVec,
+    pub waiting_time_seconds: GaugeVec,
+    pub last_tracked_waiting_time_seconds: GaugeVec,
+    pub smoothed_waiting_time_seconds: GaugeVec,
+    pub service_rate: GaugeVec,
+    pub arrival_rate: GaugeVec,
+    pub tickets_served_per_hour: IntGaugeVec,
+    pub anomaly: IntGaugeVec,
+    pub daily_max_wait_seconds: GaugeVec,
+    pub daily_min_wait_seconds: GaugeVec,
+    pub daily_max_queue_length: IntGaugeVec,
+    pub daily_min_queue_length: IntGaugeVec,
+    pub estimated_wait_littles_law_seconds: GaugeVec,
+    pub new_ticket_wait_estimate_seconds: GaugeVec,
+    pub cached: IntGauge,
+    pub cache_age_seconds: Gauge,
+    pub data_stale: IntGauge,
+    pub tracked_tickets: IntGauge,
+    pub scrape_duration_seconds: Gauge,
+    pub scrape_timestamp_seconds: Gauge,
+
+    pub waiting_time: IntGaugeVec,
+    pub tracked_waiting_time: IntGaugeVec,
+    pub scrape_duration: IntGauge,
+    pub scrape_timestamp: IntGauge,
+}
+
+/// Summary of tracked waiting times per service, with quantiles over a sliding time window
+#[derive(Clone)]
+pub struct WaitingTimeSummary {
+    desc: Desc,
+
+    /// Time span the quantiles are calculated over
+    window: Duration,
+
+    /// Observations per service within the window, along with the total count and sum
+    services: Arc<Mutex<HashMap<String, ServiceObservations>>>,
+}
+
+#[derive(Default)]
+struct ServiceObservations {
+    recent: VecDeque<(Instant, f64)>,
+    count: u64,
+    sum: f64,
+}
+
+/// Metrics describing the exporter itself
+pub struct ServerMetrics {
+    registry: Registry,
+
+    pub http_requests: IntCounterVec,
+
+    /// Only available if [opening hours](Config::opening_hours) are configured
+    pub office_open: Option<IntGauge>,
+
+    /// Time since the exporter was started, as [ServerMetrics::started]
+    uptime_seconds: Gauge,
+    started: Instant,
+}
+
+
+impl ScraperMetrics {
+    pub fn new(config: &Config) -> Self {
+        let registry = new_registry(config);
+        let frame_registry = new_registry(config);
+        let legacy_registry = new_registry(config);
+
+        let tracked_waiting_time_seconds = HistogramVec::new(
+            HistogramOpts::new(TRACKED_WAITING_TIME_NAME, TRACKED_WAITING_TIME_HELP)
+                .buckets(WAITING_TIME_BUCKETS.to_vec()),
+            &["service"]);
+        let tracked_waiting_time_summary = config.waiting_time_summary
+            .then(|| WaitingTimeSummary::new(Duration::from_secs(config.quantile_window)));
+        let tracked_waiting_time_seconds = match &tracked_waiting_time_summary {
+            Some(summary) => {
+                register(&registry, Ok(summary.clone()));
+                tracked_waiting_time_seconds.expect("Metric definitions must be valid")
+            },
+            None => register(&registry, tracked_waiting_time_seconds),
+        };
+
+        ScraperMetrics {
+            up: register(&registry, IntGauge::new(
+                "erth_up", "Whether the last scrape of the town-hall website succeeded")),
+            scrape_errors: register(&registry, IntCounterVec::new(
+                Opts::new("erth_scrape_errors_total", "Number of failed scrapes of the town-hall website"),
+                &["reason"])),
+            last_scrape_error_info: register(&registry, IntGaugeVec::new(
+                Opts::new("erth_last_scrape_error_info", "Information on the most recent failed scrape"),
+                &["reason", "message"])),
+            last_parse_error_info: register(&registry, IntGaugeVec::new(
+                Opts::new("erth_last_parse_error_info",
+                    "Location and raw text of the most recent content that could not be parsed"),
+                &["block", "selector", "raw"])),
+            cache_hits: register(&registry, IntCounter::new(
+                "erth_cache_hits_total", "Number of requests served from cache")),
+            cache_misses: register(&registry, IntCounter::new(
+                "erth_cache_misses_total", "Number of requests that required scraping the town-hall website")),
+            upstream_not_modified: register(&registry, IntCounter::new(
+                "erth_upstream_not_modified_total", "Number of fetches where the town-hall website was unchanged")),
+            upstream_throttled: register(&registry, IntGauge::new(
+                "erth_upstream_throttled", "Whether the town-hall website is rate limiting the exporter")),
+            circuit_open: register(&registry, IntGauge::new(
+                "erth_circuit_open", "Whether scraping is paused after repeated failures")),
+            tracked_waiting_time_seconds,
+            tracked_waiting_time_summary,
+            tickets_called: register(&registry, IntCounterVec::new(
+                Opts::new("erth_tickets_called_total", "Number of tickets called"),
+                &["service"])),
+            evicted_tickets: register(&registry, IntCounterVec::new(
+                Opts::new("erth_evicted_tickets_total",
+                    "Number of tracked tickets dropped because they were too old or too many"),
+                &["service"])),
+            ticket_resets: register(&registry, IntCounterVec::new(
+                Opts::new("erth_ticket_resets_total", "Number of times the ticket numbers were reset or wrapped around"),
+                &["service"])),
+            estimate_error_seconds: register(&registry, GaugeVec::new(
+                Opts::new("erth_estimate_error_seconds",
+                    "Waiting time estimation of the town hall minus the tracked waiting time of the last called ticket"),
+                &["service"])),
+            last_reset_timestamp_seconds: register(&registry, GaugeVec::new(
+                Opts::new("erth_last_reset_timestamp_seconds", "Time the ticket numbers were last reset"),
+                &["service"])),
+
+            queue_scrape_success: register(&frame_registry, IntGaugeVec::new(
+                Opts::new("erth_queue_scrape_success", "Whether the information on a queue could be parsed"),
+                &["service"])),
+            people_waiting: register(&frame_registry, IntGaugeVec::new(
+                Opts::new("erth_people_waiting", "Number of people waiting in line"),
+                &["service"])),
+            last_called_ticket: register(&frame_registry, IntGaugeVec::new(
+                Opts::new("erth_last_called_ticket", "Number of the last called ticket"),
+                &["service", "type"])),
+            waiting_time_seconds: register(&frame_registry, GaugeVec::new(
+                Opts::new("erth_waiting_time_seconds", "Waiting time estimation of the town hall"),
+                &["service"])),
+            last_tracked_waiting_time_seconds: register(&frame_registry, GaugeVec::new(
+                Opts::new("erth_last_tracked_waiting_time_seconds",
+                    "Waiting time of the last called ticket as tracked by the exporter"),
+                &["service"])),
+            smoothed_waiting_time_seconds: register(&frame_registry, GaugeVec::new(
+                Opts::new("erth_smoothed_waiting_time_seconds",
+                    "Exponentially weighted moving average of the tracked waiting times"),
+                &["service"])),
+            service_rate: register(&frame_registry, GaugeVec::new(
+                Opts::new("erth_service_rate_per_minute", "Tickets called per minute over the service rate window"),
+                &["service"])),
+            arrival_rate: register(&frame_registry, GaugeVec::new(
+                Opts::new("erth_arrival_rate_per_minute",
+                    "People joining the queue per minute over the service rate window"),
+                &["service"])),
+            tickets_served_per_hour: register(&frame_registry, IntGaugeVec::new(
+                Opts::new("erth_tickets_served_per_hour", "Tickets called during the last hour"),
+                &["service"])),
+            anomaly: register(&frame_registry, IntGaugeVec::new(
+                Opts::new("erth_anomaly", "Whether an anomaly of the given type was detected in the queue"),
+                &["service", "type"])),
+            daily_max_wait_seconds: register(&frame_registry, GaugeVec::new(
+                Opts::new("erth_daily_max_wait_seconds",
+                    "Longest tracked waiting time since the ticket numbers were last reset"),
+                &["service"])),
+            daily_min_wait_seconds: register(&frame_registry, GaugeVec::new(
+                Opts::new("erth_daily_min_wait_seconds",
+                    "Shortest tracked waiting time since the ticket numbers were last reset"),
+                &["service"])),
+            daily_max_queue_length: register(&frame_registry, IntGaugeVec::new(
+                Opts::new("erth_daily_max_queue_length",
+                    "Longest queue since the ticket numbers were last reset"),
+                &["service"])),
+            daily_min_queue_length: register(&frame_registry, IntGaugeVec::new(
+                Opts::new("erth_daily_min_queue_length",
+                    "Shortest queue since the ticket numbers were last reset"),
+                &["service"])),
+            estimated_wait_littles_law_seconds: register(&frame_registry, GaugeVec::new(
+                Opts::new("erth_estimated_wait_littles_law_seconds",
+                    "Waiting time estimated from queue length and service rate via Little's law"),
+                &["service"])),
+            new_ticket_wait_estimate_seconds: register(&frame_registry, GaugeVec::new(
+                Opts::new("erth_new_ticket_wait_estimate_seconds",
+                    "Estimated waiting time for a ticket drawn right now"),
+                &["service"])),
+            cached: register(&frame_registry, IntGauge::new(
+                "erth_cached", "Whether the data was served from cache")),
+            cache_age_seconds: register(&frame_registry, Gauge::new(
+                "erth_cache_age_seconds", "Age of the served data")),
+            data_stale: register(&frame_registry, IntGauge::new(
+                "erth_data_stale", "Whether expired data is served because scraping failed")),
+            tracked_tickets: register(&frame_registry, IntGauge::new(
+                "erth_tracked_tickets", "Number of tickets currently tracked to determine their waiting time")),
+            scrape_duration_seconds: register(&frame_registry, Gauge::new(
+                "erth_scrape_duration_seconds", "Duration of the last scrape of the town-hall website")),
+            scrape_timestamp_seconds: register(&frame_registry, Gauge::new(
+                "erth_scrape_timestamp_seconds", "Time of the last scrape of the town-hall website")),
+
+            waiting_time: register(&legacy_registry, IntGaugeVec::new(
+                Opts::new("erth_waiting_time", "Waiting time estimation of the town hall in minutes"),
+                &["service"])),
+            tracked_waiting_time: register(&legacy_registry, IntGaugeVec::new(
+                Opts::new("erth_tracked_waiting_time",
+                    "Waiting time of the last called ticket as tracked by the exporter in minutes"),
+                &["service"])),
+            scrape_duration: register(&legacy_registry, IntGauge::new(
+                "erth_scrape_duration", "Duration of the last scrape of the town-hall website in milliseconds")),
+            scrape_timestamp: register(&legacy_registry, IntGauge::new(
+                "erth_scrape_timestamp",
+                "Time of the last scrape of the town-hall website in milliseconds since the epoch")),
+
+            registry,
+            frame_registry,
+            legacy_registry: config.legacy_metric_names.then_some(legacy_registry),
+            sample_timestamps: config.sample_timestamps,
+        }
+    }
+
+    /// Collect the metric families
+    ///
+    /// The ones derived from the current data frame are only included if its creation timestamp
+    /// is passed as `frame_created`.
+    pub fn gather(&self, frame_created: Option<Duration>) -> Vec<MetricFamily> {
+        let mut families = self.registry.gather();
+        if let Some(frame_created) = frame_created {
+            let mut frame_families = self.frame_registry.gather();
+            if let Some(legacy_registry) = &self.legacy_registry {
+                frame_families.extend(legacy_registry.gather());
+            }
+
+            if self.sample_timestamps {
+                for metric in frame_families.iter_mut().flat_map(|f| f.mut_metric()) {
+                    metric.set_timestamp_ms(frame_created.as_millis() as i64);
+                }
+            }
+
+            families.extend(frame_families);
+        }
+        families
+    }
+}
+
+impl ScraperMetrics {
+    /// Record the tracked waiting time of a called ticket
+    pub fn observe_waiting_time(&self, service: &str, waiting_time: Duration) {
+        match &self.tracked_waiting_time_summary {
+            Some(summary) => summary.observe(service, waiting_time.as_secs_f64()),
+            None => self.tracked_waiting_time_seconds.with_label_values(&[service])
+                .observe(waiting_time.as_secs_f64()),
+        }
+    }
+}
+
+impl WaitingTimeSummary {
+    fn new(window: Duration) -> Self {
+        WaitingTimeSummary {
+            desc: Desc::new(TRACKED_WAITING_TIME_NAME.to_owned(), TRACKED_WAITING_TIME_HELP.to_owned(),
+                vec![String::from("service")], HashMap::new())
+                .expect("Metric definitions must be valid"),
+            window,
+            services: Arc::default(),
+        }
+    }
+
+    fn observe(&self, service: &str, value: f64) {
+        let mut services = self.services.lock().unwrap();
+        let observations = services.entry(service.to_owned()).or_default();
+        observations.recent.push_back((Instant::now(), value));
+        observations.count += 1;
+        observations.sum += value;
+        // also pruned here, in case the metrics are rarely collected
+        observations.prune(self.window);
+    }
+}
+
+impl ServiceObservations {
+    /// Drop the observations older than the window
+    fn prune(&mut self, window: Duration) {
+        while self.recent.front().is_some_and(|(i, _)| i.elapsed() > window) {
+            self.recent.pop_front();
+        }
+    }
+}
+
+impl Collector for WaitingTimeSummary {
+    fn desc(&self) -> Vec<&Desc> {
+        vec![&self.desc]
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let mut services = self.services.lock().unwrap();
+        let mut metrics = Vec::new();
+        for (service, observations) in services.iter_mut() {
+            observations.prune(self.window);
+
+            let mut values: Vec<_> = observations.recent.iter().map(|(_, v)| *v).collect();
+            values.sort_by(f64::total_cmp);
+            let quantiles = WAITING_TIME_QUANTILES.iter()
+                .map(|q| {
+                    // nearest-rank method
+                    let rank = ((q * values.len() as f64).ceil() as usize).saturating_sub(1);
+                    let mut quantile = Quantile::default();
+                    quantile.set_quantile(*q);
+                    quantile.set_value(values.get(rank).copied().unwrap_or(f64::NAN));
+                    quantile
+                })
+                .collect();
+
+            let mut summary = Summary::default();
+            summary.set_sample_count(observations.count);
+            summary.set_sample_sum(observations.sum);
+            summary.set_quantile(quantiles);
+
+            let mut label = LabelPair::default();
+            label.set_name(String::from("service"));
+            label.set_value(service.clone());
+            let mut metric = Metric::from_label(vec![label]);
+            metric.set_summary(summary);
+            metrics.push(metric);
+        }
+
+        let mut family = MetricFamily::default();
+        family.set_name(self.desc.fq_name.clone());
+        family.set_help(self.desc.help.clone());
+        family.set_field_type(MetricType::SUMMARY);
+        family.set_metric(metrics);
+        vec![family]
+    }
+}
+
+impl ServerMetrics {
+    pub fn new(config: &Config) -> Self {
+        let registry = new_registry(config);
+
+        let build_info = register(&registry, IntGaugeVec::new(
+            Opts::new("erth_exporter_build_info", "Version information of the exporter"),
+            &["version", "revision", "rustc"]));
+        build_info.with_label_values(&[
+            env!("CARGO_PKG_VERSION"), env!("ERTH_REVISION"), env!("ERTH_RUSTC_VERSION"),
+        ]).set(1);
+
+        // standard process metrics like memory usage and cpu time
+        #[cfg(target_os = "linux")]
+        registry.register(Box::new(prometheus::process_collector::ProcessCollector::for_self()))
+            .expect("Metric names must be unique");
+
+        ServerMetrics {
+            http_requests: register(&registry, IntCounterVec::new(
+                Opts::new("erth_http_requests_total", "Number of HTTP requests handled by the exporter"),
+                &["path"])),
+            office_open: config.opening_hours.is_some().then(|| register(&registry, IntGauge::new(
+                "erth_office_open", "Whether the town hall is open according to the configured opening hours"))),
+            uptime_seconds: register(&registry, Gauge::new(
+                "process_uptime_seconds", "Time since the exporter was started")),
+            started: Instant::now(),
+            registry,
+        }
+    }
+
+    pub fn gather(&self) -> Vec<MetricFamily> {
+        self.uptime_seconds.set(self.started.elapsed().as_secs_f64());
+        self.registry.gather()
+    }
+}
+
+
+/// Create a registry attaching the configured [constant labels](Config::labels) to its metrics
+fn new_registry(config: &Config) -> Registry {
+    let labels = (!config.labels.is_empty()).then(|| config.labels.clone());
+    Registry::new_custom(None, labels)
+        .expect("Constant labels are validated with the config")
+}
+
+/// Register a metric, returning a handle to it
+///
+/// Panics if the metric cannot be created or collides with an already registered one, as the
+/// metric definitions are static.
+fn register<T: Collector + Clone + 'static>(registry: &Registry, metric: prometheus::Result<T>) -> T {
+    let metric = metric.expect("Metric definitions must be valid");
+    registry.register(Box::new(metric.clone()))
+        .expect("Metric names must be unique");
+    metric
+}
+
+/// Encode metric families in the [Prometheus text format](https://prometheus.io/docs/instrumenting/exposition_formats/)
+///
+/// Only the families listed in `names` are included, an empty list disables filtering.
+pub fn encode(mut families: Vec<MetricFamily>, names: &[String]) -> String {
+    if !names.is_empty() {
+        families.retain(|f| names.iter().any(|n| n == f.name()));
+    }
+
+    TextEncoder::new().encode_to_string(&families)
+        .unwrap_or_else(|e| {
+            eprintln!("Error: {}", e);
+            String::new()
+        })
+}
+
+/// Encode metric families in the delimited protobuf exposition format
+///
+/// Only the families listed in `names` are included, an empty list disables filtering.
+pub fn encode_protobuf(mut families: Vec<MetricFamily>, names: &[String]) -> Vec<u8> {
+    if !names.is_empty() {
+        families.retain(|f| names.iter().any(|n| n == f.name()));
+    }
+
+    let mut buffer = Vec::new();
+    if let Err(e) = ProtobufEncoder::new().encode(&families, &mut buffer) {
+        eprintln!("Error: {}", e);
+        buffer.clear();
+    }
+    buffer
+}
+
+/// Whether a client prefers the protobuf exposition format over the text format according to its
+/// `Accept` header
+pub fn accepts_protobuf(accept: &str) -> bool {
+    let mut protobuf = 0.0;
+    let mut text = 0.0;
+    for media_range in accept.split(',') {
+        let mut params = media_range.split(';').map(str::trim);
+        let media_type = params.next().unwrap_or_default().to_ascii_lowercase();
+        let params: Vec<_> = params.collect();
+        let quality = params.iter()
+            .find_map(|p| p.strip_prefix("q="))
+            .and_then(|q| q.parse::<f64>().ok())
+            .unwrap_or(1.0);
+        match media_type.as_str() {
+            "application/vnd.google.protobuf" => {
+                let delimited = params.contains(&"proto=io.prometheus.client.MetricFamily")
+                    && params.contains(&"encoding=delimited");
+                if delimited {
+                    protobuf = f64::max(protobuf, quality);
+                }
+            },
+            "text/plain" | "text/*" | "*/*" => text = f64::max(text, quality),
+            _ => (),
+        }
+    }
+    protobuf > 0.0 && protobuf >= text
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summary_window() {
+        let summary = WaitingTimeSummary::new(Duration::from_millis(50));
+        summary.observe("citizen", 60.0);
+        summary.observe("citizen", 120.0);
+        std::thread::sleep(Duration::from_millis(100));
+        summary.observe("citizen", 600.0);
+        {
+            let services = summary.services.lock().unwrap();
+            let observations = &services["citizen"];
+            assert_eq!(observations.recent.len(), 1);
+            assert_eq!((observations.count, observations.sum), (3, 780.0));
+        }
+
+        std::thread::sleep(Duration::from_millis(100));
+        let families = summary.collect();
+        let summary = families[0].get_metric()[0].get_summary();
+        assert_eq!(summary.sample_count(), 3);
+        assert!(summary.get_quantile().iter().all(|q| q.value().is_nan()));
+    }
+
+    #[test]
+    fn summary_replaces_histogram() {
+        let config = Config { waiting_time_summary: true, ..Config::default() };
+        let metrics = ScraperMetrics::new(&config);
+        metrics.observe_waiting_time("citizen", Duration::from_secs(60));
+        assert_eq!(metrics.tracked_waiting_time_seconds.with_label_values(&["citizen"]).get_sample_count(), 0);
+        let families = metrics.gather(None);
+        let tracked = families.iter().find(|f| f.name() == TRACKED_WAITING_TIME_NAME).unwrap();
+        assert_eq!(tracked.get_field_type(), MetricType::SUMMARY);
+        assert_eq!(tracked.get_metric()[0].get_summary().sample_count(), 1);
+    }
+
+    #[test]
+    fn content_negotiation() {
+        // sent by Prometheus with protobuf scraping enabled
+        assert!(accepts_protobuf("application/vnd.google.protobuf;proto=io.prometheus.client.MetricFamily;\
+            encoding=delimited;q=0.7,text/plain;version=0.0.4;q=0.3,*/*;q=0.1"));
+        assert!(!accepts_protobuf("text/plain;version=0.0.4;q=1,*/*;q=0.1"));
+        assert!(!accepts_protobuf(""));
+        assert!(!accepts_protobuf("application/vnd.google.protobuf;proto=io.prometheus.client.MetricFamily;encoding=text"));
+        assert!(!accepts_protobuf("text/plain;q=0.9, application/vnd.google.protobuf;\
+            proto=io.prometheus.client.MetricFamily;encoding=delimited;q=0.5"));
+        assert!(!accepts_protobuf("application/vnd.google.protobuf;proto=io.prometheus.client.MetricFamily;\
+            encoding=delimited;q=0"));
+    }
+}
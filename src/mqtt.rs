@@ -0,0 +1,258 @@
+//! Publishing queue states to an MQTT broker, with Home Assistant discovery
+use std::collections::HashSet;
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use prometheus::proto::MetricFamily;
+use serde::Serialize;
+
+use crate::config::MqttConfig;
+use crate::history::{HistoryFrame, Sample};
+use crate::push::{self, Sink};
+
+
+/// Timeout for connecting to and communicating with the broker
+const MQTT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Keep alive interval announced to the broker in seconds
+const KEEP_ALIVE: u16 = 60;
+
+/// Sensors announced via Home Assistant discovery as key in the state, name and unit
+const SENSORS: &[(&str, &str, Option<&str>)] = &[
+    ("people_waiting", "People waiting", None),
+    ("last_called_ticket", "Last called ticket", None),
+    ("waiting_time_seconds", "Waiting time", Some("s")),
+    ("tracked_waiting_time_seconds", "Tracked waiting time", Some("s")),
+    ("new_ticket_wait_estimate_seconds", "New ticket wait estimate", Some("s")),
+];
+
+
+/// Publishes the state of each queue as retained JSON message to `<topic_prefix>/<service>`
+pub struct Mqtt {
+    config: MqttConfig,
+
+    /// Services for which discovery messages were already sent
+    discovered: HashSet<String>,
+}
+
+/// Message published to the state topic of a queue
+#[derive(Serialize)]
+struct QueueState<'a> {
+    #[serde(flatten)]
+    sample: &'a Sample,
+    new_ticket_wait_estimate_seconds: Option<f64>,
+}
+
+/// Home Assistant MQTT discovery config of a single sensor
+#[derive(Serialize)]
+struct Discovery<'a> {
+    name: &'a str,
+    unique_id: String,
+    object_id: String,
+    state_topic: String,
+    value_template: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unit_of_measurement: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    device_class: Option<&'a str>,
+    device: Device,
+}
+
+/// Device all sensors of a service are grouped under
+#[derive(Serialize)]
+struct Device {
+    identifiers: Vec<String>,
+    name: String,
+    manufacturer: &'static str,
+}
+
+
+impl Mqtt {
+    pub fn new(config: &MqttConfig) -> Self {
+        Mqtt {
+            config: config.clone(),
+            discovered: HashSet::new(),
+        }
+    }
+
+    /// Discovery messages of all sensors of a service as pairs of topic and payload
+    fn discovery(&self, service: &str) -> Vec<(String, String)> {
+        let node = push::sanitize(&self.config.topic_prefix, &[]);
+        let device = || Device {
+            identifiers: vec![format!("{}_{}", node, service)],
+            name: format!("Bürgeramt {}", service),
+            manufacturer: env!("CARGO_PKG_NAME"),
+        };
+
+        SENSORS.iter()
+            .map(|(key, name, unit)| {
+                let object_id = format!("{}_{}_{}", node, service, key);
+                let config = Discovery {
+                    name,
+                    unique_id: object_id.clone(),
+                    object_id: object_id.clone(),
+                    state_topic: self.state_topic(service),
+                    value_template: format!("{{{{ value_json.{} }}}}", key),
+                    unit_of_measurement: *unit,
+                    device_class: unit.map(|_| "duration"),
+                    device: device(),
+                };
+                let topic = format!("{}/sensor/{}/config", self.config.discovery_prefix, object_id);
+                (topic, serde_json::to_string(&config).unwrap_or_default())
+            })
+            .collect()
+    }
+
+    fn state_topic(&self, service: &str) -> String {
+        format!("{}/{}", self.config.topic_prefix, service)
+    }
+
+    /// Open a session with the broker
+    fn connect(&self) -> Result<TcpStream, String> {
+        let address = (self.config.host.as_str(), self.config.port).to_socket_addrs()
+            .map_err(|e| e.to_string())?
+            .next()
+            .ok_or_else(|| format!("cannot resolve {}", self.config.host))?;
+        let mut stream = TcpStream::connect_timeout(&address, MQTT_TIMEOUT)
+            .map_err(|e| e.to_string())?;
+        stream.set_read_timeout(Some(MQTT_TIMEOUT))
+            .and_then(|_| stream.set_write_timeout(Some(MQTT_TIMEOUT)))
+            .map_err(|e| e.to_string())?;
+
+        let mut flags = 0x02; // clean session
+        let mut payload = Vec::new();
+        encode_string(&mut payload, &self.config.client_id);
+        if let Some(username) = &self.config.username {
+            flags |= 0x80;
+            encode_string(&mut payload, username);
+            if let Some(password) = &self.config.password {
+                flags |= 0x40;
+                encode_string(&mut payload, password);
+            }
+        }
+        let mut body = Vec::new();
+        encode_string(&mut body, "MQTT");
+        body.push(0x04); // protocol level 3.1.1
+        body.push(flags);
+        body.extend_from_slice(&KEEP_ALIVE.to_be_bytes());
+        body.extend_from_slice(&payload);
+        write_packet(&mut stream, 0x10, &body)?;
+
+        let mut connack = [0; 4];
+        stream.read_exact(&mut connack)
+            .map_err(|e| format!("cannot read CONNACK: {}", e))?;
+        match connack {
+            [0x20, 0x02, _, 0x00] => Ok(stream),
+            [0x20, 0x02, _, 0x04] => Err(String::from("bad username or password")),
+            [0x20, 0x02, _, 0x05] => Err(String::from("not authorized")),
+            [0x20, 0x02, _, code] => Err(format!("connection refused with code {}", code)),
+            _ => Err(String::from("unexpected response from broker")),
+        }
+    }
+}
+
+impl Sink for Mqtt {
+    fn name(&self) -> &str {
+        "MQTT"
+    }
+
+    fn push(&mut self, frame: &HistoryFrame, families: &[MetricFamily]) -> Result<(), String> {
+        let mut stream = self.connect()?;
+
+        if self.config.discovery {
+            for service in frame.queues.keys() {
+                if !self.discovered.contains(service) {
+                    for (topic, payload) in self.discovery(service) {
+                        publish(&mut stream, &topic, payload.as_bytes())?;
+                    }
+                    self.discovered.insert(service.clone());
+                }
+            }
+        }
+
+        for (service, sample) in &frame.queues {
+            let state = QueueState {
+                sample,
+                new_ticket_wait_estimate_seconds: new_ticket_estimate(families, service),
+            };
+            let payload = serde_json::to_string(&state)
+                .map_err(|e| e.to_string())?;
+            publish(&mut stream, &self.state_topic(service), payload.as_bytes())?;
+        }
+
+        write_packet(&mut stream, 0xe0, &[])
+    }
+}
+
+
+/// Current wait estimate of a new ticket for a service, as exported on `/metrics`
+fn new_ticket_estimate(families: &[MetricFamily], service: &str) -> Option<f64> {
+    families.iter()
+        .find(|f| f.name() == "erth_new_ticket_wait_estimate_seconds")?
+        .get_metric().iter()
+        .find(|m| m.get_label().iter().any(|l| l.name() == "service" && l.value() == service))
+        .map(|m| m.get_gauge().value())
+}
+
+/// Send a retained message with QoS 0
+fn publish(stream: &mut TcpStream, topic: &str, payload: &[u8]) -> Result<(), String> {
+    let mut body = Vec::new();
+    encode_string(&mut body, topic);
+    body.extend_from_slice(payload);
+    write_packet(stream, 0x31, &body)
+}
+
+/// Send a control packet with the given fixed header byte
+fn write_packet(stream: &mut TcpStream, header: u8, body: &[u8]) -> Result<(), String> {
+    stream.write_all(&encode_packet(header, body))
+        .map_err(|e| e.to_string())
+}
+
+/// Encode a control packet with the given fixed header byte and the remaining length of the body
+fn encode_packet(header: u8, body: &[u8]) -> Vec<u8> {
+    let mut packet = vec![header];
+    let mut length = body.len();
+    loop {
+        let mut byte = (length % 128) as u8;
+        length /= 128;
+        if length > 0 {
+            byte |= 0x80;
+        }
+        packet.push(byte);
+        if length == 0 {
+            break;
+        }
+    }
+    packet.extend_from_slice(body);
+    packet
+}
+
+/// Encode a length-prefixed UTF-8 string
+fn encode_string(buffer: &mut Vec<u8>, s: &str) {
+    buffer.extend_from_slice(&(s.len() as u16).to_be_bytes());
+    buffer.extend_from_slice(s.as_bytes());
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn remaining_length() {
+        assert_eq!(encode_packet(0xe0, &[]), [0xe0, 0x00]);
+        assert_eq!(encode_packet(0x31, &[0; 127])[..2], [0x31, 0x7f]);
+        assert_eq!(encode_packet(0x31, &[0; 128])[..3], [0x31, 0x80, 0x01]);
+        assert_eq!(encode_packet(0x31, &[0; 16_384])[..4], [0x31, 0x80, 0x80, 0x01]);
+        assert_eq!(encode_packet(0x31, &[0; 300]).len(), 303);
+    }
+
+    #[test]
+    fn string() {
+        let mut buffer = Vec::new();
+        encode_string(&mut buffer, "erth/citizen");
+        assert_eq!(buffer[..2], [0x00, 0x0c]);
+        assert_eq!(&buffer[2..], b"erth/citizen");
+    }
+}
@@ -0,0 +1,217 @@
+//! Munin plugin, run by the `munin` command or when invoked through a symlink named `erth_...`
+use std::ffi::OsString;
+use std::fmt::Write;
+use std::path::Path;
+
+use clap::ValueEnum;
+
+use crate::api::{Queue, Queues};
+use crate::config::Config;
+use crate::Scraper;
+
+
+/// Prefix of the file names the exporter is run as Munin plugin under
+const PLUGIN_PREFIX: &str = "erth_";
+
+/// Environment variable with the configuration file used when run as Munin plugin, which can be
+/// set with `env.ERTH_EXPORTER_CONFIG` in the plugin configuration of munin-node
+const CONFIG_VARIABLE: &str = "ERTH_EXPORTER_CONFIG";
+
+/// Graphs provided by the plugin
+const GRAPHS: [Graph; 3] = [
+    Graph {
+        name: "erth_people_waiting",
+        title: "Bürgeramt people waiting",
+        vlabel: "people",
+        value: |q| Some(q.people_waiting as f64),
+    },
+    Graph {
+        name: "erth_waiting_time",
+        title: "Bürgeramt waiting time",
+        vlabel: "minutes",
+        value: |q| Some(q.waiting_time_seconds / 60.0),
+    },
+    Graph {
+        name: "erth_tracked_waiting_time",
+        title: "Bürgeramt tracked waiting time",
+        vlabel: "minutes",
+        value: |q| q.tracked_waiting_time_seconds.map(|t| t / 60.0),
+    },
+];
+
+
+/// Request of munin-node, given as first argument
+#[derive(Debug,Clone,Copy,ValueEnum)]
+pub enum Mode {
+    /// Print the graph definitions
+    Config,
+
+    /// Print whether the page can be scraped
+    Autoconf,
+}
+
+/// Graph with one field per service
+struct Graph {
+    name: &'static str,
+    title: &'static str,
+    vlabel: &'static str,
+
+    /// Value of a queue, in the unit of the vertical label
+    value: fn(&Queue) -> Option<f64>,
+}
+
+
+/// Rewrite the command line of a Munin plugin invocation into one of the `munin` command
+///
+/// If the program is invoked through a file name starting with `erth_`, e.g. a symlink in
+/// `/etc/munin/plugins`, the configuration file is taken from `ERTH_EXPORTER_CONFIG` and the
+/// arguments of munin-node are passed to the `munin` command. Other command lines are returned
+/// unchanged.
+pub fn plugin_args(args: Vec<OsString>) -> Vec<OsString> {
+    let is_plugin = args.first()
+        .and_then(|program| Path::new(program).file_name())
+        .is_some_and(|name| name.to_string_lossy().starts_with(PLUGIN_PREFIX));
+    if !is_plugin {
+        return args;
+    }
+
+    let mut rewritten = vec![args[0].clone()];
+    if let Some(config) = std::env::var_os(CONFIG_VARIABLE) {
+        rewritten.push(OsString::from("--config"));
+        rewritten.push(config);
+    }
+    rewritten.push(OsString::from("munin"));
+    rewritten.extend(args.into_iter().skip(1));
+    rewritten
+}
+
+/// Scrape the page once and print the requested output for munin-node
+///
+/// The [state file](Config::state_file) is used if configured, so that waiting times can be
+/// tracked across invocations, and the frame is stored in the database if configured.
+pub fn run(config: &Config, mode: Option<Mode>) -> Result<(), String> {
+    let mut scraper = Scraper::open(config)?;
+    scraper.refresh();
+    let queues = scraper.queues(&config.timezone);
+
+    let output = match mode {
+        Some(Mode::Autoconf) => match queues {
+            Some(_) => String::from("yes\n"),
+            None => String::from("no (cannot scrape page)\n"),
+        },
+        Some(Mode::Config) => graph_config(queues.as_ref()),
+        None => values(queues.as_ref()),
+    };
+    print!("{}", output);
+    Ok(())
+}
+
+
+/// Graph definitions for all services found on the page
+fn graph_config(queues: Option<&Queues>) -> String {
+    let mut output = String::new();
+    for graph in &GRAPHS {
+        let _ = writeln!(output, "multigraph {}", graph.name);
+        let _ = writeln!(output, "graph_title {}", graph.title);
+        let _ = writeln!(output, "graph_vlabel {}", graph.vlabel);
+        let _ = writeln!(output, "graph_args --base 1000 -l 0");
+        let _ = writeln!(output, "graph_category {}", env!("CARGO_PKG_NAME"));
+        for service in queues.iter().flat_map(|q| q.queues.keys()) {
+            let _ = writeln!(output, "{}.label {}", field(service), service);
+            let _ = writeln!(output, "{}.min 0", field(service));
+        }
+    }
+    output
+}
+
+/// Current values of all services, or `U` if unknown or not a number
+fn values(queues: Option<&Queues>) -> String {
+    let mut output = String::new();
+    for graph in &GRAPHS {
+        let _ = writeln!(output, "multigraph {}", graph.name);
+        for (service, queue) in queues.iter().flat_map(|q| &q.queues) {
+            let value = (graph.value)(queue)
+                .filter(|v| v.is_finite())
+                .map_or_else(|| String::from("U"), |v| v.to_string());
+            let _ = writeln!(output, "{}.value {}", field(service), value);
+        }
+    }
+    output
+}
+
+/// Munin field name of a service
+fn field(service: &str) -> String {
+    let mut field: String = service.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if field.starts_with(|c: char| c.is_ascii_digit()) {
+        field.insert(0, '_');
+    }
+    field
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queues() -> Queues {
+        let queue = |people_waiting, tracked| Queue {
+            people_waiting,
+            last_called_ticket: String::from("B001"),
+            waiting_time_seconds: 90.0,
+            tracked_waiting_time_seconds: tracked,
+            smoothed_waiting_time_seconds: None,
+            new_ticket_wait_estimate_seconds: None,
+            service_rate_per_minute: 0.0,
+            arrival_rate_per_minute: 0.0,
+        };
+        Queues {
+            timestamp: 1704096000.0,
+            scraped_at: String::from("2024-01-01T09:00:00+01:00"),
+            age_seconds: 0.0,
+            stale: false,
+            queues: [
+                (String::from("1st floor"), queue(3, Some(f64::NAN))),
+                (String::from("citizen"), queue(12, None)),
+            ].into(),
+            errors: Default::default(),
+        }
+    }
+
+    #[test]
+    fn plugin_invocation() {
+        let args = |args: &[&str]| args.iter().map(OsString::from).collect::<Vec<_>>();
+        assert_eq!(plugin_args(args(&["erth-exporter", "scrape"])), args(&["erth-exporter", "scrape"]));
+        // the configuration file in between depends on the environment
+        let rewritten = plugin_args(args(&["/etc/munin/plugins/erth_queues", "config"]));
+        assert_eq!(rewritten[0], "/etc/munin/plugins/erth_queues");
+        assert_eq!(rewritten[rewritten.len() - 2..], args(&["munin", "config"]));
+    }
+
+    #[test]
+    fn fields() {
+        assert_eq!(field("citizen"), "citizen");
+        assert_eq!(field("drivers-license"), "drivers_license");
+        assert_eq!(field("1st floor"), "_1st_floor");
+    }
+
+    #[test]
+    fn config() {
+        let config = graph_config(Some(&queues()));
+        assert!(config.starts_with("multigraph erth_people_waiting\ngraph_title Bürgeramt people waiting\n\
+            graph_vlabel people\ngraph_args --base 1000 -l 0\ngraph_category erth-exporter\n\
+            _1st_floor.label 1st floor\n_1st_floor.min 0\ncitizen.label citizen\ncitizen.min 0\n"));
+        assert_eq!(config.matches("multigraph").count(), GRAPHS.len());
+        assert_eq!(graph_config(None).matches(".label").count(), 0);
+    }
+
+    #[test]
+    fn unknown_values() {
+        assert_eq!(values(Some(&queues())), "multigraph erth_people_waiting\n_1st_floor.value 3\ncitizen.value 12\n\
+            multigraph erth_waiting_time\n_1st_floor.value 1.5\ncitizen.value 1.5\n\
+            multigraph erth_tracked_waiting_time\n_1st_floor.value U\ncitizen.value U\n");
+        assert_eq!(values(None), "multigraph erth_people_waiting\nmultigraph erth_waiting_time\n\
+            multigraph erth_tracked_waiting_time\n");
+    }
+}
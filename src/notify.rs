@@ -0,0 +1,274 @@
+//! Notifications when queues cross configured thresholds
+use prometheus::proto::MetricFamily;
+use serde::Serialize;
+
+use crate::config::{Condition, RuleConfig, RuleMetric};
+use crate::history::{HistoryFrame, Sample};
+use crate::push::Sink;
+
+
+/// Channel notifications are delivered through
+pub trait Notifier: Send {
+    /// Name of the channel used in log messages
+    fn name(&self) -> &str;
+
+    /// Deliver a single notification
+    fn notify(&mut self, event: &Event) -> Result<(), String>;
+}
+
+
+/// Evaluates the threshold rules on every new data frame and notifies all channels about
+/// crossings
+pub struct Notifications {
+    rules: Vec<Rule>,
+    notifiers: Vec<Box<dyn Notifier>>,
+}
+
+/// Threshold rule along with its state per service
+struct Rule {
+    config: RuleConfig,
+
+    /// Services for which the rule currently fires
+    firing: Vec<String>,
+}
+
+/// Crossing of a threshold, sent as JSON payload by webhooks
+#[derive(Debug,Clone,Serialize)]
+pub struct Event {
+    /// Name of the rule
+    pub rule: String,
+
+    pub service: String,
+    pub metric: RuleMetric,
+    pub condition: Condition,
+    pub threshold: f64,
+
+    /// Value that caused the crossing
+    pub value: f64,
+
+    /// Whether the condition started to hold, otherwise it stopped holding
+    pub firing: bool,
+
+    /// Time of the data frame in seconds since the epoch
+    pub timestamp: f64,
+}
+
+
+impl Notifications {
+    pub fn new(rules: &[RuleConfig], notifiers: Vec<Box<dyn Notifier>>) -> Self {
+        Notifications {
+            rules: rules.iter()
+                .map(|config| Rule { config: config.clone(), firing: Vec::new() })
+                .collect(),
+            notifiers,
+        }
+    }
+
+    /// Update the rule states, returning the resulting crossings
+    fn evaluate(&mut self, frame: &HistoryFrame) -> Vec<Event> {
+        let mut events = Vec::new();
+        for rule in &mut self.rules {
+            let services = frame.queues.iter()
+                .filter(|(s, _)| rule.config.service.as_ref().is_none_or(|r| r == *s));
+            for (service, sample) in services {
+                let Some(value) = rule.config.metric.value(sample) else {
+                    continue;
+                };
+                let firing = rule.firing.contains(service);
+                let crossed = match firing {
+                    false => rule.config.holds(value),
+                    true => rule.config.released(value),
+                };
+                if !crossed {
+                    continue;
+                }
+
+                match firing {
+                    false => rule.firing.push(service.clone()),
+                    true => rule.firing.retain(|s| s != service),
+                }
+                events.push(Event {
+                    rule: rule.config.name.clone(),
+                    service: service.clone(),
+                    metric: rule.config.metric,
+                    condition: rule.config.condition(),
+                    threshold: rule.config.threshold(),
+                    value,
+                    firing: !firing,
+                    timestamp: frame.timestamp,
+                });
+            }
+        }
+        events
+    }
+}
+
+impl Event {
+    /// Short summary of the event, e.g. as message title
+    pub fn title(&self) -> String {
+        match self.firing {
+            true => format!("{} ({})", self.rule, self.service),
+            false => format!("{} ({}) resolved", self.rule, self.service),
+        }
+    }
+
+    /// Human-readable description of the event
+    pub fn message(&self) -> String {
+        let direction = match (self.condition, self.firing) {
+            (Condition::Below, true) => "dropped below",
+            (Condition::Above, true) => "rose above",
+            (Condition::Below, false) => "is no longer below",
+            (Condition::Above, false) => "is no longer above",
+        };
+        format!("{} for {} {} {}, now {}", self.metric.description(), self.service, direction,
+            self.metric.format(self.threshold), self.metric.format(self.value))
+    }
+}
+
+impl Sink for Notifications {
+    fn name(&self) -> &str {
+        "notifications"
+    }
+
+    fn push(&mut self, frame: &HistoryFrame, _families: &[MetricFamily]) -> Result<(), String> {
+        for event in self.evaluate(frame) {
+            for notifier in &mut self.notifiers {
+                if let Err(e) = notifier.notify(&event) {
+                    eprintln!("Warning: cannot notify via {}: {}", notifier.name(), e);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl RuleMetric {
+    fn description(&self) -> &'static str {
+        match self {
+            RuleMetric::PeopleWaiting => "People waiting",
+            RuleMetric::WaitingTime => "Waiting time",
+            RuleMetric::TrackedWaitingTime => "Tracked waiting time",
+        }
+    }
+
+    /// Format a value of the metric with its unit
+    fn format(&self, value: f64) -> String {
+        match self {
+            RuleMetric::PeopleWaiting => format!("{}", value),
+            RuleMetric::WaitingTime | RuleMetric::TrackedWaitingTime => format_duration(value),
+        }
+    }
+
+    /// Current value of the metric in a sample, if known
+    fn value(&self, sample: &Sample) -> Option<f64> {
+        match self {
+            RuleMetric::PeopleWaiting => Some(sample.people_waiting as f64),
+            RuleMetric::WaitingTime => Some(sample.waiting_time_seconds),
+            RuleMetric::TrackedWaitingTime => sample.tracked_waiting_time_seconds,
+        }
+    }
+}
+
+
+/// Human-readable summary of all queues in a data frame
+pub fn status(frame: &HistoryFrame) -> String {
+    if frame.queues.is_empty() {
+        return String::from("No queue information available");
+    }
+    frame.queues.iter()
+        .map(|(service, sample)| format!("{}: {} waiting, last called {}, about {}", service,
+            sample.people_waiting, sample.last_called_ticket, format_duration(sample.waiting_time_seconds)))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Format a duration in seconds as whole minutes
+fn format_duration(seconds: f64) -> String {
+    format!("{} min", (seconds / 60.0).round())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(service: Option<&str>, metric: RuleMetric, above: f64, hysteresis: f64) -> RuleConfig {
+        RuleConfig {
+            name: String::from("busy"),
+            service: service.map(str::to_owned),
+            metric,
+            below: None,
+            above: Some(above),
+            hysteresis,
+        }
+    }
+
+    fn frame(timestamp: f64, queues: &[(&str, usize, Option<f64>)]) -> HistoryFrame {
+        HistoryFrame {
+            timestamp,
+            queues: queues.iter()
+                .map(|(service, people_waiting, tracked)| (service.to_string(), Sample {
+                    people_waiting: *people_waiting,
+                    last_called_ticket: String::from("B001"),
+                    waiting_time_seconds: 600.0,
+                    tracked_waiting_time_seconds: *tracked,
+                }))
+                .collect(),
+        }
+    }
+
+    /// Services and directions of the crossings caused by a frame
+    fn crossings(notifications: &mut Notifications, frame: &HistoryFrame) -> Vec<(String, bool)> {
+        notifications.evaluate(frame).into_iter()
+            .map(|e| (e.service, e.firing))
+            .collect()
+    }
+
+    #[test]
+    fn hysteresis() {
+        let mut notifications = Notifications::new(&[rule(None, RuleMetric::PeopleWaiting, 10.0, 2.0)], Vec::new());
+        let crossings = [11, 10, 11, 9, 11, 8, 9, 11]
+            .map(|people| crossings(&mut notifications, &frame(0.0, &[("citizen", people, None)])));
+        let fired = (String::from("citizen"), true);
+        let resolved = (String::from("citizen"), false);
+        // flapping around the threshold only fires once, until the value recovers by the hysteresis
+        assert_eq!(crossings, [vec![fired.clone()], vec![], vec![], vec![], vec![], vec![resolved], vec![], vec![fired]]);
+    }
+
+    #[test]
+    fn state_per_service() {
+        let mut notifications = Notifications::new(&[rule(None, RuleMetric::PeopleWaiting, 10.0, 0.0)], Vec::new());
+        let first = frame(0.0, &[("citizen", 20, None), ("drivers_license", 5, None)]);
+        assert_eq!(crossings(&mut notifications, &first), [(String::from("citizen"), true)]);
+        let second = frame(60.0, &[("citizen", 20, None), ("drivers_license", 15, None)]);
+        assert_eq!(crossings(&mut notifications, &second), [(String::from("drivers_license"), true)]);
+    }
+
+    #[test]
+    fn filtered_and_unknown_values() {
+        let rules = [
+            rule(Some("drivers_license"), RuleMetric::PeopleWaiting, 10.0, 0.0),
+            rule(None, RuleMetric::TrackedWaitingTime, 60.0, 0.0),
+        ];
+        let mut notifications = Notifications::new(&rules, Vec::new());
+        assert!(notifications.evaluate(&frame(0.0, &[("citizen", 20, None)])).is_empty());
+        assert!(notifications.evaluate(&frame(0.0, &[("citizen", 20, Some(f64::NAN))])).is_empty());
+
+        let events = notifications.evaluate(&frame(60.0, &[("citizen", 20, Some(900.0))]));
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].title(), "busy (citizen)");
+        assert_eq!(events[0].message(), "Tracked waiting time for citizen rose above 1 min, now 15 min");
+    }
+
+    #[test]
+    fn below() {
+        let config = RuleConfig { below: Some(5.0), above: None, ..rule(None, RuleMetric::PeopleWaiting, 0.0, 1.0) };
+        let mut notifications = Notifications::new(&[config], Vec::new());
+        let events = notifications.evaluate(&frame(0.0, &[("citizen", 4, None)]));
+        assert_eq!(events[0].message(), "People waiting for citizen dropped below 5, now 4");
+        assert!(notifications.evaluate(&frame(60.0, &[("citizen", 5, None)])).is_empty());
+        let events = notifications.evaluate(&frame(120.0, &[("citizen", 6, None)]));
+        assert_eq!(events[0].title(), "busy (citizen) resolved");
+        assert_eq!(events[0].message(), "People waiting for citizen is no longer below 5, now 6");
+    }
+}
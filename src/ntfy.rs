@@ -0,0 +1,66 @@
+//! Notifications via ntfy
+use serde::Serialize;
+
+use crate::config::{Config, NtfyConfig};
+use crate::notify::{Event, Notifier};
+use crate::push;
+
+
+/// Publishes notifications to an ntfy topic
+pub struct Ntfy {
+    config: NtfyConfig,
+    client: reqwest::blocking::Client,
+}
+
+/// Message as published via the JSON API
+#[derive(Serialize)]
+struct Publish<'a> {
+    topic: &'a str,
+    title: &'a str,
+    message: &'a str,
+    priority: u8,
+    tags: [&'static str; 1],
+}
+
+
+impl Ntfy {
+    pub fn new(config: &Config, ntfy: &NtfyConfig) -> Self {
+        Ntfy {
+            config: ntfy.clone(),
+            client: push::client(config),
+        }
+    }
+}
+
+impl Notifier for Ntfy {
+    fn name(&self) -> &str {
+        "ntfy"
+    }
+
+    fn notify(&mut self, event: &Event) -> Result<(), String> {
+        let title = self.config.title.clone()
+            .unwrap_or_else(|| event.title());
+        let publish = Publish {
+            topic: &self.config.topic,
+            title: &title,
+            message: &event.message(),
+            priority: self.config.priority,
+            tags: [if event.firing { "hourglass" } else { "white_check_mark" }],
+        };
+        let body = serde_json::to_vec(&publish)
+            .map_err(|e| e.to_string())?;
+
+        let mut request = self.client.post(&self.config.url)
+            .header("Content-Type", "application/json")
+            .body(body);
+        if let Some(token) = &self.config.token {
+            request = request.bearer_auth(token);
+        } else if let Some(username) = &self.config.username {
+            request = request.basic_auth(username, self.config.password.as_ref());
+        }
+
+        let response = request.send()
+            .map_err(|e| e.to_string())?;
+        push::check_response(response)
+    }
+}
@@ -0,0 +1,176 @@
+//! OpenAPI description of the JSON, CSV, GraphQL and admin endpoints, served on `/api/openapi.json`
+use std::collections::BTreeMap;
+
+use schemars::gen::{SchemaGenerator, SchemaSettings};
+use schemars::JsonSchema;
+use serde_json::{json, Value};
+
+use crate::api::{ForecastPoint, Queues, ServiceStats, WaitEstimate};
+use crate::history::HistoryFrame;
+use crate::widget::Waybar;
+
+
+/// Generate the OpenAPI 3.0 document, with schemas derived from the response types
+pub fn document() -> Value {
+    let mut generator = SchemaGenerator::new(SchemaSettings::openapi3());
+
+    #[cfg_attr(not(feature = "sqlite"), allow(unused_mut))]
+    let mut paths = json!({
+        "/api/v1/queues": {
+            "get": operation::<Queues>(&mut generator, "Current state of all queues", &[]),
+        },
+        "/api/v1/stats": {
+            "get": operation::<BTreeMap<String, ServiceStats>>(&mut generator,
+                "Waiting time statistics per day and weekday, by service",
+                &[("from", "string", false, "Start of the statistics in seconds since the epoch or RFC 3339 (default 28 days ago)"),
+                  ("to", "string", false, "End of the statistics in seconds since the epoch or RFC 3339")]),
+        },
+        "/api/v1/forecast": {
+            "get": operation::<BTreeMap<String, Vec<ForecastPoint>>>(&mut generator,
+                "Expected waiting times of the upcoming hours, by service",
+                &[("hours", "integer", false, "Number of hours to forecast, from 1 to at most one week (default 3)")]),
+        },
+        "/wait": {
+            "get": operation::<WaitEstimate>(&mut generator, "Expected call of a ticket",
+                &[("ticket", "string", true, "Ticket like `B123`")]),
+        },
+        "/history": {
+            "get": operation::<Vec<HistoryFrame>>(&mut generator, "Recent data frames kept in memory",
+                &[("minutes", "integer", false, "Only include frames of the last minutes")]),
+        },
+        "/export.csv": {
+            "get": {
+                "summary": "Stored data frames as CSV, one row per queue and frame",
+                "parameters": parameters(&[
+                    ("from", "string", false, "Start of the export in seconds since the epoch or RFC 3339"),
+                    ("to", "string", false, "End of the export in seconds since the epoch or RFC 3339"),
+                ]),
+                "responses": {
+                    "200": {
+                        "description": "OK",
+                        "content": { "text/csv": { "schema": { "type": "string" } } },
+                    },
+                    "400": { "description": "Invalid parameters" },
+                },
+            },
+        },
+        "/graphql": {
+            "get": graphql_operation(parameters(&[
+                ("query", "string", true, "GraphQL query"),
+                ("operationName", "string", false, "Operation to execute if the query contains several"),
+                ("variables", "string", false, "Variables of the query as JSON object"),
+            ]), None),
+            "post": graphql_operation(Vec::new(), Some(json!({
+                "required": true,
+                "content": {
+                    "application/json": {
+                        "schema": {
+                            "type": "object",
+                            "required": ["query"],
+                            "properties": {
+                                "query": { "type": "string" },
+                                "operationName": { "type": "string" },
+                                "variables": { "type": "object" },
+                            },
+                        },
+                    },
+                },
+            }))),
+        },
+        "/widget/waybar": {
+            "get": operation::<Waybar>(&mut generator, "Status of the queues for waybar custom modules",
+                &[("service", "string", false, "Only include this service")]),
+        },
+    });
+
+    #[cfg(feature = "sqlite")]
+    {
+        paths["/admin/backup"] = json!({
+            "get": {
+                "summary": "Consistent snapshot of the database, requires the admin token",
+                "security": [{ "adminToken": [] }],
+                "responses": {
+                    "200": {
+                        "description": "OK",
+                        "content": {
+                            "application/vnd.sqlite3": { "schema": { "type": "string", "format": "binary" } },
+                        },
+                    },
+                    "401": { "description": "Missing or invalid admin token" },
+                    "404": { "description": "No admin token or database configured" },
+                },
+            },
+        });
+    }
+
+    let schemas = generator.take_definitions();
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": env!("CARGO_PKG_NAME"),
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": paths,
+        "components": {
+            "schemas": schemas,
+            "securitySchemes": {
+                "adminToken": { "type": "http", "scheme": "bearer" },
+            },
+        },
+    })
+}
+
+
+/// Description of a `GET` operation returning `T`, with query parameters given as name, type,
+/// whether they are required and description
+fn operation<T: JsonSchema>(generator: &mut SchemaGenerator, summary: &str, parameters: &[(&str, &str, bool, &str)])
+        -> Value {
+    json!({
+        "summary": summary,
+        "parameters": self::parameters(parameters),
+        "responses": {
+            "200": {
+                "description": "OK",
+                "content": {
+                    "application/json": {
+                        "schema": generator.subschema_for::<T>(),
+                    },
+                },
+            },
+            "400": { "description": "Invalid parameters" },
+        },
+    })
+}
+
+/// Description of a GraphQL operation, with the request given either as query parameters or
+/// as body
+fn graphql_operation(parameters: Vec<Value>, request_body: Option<Value>) -> Value {
+    let mut operation = json!({
+        "summary": "GraphQL queries of the queues, history and statistics",
+        "parameters": parameters,
+        "responses": {
+            "200": {
+                "description": "Result of the query, including errors of its execution",
+                "content": { "application/json": { "schema": { "type": "object" } } },
+            },
+            "400": { "description": "Invalid request" },
+        },
+    });
+    if let Some(request_body) = request_body {
+        operation["requestBody"] = request_body;
+    }
+    operation
+}
+
+/// Query parameters given as name, type, whether they are required and description
+fn parameters(parameters: &[(&str, &str, bool, &str)]) -> Vec<Value> {
+    parameters.iter()
+        .map(|(name, kind, required, description)| json!({
+            "name": name,
+            "in": "query",
+            "required": required,
+            "description": description,
+            "schema": { "type": kind },
+        }))
+        .collect()
+}
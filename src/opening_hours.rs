@@ -0,0 +1,65 @@
+//! Weekly opening hours of the town hall
+use std::collections::HashMap;
+
+use chrono::{DateTime, Datelike, NaiveTime, TimeZone, Weekday};
+use serde::Deserialize;
+
+
+/// Opening hours for each day of the week
+///
+/// Configured as a table mapping weekdays to lists of time ranges in local time:
+///
+/// ```toml
+/// [opening_hours]
+/// mon = ["07:30-12:00"]
+/// thu = ["07:30-12:00", "14:00-18:00"]
+/// ```
+#[derive(Debug,Clone,Deserialize)]
+#[serde(try_from = "HashMap<String, Vec<String>>")]
+pub struct OpeningHours {
+    /// Time ranges indexed by [Weekday::num_days_from_monday]
+    days: [Vec<(NaiveTime, NaiveTime)>; 7],
+}
+
+
+impl OpeningHours {
+    /// Whether the office is open at the given point in time
+    pub fn is_open<Tz: TimeZone>(&self, time: &DateTime<Tz>) -> bool {
+        let local_time = time.time();
+        self.days[time.weekday().num_days_from_monday() as usize].iter()
+            .any(|(from, to)| *from <= local_time && local_time < *to)
+    }
+
+    /// Parse a time range in the format `HH:MM-HH:MM`
+    fn parse_range(s: &str) -> Result<(NaiveTime, NaiveTime), String> {
+        let (from, to) = s.split_once('-')
+            .ok_or_else(|| format!("invalid time range '{}'", s))?;
+        let from = NaiveTime::parse_from_str(from.trim(), "%H:%M")
+            .map_err(|e| format!("invalid time '{}': {}", from, e))?;
+        let to = NaiveTime::parse_from_str(to.trim(), "%H:%M")
+            .map_err(|e| format!("invalid time '{}': {}", to, e))?;
+
+        if from >= to {
+            return Err(format!("time range '{}' ends before it starts", s));
+        }
+
+        Ok((from, to))
+    }
+}
+
+impl TryFrom<HashMap<String, Vec<String>>> for OpeningHours {
+    type Error = String;
+
+    fn try_from(value: HashMap<String, Vec<String>>) -> Result<Self, Self::Error> {
+        let mut days: [Vec<_>; 7] = Default::default();
+        for (day, ranges) in value {
+            let weekday: Weekday = day.parse()
+                .map_err(|_| format!("invalid weekday '{}'", day))?;
+            days[weekday.num_days_from_monday() as usize] = ranges.iter()
+                .map(|r| Self::parse_range(r))
+                .collect::<Result<_, _>>()?;
+        }
+
+        Ok(OpeningHours { days })
+    }
+}
@@ -0,0 +1,136 @@
+//! Pushing scraped data to external systems
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use prometheus::proto::{MetricFamily, MetricType};
+
+use crate::config::Config;
+use crate::history::HistoryFrame;
+use crate::Scraper;
+
+
+/// Timeout for requests to push targets
+const PUSH_TIMEOUT: Duration = Duration::from_secs(10);
+
+
+/// Single value of a series, as exposed in the text format
+#[derive(Debug,Clone)]
+pub struct FlatSample {
+    /// Name of the series, including suffixes like `_bucket` or `_sum`
+    pub name: String,
+
+    /// Labels of the series, including `le` and `quantile`
+    pub labels: Vec<(String, String)>,
+
+    pub value: f64,
+}
+
+
+/// System the scraped data is pushed to
+pub trait Sink: Send {
+    /// Name of the sink used in log messages
+    fn name(&self) -> &str;
+
+    /// Push the latest data frame, along with all metrics as served on `/metrics`
+    fn push(&mut self, frame: &HistoryFrame, families: &[MetricFamily]) -> Result<(), String>;
+}
+
+
+/// Periodically push new data frames of the scraper to a sink in a background thread
+pub fn spawn(mut sink: Box<dyn Sink>, scraper: Arc<Mutex<Scraper>>, interval: Duration) {
+    thread::spawn(move || {
+        let mut last_pushed = None;
+        loop {
+            thread::sleep(interval);
+
+            let (frame, families) = {
+                let mut scraper = scraper.lock().unwrap();
+                let Some(data) = &scraper.cache else {
+                    continue;
+                };
+                if last_pushed == Some(data.created_timestamp) {
+                    continue;
+                }
+                last_pushed = Some(data.created_timestamp);
+                (HistoryFrame::from(data), scraper.snapshot())
+            };
+
+            if let Err(e) = sink.push(&frame, &families) {
+                eprintln!("Warning: cannot push to {}: {}", sink.name(), e);
+            }
+        }
+    });
+}
+
+/// HTTP client for pushing to sinks
+pub fn client(config: &Config) -> reqwest::blocking::Client {
+    let mut client = reqwest::blocking::Client::builder()
+        .timeout(PUSH_TIMEOUT)
+        .user_agent(&config.user_agent);
+    if let Some(proxy) = &config.proxy {
+        client = client.proxy(reqwest::Proxy::all(proxy).expect("Proxy is validated with the config"));
+    }
+    client.build()
+        .expect("HTTP client configuration must be valid")
+}
+
+/// Check that a push target accepted a request
+pub fn check_response(response: reqwest::blocking::Response) -> Result<(), String> {
+    match response.status().is_success() {
+        true => Ok(()),
+        false => Err(format!("server responded with {}", response.status())),
+    }
+}
+
+/// Replace characters with a special meaning in metric names, paths and identifiers of push
+/// targets, keeping only ASCII alphanumerics, `_`, `-` and the characters in `keep`
+pub fn sanitize(s: &str, keep: &[char]) -> String {
+    s.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == '-' || keep.contains(&c) { c } else { '_' })
+        .collect()
+}
+
+/// Expand metric families into single values, with histograms and summaries split into their
+/// buckets or quantiles, sum and count
+pub fn flatten(families: &[MetricFamily]) -> Vec<FlatSample> {
+    let mut samples = Vec::new();
+    for family in families {
+        for metric in family.get_metric() {
+            let labels: Vec<_> = metric.get_label().iter()
+                .map(|l| (l.name().to_owned(), l.value().to_owned()))
+                .collect();
+            let mut push = |suffix: &str, extra: Option<(&str, f64)>, value: f64| {
+                let mut labels = labels.clone();
+                if let Some((name, value)) = extra {
+                    labels.push((name.to_owned(), value.to_string()));
+                }
+                samples.push(FlatSample { name: format!("{}{}", family.name(), suffix), labels, value });
+            };
+
+            match family.get_field_type() {
+                MetricType::COUNTER => push("", None, metric.get_counter().value()),
+                MetricType::GAUGE => push("", None, metric.get_gauge().value()),
+                MetricType::UNTYPED => push("", None, metric.untyped.value()),
+                MetricType::SUMMARY => {
+                    let summary = metric.get_summary();
+                    for quantile in summary.get_quantile() {
+                        push("", Some(("quantile", quantile.quantile())), quantile.value());
+                    }
+                    push("_sum", None, summary.sample_sum());
+                    push("_count", None, summary.sample_count() as f64);
+                },
+                MetricType::HISTOGRAM => {
+                    let histogram = metric.get_histogram();
+                    for bucket in histogram.get_bucket() {
+                        push("_bucket", Some(("le", bucket.upper_bound())), bucket.cumulative_count() as f64);
+                    }
+                    push("_bucket", Some(("le", f64::INFINITY)), histogram.sample_count() as f64);
+                    push("_sum", None, histogram.sample_sum());
+                    push("_count", None, histogram.sample_count() as f64);
+                },
+            }
+        }
+    }
+    samples
+}
@@ -0,0 +1,52 @@
+//! Pushing metrics to a Prometheus Pushgateway
+use prometheus::proto::MetricFamily;
+use reqwest::Url;
+
+use crate::config::{Config, PushgatewayConfig};
+use crate::history::HistoryFrame;
+use crate::metrics;
+use crate::push::{self, Sink};
+
+
+/// Replaces the metric group of the configured job and instance on a Pushgateway
+pub struct Pushgateway {
+    config: PushgatewayConfig,
+    client: reqwest::blocking::Client,
+    url: Url,
+}
+
+
+impl Pushgateway {
+    pub fn new(config: &Config, pushgateway: &PushgatewayConfig) -> Self {
+        Pushgateway {
+            config: pushgateway.clone(),
+            client: push::client(config),
+            url: pushgateway.group_url().expect("Pushgateway URL is validated with the config"),
+        }
+    }
+}
+
+impl Sink for Pushgateway {
+    fn name(&self) -> &str {
+        "pushgateway"
+    }
+
+    fn push(&mut self, _frame: &HistoryFrame, families: &[MetricFamily]) -> Result<(), String> {
+        // the Pushgateway rejects samples with timestamps, as added with `sample_timestamps`
+        let mut families = families.to_vec();
+        for metric in families.iter_mut().flat_map(|f| f.mut_metric()) {
+            metric.clear_timestamp_ms();
+        }
+
+        let mut request = self.client.put(self.url.clone())
+            .header("Content-Type", "text/plain; version=0.0.4")
+            .body(metrics::encode(families, &[]));
+        if let Some(username) = &self.config.username {
+            request = request.basic_auth(username, self.config.password.as_ref());
+        }
+
+        let response = request.send()
+            .map_err(|e| e.to_string())?;
+        push::check_response(response)
+    }
+}
@@ -0,0 +1,47 @@
+//! Event rates over a sliding time window
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+
+/// Counts events over a sliding time window
+#[derive(Debug,Clone)]
+pub struct RateWindow {
+    /// Number of events recorded at the respective point in time
+    events: VecDeque<(Instant, usize)>,
+
+    /// Time span over which events are considered
+    window: Duration,
+}
+
+
+impl RateWindow {
+    pub fn new(window: Duration) -> Self {
+        RateWindow {
+            events: VecDeque::new(),
+            window,
+        }
+    }
+
+    /// Record a number of events that happened just now
+    pub fn record(&mut self, count: usize) {
+        while self.events.front().is_some_and(|(i, _)| i.elapsed() > self.window) {
+            self.events.pop_front();
+        }
+        if count > 0 {
+            self.events.push_back((Instant::now(), count));
+        }
+    }
+
+    /// Number of events within the window
+    pub fn count(&self) -> usize {
+        self.events.iter()
+            .filter(|(i, _)| i.elapsed() <= self.window)
+            .map(|(_, n)| n)
+            .sum()
+    }
+
+    /// Events per minute over the window
+    pub fn per_minute(&self) -> f64 {
+        self.count() as f64 / (self.window.as_secs_f64() / 60.0)
+    }
+}
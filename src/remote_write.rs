@@ -0,0 +1,140 @@
+//! Pushing metrics via Prometheus remote write
+use prometheus::proto::MetricFamily;
+
+use crate::config::{Config, RemoteWriteConfig};
+use crate::history::HistoryFrame;
+use crate::push::{self, Sink};
+
+
+/// Sends the metrics as snappy-compressed `WriteRequest` to a remote-write receiver such as
+/// Mimir, Cortex or VictoriaMetrics
+pub struct RemoteWrite {
+    config: RemoteWriteConfig,
+    client: reqwest::blocking::Client,
+}
+
+
+impl RemoteWrite {
+    pub fn new(config: &Config, remote_write: &RemoteWriteConfig) -> Self {
+        RemoteWrite {
+            config: remote_write.clone(),
+            client: push::client(config),
+        }
+    }
+}
+
+impl Sink for RemoteWrite {
+    fn name(&self) -> &str {
+        "remote write"
+    }
+
+    fn push(&mut self, frame: &HistoryFrame, families: &[MetricFamily]) -> Result<(), String> {
+        let request = write_request(families, (frame.timestamp * 1000.0) as i64);
+        let body = snap::raw::Encoder::new().compress_vec(&request)
+            .map_err(|e| e.to_string())?;
+
+        let mut request = self.client.post(&self.config.url)
+            .header("Content-Type", "application/x-protobuf")
+            .header("Content-Encoding", "snappy")
+            .header("X-Prometheus-Remote-Write-Version", "0.1.0")
+            .body(body);
+        if let Some(token) = &self.config.bearer_token {
+            request = request.bearer_auth(token);
+        } else if let Some(username) = &self.config.username {
+            request = request.basic_auth(username, self.config.password.as_ref());
+        }
+
+        let response = request.send()
+            .map_err(|e| e.to_string())?;
+        push::check_response(response)
+    }
+}
+
+
+/// Encode the metrics as protobuf `WriteRequest` with one sample per series
+fn write_request(families: &[MetricFamily], timestamp: i64) -> Vec<u8> {
+    let mut request = Vec::new();
+    for sample in push::flatten(families) {
+        let mut labels = sample.labels;
+        labels.push((String::from("__name__"), sample.name));
+        labels.sort();
+
+        let mut series = Vec::new();
+        for (name, value) in &labels {
+            let mut label = Vec::new();
+            encode_bytes(&mut label, 1, name.as_bytes());
+            encode_bytes(&mut label, 2, value.as_bytes());
+            encode_bytes(&mut series, 1, &label);
+        }
+        let mut value = Vec::new();
+        encode_key(&mut value, 1, 1);
+        value.extend_from_slice(&sample.value.to_le_bytes());
+        encode_key(&mut value, 2, 0);
+        encode_varint(&mut value, timestamp as u64);
+        encode_bytes(&mut series, 2, &value);
+
+        encode_bytes(&mut request, 1, &series);
+    }
+    request
+}
+
+/// Encode a length-delimited protobuf field
+fn encode_bytes(buffer: &mut Vec<u8>, field: u32, bytes: &[u8]) {
+    encode_key(buffer, field, 2);
+    encode_varint(buffer, bytes.len() as u64);
+    buffer.extend_from_slice(bytes);
+}
+
+/// Encode the key of a protobuf field with the given wire type
+fn encode_key(buffer: &mut Vec<u8>, field: u32, wire_type: u8) {
+    encode_varint(buffer, ((field << 3) | wire_type as u32) as u64);
+}
+
+/// Encode an integer in protobuf varint format
+fn encode_varint(buffer: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buffer.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use prometheus::core::Collector;
+    use prometheus::{Gauge, Opts};
+
+    use super::*;
+
+    #[test]
+    fn varint() {
+        let encode = |value| {
+            let mut buffer = Vec::new();
+            encode_varint(&mut buffer, value);
+            buffer
+        };
+        assert_eq!(encode(0), [0x00]);
+        assert_eq!(encode(127), [0x7f]);
+        assert_eq!(encode(300), [0xac, 0x02]);
+        assert_eq!(encode(1_700_000_000_000), [0x80, 0xd0, 0x95, 0xff, 0xbc, 0x31]);
+    }
+
+    #[test]
+    fn request() {
+        let gauge = Gauge::with_opts(Opts::new("a", "help").const_label("s", "b")).unwrap();
+        gauge.set(1.0);
+
+        let mut expected = vec![0x0a, 0x25];
+        // labels sorted by name, starting with the metric name
+        expected.extend([0x0a, 0x0d, 0x0a, 0x08]);
+        expected.extend(b"__name__");
+        expected.extend([0x12, 0x01, b'a']);
+        expected.extend([0x0a, 0x06, 0x0a, 0x01, b's', 0x12, 0x01, b'b']);
+        // sample with double value and varint timestamp
+        expected.extend([0x12, 0x0c, 0x09]);
+        expected.extend(1.0f64.to_le_bytes());
+        expected.extend([0x10, 0xe8, 0x07]);
+        assert_eq!(write_request(&gauge.collect(), 1000), expected);
+    }
+}
@@ -0,0 +1,70 @@
+//! Diagnostics of the configured page, run by the `selftest` command
+use std::fmt::Write;
+
+use crate::config::Config;
+use crate::source::{HtmlSource, ScrapeSource};
+
+
+/// Fetch the configured page and each fallback once and print a report on whether they can be
+/// reached and all queues parse
+///
+/// Fails if any of the pages cannot be fetched or contains a queue that cannot be parsed.
+pub fn run(config: &Config) -> Result<(), String> {
+    let module = config.find_module(&config.module)
+        .ok_or_else(|| format!("module '{}' not found", config.module))?;
+    println!("Module: {}", config.module);
+
+    let urls = [vec![config.url.clone()], config.fallback_urls.clone()].concat();
+    let mut failures = 0;
+    for url in urls {
+        let (ok, report) = check_page(&url, HtmlSource::new(vec![url.clone()], module.clone(), config));
+        if !ok {
+            failures += 1;
+        }
+        print!("{}", report);
+    }
+
+    match failures {
+        0 => {
+            println!("Result: OK");
+            Ok(())
+        },
+        _ => {
+            println!("Result: FAILED");
+            Err(format!("self-test failed for {} page(s)", failures))
+        },
+    }
+}
+
+
+/// Fetch a single page, returns whether it passed along with its part of the report
+fn check_page(url: &str, mut source: HtmlSource) -> (bool, String) {
+    let mut report = String::new();
+    let data = match source.fetch() {
+        Ok(Some(data)) => data,
+        Ok(None) => {
+            let _ = writeln!(report, "{}: FAILED, no content received", url);
+            return (false, report);
+        },
+        Err(e) => {
+            let _ = writeln!(report, "{}: FAILED, {}", url, e);
+            return (false, report);
+        },
+    };
+
+    let failed = data.queues.values().filter(|q| q.is_err()).count();
+    let _ = writeln!(report, "{}: {}, {} queue(s) found in {:.2} s", url, if failed == 0 { "OK" } else { "FAILED" },
+        data.queues.len(), data.scrape_duration.as_secs_f64());
+    for (service, queue) in &data.queues {
+        match queue {
+            Ok(queue) => {
+                let _ = writeln!(report, "  {}: OK, {} waiting, last called {}, {} min waiting time", service,
+                    queue.people_waiting, queue.last_called_ticket, queue.waiting_time_estimation);
+            },
+            Err(e) => {
+                let _ = writeln!(report, "  {}: FAILED, {}", service, e);
+            },
+        }
+    }
+    (failed == 0, report)
+}
@@ -0,0 +1,168 @@
+//! Cache shared between replicas via Redis
+//!
+//! One replica at a time holds a lock and scrapes the page, then publishes the data frame and the
+//! ticket tracker for the other replicas to adopt.
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::config::RedisConfig;
+use crate::state::State;
+use crate::{unix_timestamp, DataFrame, QueueDataFrame, Ticket, TicketType};
+
+
+/// Timeout for connecting to and talking to Redis
+const REDIS_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Extends the lock given as key by the milliseconds in the second argument, only if it is held
+/// by the instance in the first argument
+const RENEW_SCRIPT: &str = "if redis.call('get', KEYS[1]) == ARGV[1] then return redis.call('pexpire', KEYS[1], ARGV[2]) end";
+
+
+/// Connection to the shared cache
+pub struct SharedCache {
+    connection: redis::Connection,
+
+    /// Prefix of all keys used by the exporter
+    prefix: String,
+
+    /// Random identifier of this replica, stored in the lock while holding it
+    instance: String,
+}
+
+/// Data frame as published to the shared cache, only including queues that could be parsed
+#[derive(Debug,Serialize,Deserialize)]
+struct SharedFrame {
+    queues: BTreeMap<String, SharedQueue>,
+
+    /// How long it took to scrape the data in seconds
+    scrape_duration: f64,
+
+    /// Time the frame was scraped in seconds since the epoch
+    created_timestamp: f64,
+}
+
+/// Published [QueueDataFrame]
+#[derive(Debug,Serialize,Deserialize)]
+struct SharedQueue {
+    people_waiting: usize,
+    letter: Option<char>,
+    number: usize,
+    waiting_time_estimation: usize,
+    tracked_waiting_time: Option<f64>,
+}
+
+
+impl SharedCache {
+    pub fn open(config: &RedisConfig) -> Result<Self, String> {
+        let connection = redis::Client::open(config.url.as_str())
+            .and_then(|client| client.get_connection_with_timeout(REDIS_TIMEOUT))
+            .map_err(|e| format!("cannot connect to redis: {}", e))?;
+        connection.set_read_timeout(Some(REDIS_TIMEOUT))
+            .and_then(|_| connection.set_write_timeout(Some(REDIS_TIMEOUT)))
+            .map_err(|e| format!("cannot connect to redis: {}", e))?;
+
+        Ok(SharedCache {
+            connection,
+            prefix: config.prefix.clone(),
+            instance: format!("{:016x}", rand::thread_rng().gen::<u64>()),
+        })
+    }
+
+    /// Try to become or stay the replica that scrapes the page for the next `ttl`
+    pub fn acquire(&mut self, ttl: Duration) -> Result<bool, String> {
+        let key = format!("{}:lock", self.prefix);
+        let ttl = ttl.as_millis() as u64;
+        let acquired: Option<String> = redis::cmd("SET").arg(&key).arg(&self.instance).arg("NX").arg("PX").arg(ttl)
+            .query(&mut self.connection)
+            .map_err(|e| format!("cannot acquire redis lock: {}", e))?;
+        if acquired.is_some() {
+            return Ok(true);
+        }
+
+        let renewed: Option<i64> = redis::cmd("EVAL").arg(RENEW_SCRIPT).arg(1).arg(&key).arg(&self.instance).arg(ttl)
+            .query(&mut self.connection)
+            .map_err(|e| format!("cannot renew redis lock: {}", e))?;
+        Ok(renewed == Some(1))
+    }
+
+    /// Publish a data frame and the ticket tracker, expiring after `ttl`
+    pub fn publish(&mut self, data: &DataFrame, state: &State, ttl: Duration) -> Result<(), String> {
+        let queues = data.queues.iter()
+            .filter_map(|(service, queue)| {
+                let queue = queue.as_ref().ok()?;
+                Some((service.clone(), SharedQueue {
+                    people_waiting: queue.people_waiting,
+                    letter: match queue.last_called_ticket.0 {
+                        TicketType::Letter(letter) => Some(letter),
+                        TicketType::None => None,
+                    },
+                    number: queue.last_called_ticket.1,
+                    waiting_time_estimation: queue.waiting_time_estimation,
+                    tracked_waiting_time: queue.tracked_waiting_time.map(|t| t.as_secs_f64()),
+                }))
+            })
+            .collect();
+        let frame = SharedFrame {
+            queues,
+            scrape_duration: data.scrape_duration.as_secs_f64(),
+            created_timestamp: data.created_timestamp.as_secs_f64(),
+        };
+
+        let frame = serde_json::to_string(&frame)
+            .map_err(|e| format!("cannot serialize data frame: {}", e))?;
+        let state = serde_json::to_string(state)
+            .map_err(|e| format!("cannot serialize state: {}", e))?;
+        let ttl = ttl.as_millis() as u64;
+        redis::pipe().atomic()
+            .pset_ex(format!("{}:frame", self.prefix), frame, ttl)
+            .pset_ex(format!("{}:state", self.prefix), state, ttl)
+            .query::<()>(&mut self.connection)
+            .map_err(|e| format!("cannot publish to redis: {}", e))
+    }
+
+    /// Fetch the data frame and ticket tracker published most recently, if they have not expired
+    pub fn fetch(&mut self) -> Result<Option<(DataFrame, State)>, String> {
+        let (frame, state): (Option<String>, Option<String>) = redis::pipe()
+            .get(format!("{}:frame", self.prefix))
+            .get(format!("{}:state", self.prefix))
+            .query(&mut self.connection)
+            .map_err(|e| format!("cannot fetch from redis: {}", e))?;
+        let (Some(frame), Some(state)) = (frame, state) else {
+            return Ok(None);
+        };
+
+        let frame: SharedFrame = serde_json::from_str(&frame)
+            .map_err(|e| format!("cannot parse shared data frame: {}", e))?;
+        let state: State = serde_json::from_str(&state)
+            .map_err(|e| format!("cannot parse shared state: {}", e))?;
+
+        let queues = frame.queues.into_iter()
+            .map(|(service, queue)| {
+                let ticket_type = queue.letter.map_or(TicketType::None, TicketType::Letter);
+                Ok((service, Ok(QueueDataFrame {
+                    people_waiting: queue.people_waiting,
+                    last_called_ticket: Ticket(ticket_type, queue.number),
+                    waiting_time_estimation: queue.waiting_time_estimation,
+                    tracked_waiting_time: queue.tracked_waiting_time
+                        .map(Duration::try_from_secs_f64)
+                        .transpose()
+                        .map_err(|e| format!("invalid shared data frame: {}", e))?,
+                })))
+            })
+            .collect::<Result<_, String>>()?;
+        let created_timestamp = Duration::try_from_secs_f64(frame.created_timestamp).unwrap_or_default();
+        let age = unix_timestamp().saturating_sub(created_timestamp);
+        let data = DataFrame {
+            queues,
+            cached: false,
+            scrape_duration: Duration::try_from_secs_f64(frame.scrape_duration).unwrap_or_default(),
+            created_instant: Instant::now().checked_sub(age).unwrap_or_else(Instant::now),
+            created_timestamp,
+        };
+
+        Ok(Some((data, state)))
+    }
+}
@@ -0,0 +1,78 @@
+//! Notifications via email
+use std::time::Duration;
+
+use lettre::message::header::ContentType;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Message, SmtpTransport, Transport};
+
+use crate::config::{SmtpConfig, SmtpSecurity};
+use crate::notify::{Event, Notifier};
+
+
+/// Timeout for communicating with the mail server
+const SMTP_TIMEOUT: Duration = Duration::from_secs(30);
+
+
+/// Sends notifications as plain-text mails to all configured recipients
+pub struct Smtp {
+    transport: SmtpTransport,
+    from: Mailbox,
+    to: Vec<Mailbox>,
+}
+
+
+impl Smtp {
+    pub fn new(config: &SmtpConfig) -> Result<Self, String> {
+        let mut transport = match config.security {
+            SmtpSecurity::Tls => SmtpTransport::relay(&config.host)
+                .map_err(|e| e.to_string())?,
+            SmtpSecurity::Starttls => SmtpTransport::starttls_relay(&config.host)
+                .map_err(|e| e.to_string())?,
+            SmtpSecurity::None => SmtpTransport::builder_dangerous(&config.host),
+        };
+        if let Some(port) = config.port {
+            transport = transport.port(port);
+        }
+        if let (Some(username), Some(password)) = (&config.username, &config.password) {
+            transport = transport.credentials(Credentials::new(username.clone(), password.clone()));
+        }
+
+        Ok(Smtp {
+            transport: transport.timeout(Some(SMTP_TIMEOUT)).build(),
+            from: parse_mailbox(&config.from)?,
+            to: config.to.iter()
+                .map(|a| parse_mailbox(a))
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+impl Notifier for Smtp {
+    fn name(&self) -> &str {
+        "SMTP"
+    }
+
+    fn notify(&mut self, event: &Event) -> Result<(), String> {
+        let mut message = Message::builder()
+            .from(self.from.clone())
+            .subject(event.title())
+            .header(ContentType::TEXT_PLAIN);
+        for to in &self.to {
+            message = message.to(to.clone());
+        }
+        let message = message.body(event.message())
+            .map_err(|e| e.to_string())?;
+
+        self.transport.send(&message)
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+}
+
+
+/// Parse an address like `Name <user@example.org>`
+fn parse_mailbox(address: &str) -> Result<Mailbox, String> {
+    address.parse()
+        .map_err(|e| format!("invalid mail address '{}': {}", address, e))
+}
@@ -0,0 +1,685 @@
+//! Sources providing the queue data of a town hall
+use std::fs;
+#[cfg(feature = "browser")]
+use std::collections::HashMap;
+#[cfg(feature = "browser")]
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+#[cfg(feature = "browser")]
+use chromiumoxide::browser::BrowserConfig;
+#[cfg(feature = "browser")]
+use chromiumoxide::cdp::browser_protocol::network::{Headers, SetExtraHttpHeadersParams};
+#[cfg(feature = "browser")]
+use chromiumoxide::error::CdpError;
+#[cfg(feature = "browser")]
+use futures::StreamExt;
+use rand::Rng;
+use reqwest::StatusCode;
+use reqwest::header;
+
+use crate::config::{CaptureConfig, Config, Module, RetryConfig};
+use crate::{unix_timestamp, DataFrame, ParseError, QueueDataFrame, ScrapeError, Ticket, UPSTREAM_KEEPALIVE};
+
+
+/// Time to back off after being rate limited without a `Retry-After` header
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(60);
+/// Prefix of the file names of [captured](HtmlSource::capture) pages
+const CAPTURE_PREFIX: &str = "erth-";
+/// Words marking approximate values, which are ignored while parsing
+const APPROXIMATIONS: [&str; 5] = ["ca.", "ca", "circa", "etwa", "~"];
+
+
+/// Provides the current queue data, independent of the [Scraper](crate::Scraper) state
+///
+/// The tracked waiting times of the returned frames are filled in by the scraper.
+pub trait ScrapeSource: Send {
+    /// Fetch new queue data
+    ///
+    /// Returns `None` if the data has not changed since the last fetch.
+    fn fetch(&mut self) -> Result<Option<DataFrame>, ScrapeError>;
+
+    /// Whether the source may be fetched again already
+    fn ready(&self) -> bool {
+        true
+    }
+
+    /// Services that are known in advance, before they have been fetched
+    fn services(&self) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+
+/// Scrapes the wait-time page of a town hall
+#[derive(Debug,Clone)]
+pub struct HtmlSource {
+    /// Page to be scraped followed by its fallbacks, `file://` URLs are read from the local file system
+    urls: Vec<String>,
+
+    /// Parser configuration for the page
+    module: Module,
+
+    /// Retry behavior for fetching [HtmlSource::urls]
+    retry: RetryConfig,
+
+    /// HTTP client keeping connections to the upstream server alive between scrapes
+    client: reqwest::blocking::Client,
+
+    /// Minimum time between two fetches, regardless of how often data is requested
+    min_interval: Duration,
+
+    /// Start of the last fetch
+    last_fetch: Option<Instant>,
+
+    /// `ETag` of the last fetched page, revalidated via `If-None-Match`
+    etag: Option<String>,
+
+    /// `Last-Modified` date of the last fetched page, revalidated via `If-Modified-Since`
+    last_modified: Option<String>,
+
+    /// Index of the URL the validators were received from, they are only sent back to it
+    validated_url: Option<usize>,
+
+    /// Point in time until which each of the [URLs](HtmlSource::urls) asked not to be requested
+    throttled_until: Vec<Option<Instant>>,
+
+    /// Where pages that could not be parsed are stored
+    capture: Option<CaptureConfig>,
+
+    /// Headless Chromium rendering the pages, which are requested directly if unset
+    #[cfg(feature = "browser")]
+    browser: Option<Browser>,
+}
+
+
+/// Renders pages with a headless Chromium, driven via the DevTools protocol
+#[cfg(feature = "browser")]
+#[derive(Debug,Clone)]
+struct Browser {
+    /// Path to the Chromium executable
+    executable: PathBuf,
+
+    /// `User-Agent` the browser identifies with
+    user_agent: String,
+
+    /// [Additional headers](Config::headers) sent with every request of the browser
+    headers: HashMap<String, String>,
+
+    /// Proxy for all requests of the browser
+    proxy: Option<String>,
+}
+
+
+impl HtmlSource {
+    /// Services assumed for blocks without a heading, in the order they appear on the page
+    const SERVICES: [&'static str; 2] = ["citizen", "drivers_license"];
+
+    /// Create a source scraping the configured page and its fallbacks with the configured module
+    pub fn configured(config: &Config) -> Self {
+        let module = config.find_module(&config.module).unwrap_or_default();
+        let urls = [vec![config.url.clone()], config.fallback_urls.clone()].concat();
+        HtmlSource::new(urls, module, config)
+    }
+
+    /// Create a source scraping the first of the `urls` that is available
+    pub fn new(urls: Vec<String>, module: Module, config: &Config) -> Self {
+        let mut client = reqwest::blocking::Client::builder()
+            .tcp_keepalive(UPSTREAM_KEEPALIVE)
+            .pool_idle_timeout(UPSTREAM_KEEPALIVE)
+            .default_headers(config.header_map().expect("Headers are validated with the config"));
+        if let Some(proxy) = &config.proxy {
+            client = client.proxy(reqwest::Proxy::all(proxy).expect("Proxy is validated with the config"));
+        }
+
+        HtmlSource {
+            throttled_until: vec![None; urls.len()],
+            urls, module,
+            retry: config.retry.clone(),
+            capture: config.capture.clone(),
+            #[cfg(feature = "browser")]
+            browser: config.browser.clone().map(|executable| Browser {
+                executable,
+                user_agent: config.user_agent.clone(),
+                headers: config.headers.clone(),
+                proxy: config.proxy.clone(),
+            }),
+            client: client.build()
+                .expect("HTTP client configuration must be valid"),
+            min_interval: Duration::from_secs(config.min_scrape_interval),
+            last_fetch: None,
+            etag: None,
+            last_modified: None,
+            validated_url: None,
+        }
+    }
+
+    /// Fetch the page unconditionally and parse it, returning the page along with the result
+    pub fn fetch_raw(&mut self) -> Result<(String, Result<DataFrame, ScrapeError>), ScrapeError> {
+        self.etag = None;
+        self.last_modified = None;
+        let start = Instant::now();
+        let page = self.fetch_page()?
+            .ok_or_else(|| ScrapeError::Http(String::from("page not modified")))?;
+        let data = self.parse(&page, start);
+        Ok((page, data))
+    }
+
+    /// Fetch the page, retrying with exponential backoff and jitter on failure
+    ///
+    /// Returns `None` if the page has not been modified since the last fetch.
+    fn fetch_page(&mut self) -> Result<Option<String>, ScrapeError> {
+        self.last_fetch = Some(Instant::now());
+        let mut backoff = self.retry.initial_backoff;
+        let mut attempt = 1;
+        loop {
+            match self.fetch_any() {
+                // retrying right away would only get throttled again
+                Err(e) if attempt < self.retry.attempts && !matches!(e, ScrapeError::Throttled(_)) => {
+                    let delay = (backoff * rand::thread_rng().gen_range(0.5..1.5)).min(self.retry.max_backoff);
+                    eprintln!("Warning: {} (retrying in {:.1}s)", e, delay);
+                    thread::sleep(Duration::from_secs_f64(delay));
+                    backoff *= 2.0;
+                    attempt += 1;
+                },
+                result => return result,
+            }
+        }
+    }
+
+    /// Fetch the page from the first of the [URLs](HtmlSource::urls) that is available
+    fn fetch_any(&mut self) -> Result<Option<String>, ScrapeError> {
+        let mut error = None;
+        for index in 0..self.urls.len() {
+            match self.request(index) {
+                Ok(page) => return Ok(page),
+                Err(e) => {
+                    if let Some(next) = self.urls.get(index + 1) {
+                        eprintln!("Warning: {} (falling back to {})", e, next);
+                    }
+                    error = Some(e);
+                },
+            }
+        }
+        Err(error.expect("At least one URL must be configured"))
+    }
+
+    /// Send a single conditional request for the page at the given index of [HtmlSource::urls]
+    fn request(&mut self, index: usize) -> Result<Option<String>, ScrapeError> {
+        let url = &self.urls[index];
+        if let Some(until) = self.throttled_until[index].filter(|t| *t > Instant::now()) {
+            return Err(ScrapeError::Throttled(until - Instant::now()));
+        }
+        if let Some(path) = url.strip_prefix("file://") {
+            return fs::read_to_string(path)
+                .map(Some)
+                .map_err(|e| ScrapeError::Http(format!("cannot read {}: {}", path, e)));
+        }
+        #[cfg(feature = "browser")]
+        if let Some(browser) = &self.browser {
+            return browser.render(url, &self.module.block_content_filter).map(Some);
+        }
+
+        let mut request = self.client.get(url);
+        if self.validated_url == Some(index) {
+            if let Some(etag) = &self.etag {
+                request = request.header(header::IF_NONE_MATCH, etag);
+            }
+            if let Some(last_modified) = &self.last_modified {
+                request = request.header(header::IF_MODIFIED_SINCE, last_modified);
+            }
+        }
+
+        let response = request.send()
+            .map_err(|e| ScrapeError::Http(e.to_string()))?;
+        if response.status() == StatusCode::TOO_MANY_REQUESTS {
+            let delay = response.headers().get(header::RETRY_AFTER)
+                .and_then(|v| v.to_str().ok())
+                .and_then(parse_retry_after)
+                .unwrap_or(DEFAULT_RETRY_AFTER);
+            self.throttled_until[index] = Some(Instant::now() + delay);
+            return Err(ScrapeError::Throttled(delay));
+        }
+
+        let response = response.error_for_status()
+            .map_err(|e| ScrapeError::Http(e.to_string()))?;
+        if response.status() == StatusCode::NOT_MODIFIED {
+            return Ok(None);
+        }
+
+        let validator = |name| response.headers().get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_owned);
+        self.etag = validator(header::ETAG);
+        self.last_modified = validator(header::LAST_MODIFIED);
+        self.validated_url = Some(index);
+
+        response.text()
+            .map(Some)
+            .map_err(|e| ScrapeError::Http(e.to_string()))
+    }
+
+    /// Extract the queues from the page, that was requested at `start`
+    fn parse(&self, page: &str, start: Instant) -> Result<DataFrame, ScrapeError> {
+        let document = scraper::Html::parse_document(page);
+
+        let block_selector = scraper::Selector::parse(&self.module.block_selector)
+            .map_err(|e| ParseError::new(None, &self.module.block_selector, e.to_string(), None))?;
+        let line_selector = scraper::Selector::parse(&self.module.value_selector)
+            .map_err(|e| ParseError::new(None, &self.module.value_selector, e.to_string(), None))?;
+
+        let blocks = document.select(&block_selector)
+            .filter(|b| b.inner_html().contains(&self.module.block_content_filter));
+
+        let heading_selector = scraper::Selector::parse(&self.module.heading_selector)
+            .map_err(|e| ParseError::new(None, &self.module.heading_selector, e.to_string(), None))?;
+
+        let mut data_frames = Vec::new();
+        for (position, block) in blocks.enumerate() {
+            let service = self.identify_queue(position, block, &heading_selector);
+            // a service listed twice is only taken from its first block
+            if !data_frames.iter().any(|(s, _)| *s == service) {
+                let data_frame = Self::parse_queue(position, block, &line_selector, &self.module.value_selector);
+                data_frames.push((service, data_frame));
+            }
+        }
+
+        if data_frames.is_empty() {
+            return Err(ScrapeError::NotEnoughBlocks);
+        }
+        if data_frames.iter().all(|(_, f)| f.is_err()) {
+            return Err(data_frames.swap_remove(0).1.unwrap_err());
+        }
+
+        Ok(DataFrame {
+            queues: data_frames.into_iter().collect(),
+            scrape_duration: start.elapsed(),
+            cached: false,
+            created_instant: Instant::now(),
+            created_timestamp: unix_timestamp(),
+        })
+    }
+
+    /// Store a page that could not be parsed in the [capture directory](CaptureConfig::dir),
+    /// removing the oldest captures beyond [CaptureConfig::keep]
+    fn capture(&self, page: &str) {
+        let Some(capture) = &self.capture else {
+            return;
+        };
+
+        let name = format!("{}.html", capture_name());
+        let result = fs::create_dir_all(&capture.dir)
+            .and_then(|_| fs::write(capture.dir.join(name), page))
+            .and_then(|_| {
+                let mut captures: Vec<_> = fs::read_dir(&capture.dir)?
+                    .flatten()
+                    .map(|e| e.path())
+                    .filter(|p| p.file_name().and_then(|n| n.to_str())
+                        .is_some_and(|n| n.starts_with(CAPTURE_PREFIX) && n.ends_with(".html")))
+                    .collect();
+                captures.sort();
+                let excess = captures.len().saturating_sub(capture.keep);
+                captures.iter().take(excess).try_for_each(fs::remove_file)
+            });
+
+        if let Err(e) = result {
+            eprintln!("Warning: cannot capture page in {}: {}", capture.dir.display(), e);
+        }
+    }
+
+    /// Determine the service name of the queue in a block from its heading
+    ///
+    /// Headings matching one of the [configured headings](Module::headings) use its service name,
+    /// others are turned into a name of their own. Blocks without a heading are assumed to be in the
+    /// order of [HtmlSource::SERVICES].
+    fn identify_queue(&self, position: usize, block: scraper::ElementRef, heading_selector: &scraper::Selector)
+            -> String {
+        let heading: String = block.select(heading_selector).next()
+            .map(|h| h.text().collect())
+            .unwrap_or_default();
+
+        let configured = self.module.headings.iter()
+            .find(|(_, h)| heading.contains(h.as_str()));
+        if let Some((service, _)) = configured {
+            return service.clone();
+        }
+
+        match Self::service_name(&heading) {
+            Some(service) => service,
+            None => Self::SERVICES.get(position)
+                .map(|s| s.to_string())
+                .unwrap_or_else(|| format!("queue_{}", position)),
+        }
+    }
+
+    /// Turn a heading into a service name suitable as label value, like `"Bürgerbüro Süd"` into
+    /// `buergerbuero_sued`
+    fn service_name(heading: &str) -> Option<String> {
+        let mut name = String::new();
+        for c in heading.trim().to_lowercase().chars() {
+            match c {
+                'ä' => name.push_str("ae"),
+                'ö' => name.push_str("oe"),
+                'ü' => name.push_str("ue"),
+                'ß' => name.push_str("ss"),
+                c if c.is_ascii_alphanumeric() => name.push(c),
+                _ if !name.is_empty() && !name.ends_with('_') => name.push('_'),
+                _ => (),
+            }
+        }
+        let name = name.trim_end_matches('_');
+        (!name.is_empty()).then(|| name.to_owned())
+    }
+
+    /// Parse the values of a single queue block
+    ///
+    /// The `index` of the block and the source of the `line_selector` are only used for error reporting.
+    fn parse_queue(index: usize, block: scraper::ElementRef, line_selector: &scraper::Selector, selector: &str)
+            -> Result<QueueDataFrame, ScrapeError> {
+        let values: Vec<_> = block.select(line_selector)
+            .map(|e| e.text().collect::<String>().replace('\u{a0}', " ").trim().to_owned())
+            .collect();
+        let error = |message: &str, raw: &str| {
+            ParseError::new(Some(index), selector, message.to_owned(), Some(raw.to_owned()))
+        };
+        if values.len() < 3 {
+            return Err(error(&format!("expected 3 values, found {}", values.len()), &values.join(" | ")).into());
+        }
+
+        let people_waiting = parse_count(&values[0])
+            .ok_or_else(|| error("cannot parse waiting persons", &values[0]))?;
+        let last_called_ticket = Ticket::parse(&values[1].split_whitespace().collect::<String>())
+            .map_err(|_| error("cannot parse current ticket", &values[1]))?;
+        let waiting_time_estimation = parse_minutes(&values[2])
+            .ok_or_else(|| error("cannot parse waiting-time estimation", &values[2]))?;
+
+        Ok(QueueDataFrame {
+            people_waiting, last_called_ticket, waiting_time_estimation,
+            tracked_waiting_time: None,
+        })
+    }
+}
+
+#[cfg(feature = "browser")]
+impl Browser {
+    /// Time scripts on the page are given to fill in the queues after it has loaded
+    const SCRIPT_TIMEOUT: Duration = Duration::from_secs(10);
+
+    /// Interval at which the rendered page is checked for the queues
+    const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+    /// Load a page and return its DOM once it contains `ready` or the script timeout has passed
+    fn render(&self, url: &str, ready: &str) -> Result<String, ScrapeError> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(|e| ScrapeError::Http(format!("cannot start browser: {}", e)))?;
+        runtime.block_on(self.load(url, ready))
+            .map_err(|e| ScrapeError::Http(format!("rendering {} failed: {}", url, e)))
+    }
+
+    /// Launch Chromium, load the page with the configured headers and close Chromium again
+    async fn load(&self, url: &str, ready: &str) -> Result<String, String> {
+        // a profile of its own, instead of a fixed directory shared by all processes
+        let profile = tempfile::tempdir()
+            .map_err(|e| format!("cannot create browser profile: {}", e))?;
+        let mut config = BrowserConfig::builder()
+            .chrome_executable(&self.executable)
+            .user_data_dir(profile.path());
+        if let Some(proxy) = &self.proxy {
+            config = config.arg(format!("--proxy-server={}", proxy));
+        }
+        let (mut browser, mut handler) = chromiumoxide::Browser::launch(config.build()?).await
+            .map_err(|e| e.to_string())?;
+        let events = tokio::spawn(async move {
+            while handler.next().await.is_some() {}
+        });
+
+        let result: Result<String, CdpError> = async {
+            let page = browser.new_page("about:blank").await?;
+            page.set_user_agent(self.user_agent.as_str()).await?;
+            let headers: serde_json::Map<_, _> = self.headers.iter()
+                .map(|(name, value)| (name.clone(), value.as_str().into()))
+                .collect();
+            page.execute(SetExtraHttpHeadersParams::new(Headers::new(headers))).await?;
+            page.goto(url).await?;
+
+            let start = Instant::now();
+            loop {
+                let html = page.content().await?;
+                if html.contains(ready) || start.elapsed() >= Self::SCRIPT_TIMEOUT {
+                    return Ok(html);
+                }
+                tokio::time::sleep(Self::POLL_INTERVAL).await;
+            }
+        }.await;
+
+        let _ = browser.close().await;
+        let _ = browser.wait().await;
+        events.abort();
+        result.map_err(|e| e.to_string())
+    }
+}
+
+impl ScrapeSource for HtmlSource {
+    fn fetch(&mut self) -> Result<Option<DataFrame>, ScrapeError> {
+        let start = Instant::now();
+        match self.fetch_page()? {
+            Some(page) => {
+                let data = self.parse(&page, start);
+                let failed = data.as_ref().map_or(true, |d| d.queues.values().any(Result::is_err));
+                if failed {
+                    self.capture(&page);
+                }
+                data.map(Some)
+            },
+            // parsing an unchanged page again would yield the same data
+            None => Ok(None),
+        }
+    }
+
+    /// Whether the [minimum scrape interval](Config::min_scrape_interval) has passed since the last fetch
+    fn ready(&self) -> bool {
+        self.last_fetch.is_none_or(|t| t.elapsed() >= self.min_interval)
+    }
+
+    fn services(&self) -> Vec<String> {
+        self.module.headings.keys().cloned().collect()
+    }
+}
+
+
+/// File name of a page captured now, without extension
+pub fn capture_name() -> String {
+    format!("{}{}", CAPTURE_PREFIX, Utc::now().format("%Y%m%dT%H%M%S%.3fZ"))
+}
+
+/// Parse a `Retry-After` header, which is either a number of seconds or an HTTP date
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    if let Ok(seconds) = value.trim().parse() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    Some((date.with_timezone(&Utc) - Utc::now()).to_std().unwrap_or_default())
+}
+
+/// Strip a leading [approximation](APPROXIMATIONS) like `ca.` from a value
+fn strip_approximation(s: &str) -> &str {
+    let s = s.trim();
+    APPROXIMATIONS.iter()
+        .find_map(|a| s.strip_prefix(a).filter(|rest| rest.starts_with(|c: char| !c.is_alphabetic())))
+        .map(str::trim)
+        .unwrap_or(s)
+}
+
+/// Parse a number of people like `"1.234"`, empty values count as zero
+fn parse_count(s: &str) -> Option<usize> {
+    let digits: String = strip_approximation(s).chars()
+        .filter(|c| !matches!(c, '.' | ',' | '\'' | ' '))
+        .collect();
+    match digits.is_empty() {
+        true => Some(0),
+        false => digits.parse().ok(),
+    }
+}
+
+/// Parse a duration like `"ca. 15 Minuten"` or `"1 Stunde 5 Minuten"` into minutes
+///
+/// Numbers without a unit are taken as minutes, empty values count as zero. Of a range like
+/// `"10 - 15 Minuten"` the upper bound is taken.
+fn parse_minutes(s: &str) -> Option<usize> {
+    let mut minutes = 0.0;
+    let mut number: Option<f64> = None;
+    let mut range = false;
+    for token in tokenize(&strip_approximation(s).to_lowercase()) {
+        if let Ok(n) = token.replace(',', ".").parse::<f64>() {
+            minutes += number.replace(n).unwrap_or(0.0);
+        } else if token.starts_with("stund") || token.starts_with("std") || token == "h" {
+            minutes += number.take()? * 60.0;
+        } else if token.starts_with("min") {
+            minutes += number.take()?;
+        } else if token == "-" || token == "bis" {
+            // a dash without a lower bound is a placeholder for no value
+            if minutes > 0.0 || number.is_some() {
+                (minutes, number, range) = (0.0, None, true);
+            }
+        } else if token != "und" {
+            return None;
+        }
+    }
+    if range && minutes == 0.0 && number.is_none() {
+        return None;
+    }
+    minutes += number.unwrap_or(0.0);
+    Some(minutes.round() as usize)
+}
+
+/// Split a value into numbers (including decimal separators), words and dashes, dropping
+/// everything else
+fn tokenize(s: &str) -> Vec<String> {
+    let mut tokens: Vec<String> = Vec::new();
+    // whether the previous character was part of a number, a word, or neither
+    let mut previous = None;
+    for c in s.chars() {
+        if matches!(c, '-' | '–') {
+            tokens.push(String::from("-"));
+            previous = None;
+            continue;
+        }
+        let numeric = c.is_ascii_digit() || (matches!(c, '.' | ',') && previous == Some(true));
+        let class = match c {
+            _ if numeric => Some(true),
+            c if c.is_alphabetic() => Some(false),
+            _ => None,
+        };
+        match (class, tokens.last_mut()) {
+            (Some(class), Some(token)) if previous == Some(class) => token.push(c),
+            (Some(_), _) => tokens.push(c.to_string()),
+            (None, _) => (),
+        }
+        previous = class;
+    }
+    tokens
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Page in the layout of the town hall, with one unrelated block and one block that cannot be parsed
+    const PAGE: &str = r#"<html><body>
+        <div class="fr-view"><h2>Bürgerservice</h2>
+        <p class="flex"><strong>Wartende Personen:</strong> <span>1.234</span></p>
+        <p class="flex"><strong>Aktuelle Aufrufnummer:</strong> <span>B 123</span></p>
+        <p class="flex"><strong>Durchschnittliche Wartezeit:</strong> <span>ca. 1 Stunde 5 Minuten</span></p>
+        </div>
+        <div class="fr-view"><p>Other text</p></div>
+        <div class="fr-view"><h2>Fahrerlaubnisangelegenheiten</h2>
+        <p class="flex"><strong>Wartende Personen:</strong> <span>3</span></p>
+        <p class="flex"><strong>Aktuelle Aufrufnummer:</strong> <span>F045</span></p>
+        <p class="flex"><strong>Durchschnittliche Wartezeit:</strong> <span>10 Minuten</span></p>
+        </div>
+        <div class="fr-view"><h2>Bürgerbüro Süd</h2>
+        <p class="flex"><strong>Wartende Personen:</strong> <span>viele</span></p>
+        <p class="flex"><strong>Aktuelle Aufrufnummer:</strong> <span>S001</span></p>
+        <p class="flex"><strong>Durchschnittliche Wartezeit:</strong> <span>5 Minuten</span></p>
+        </div>
+        </body></html>"#;
+
+    #[test]
+    fn page() {
+        let source = HtmlSource::new(Vec::new(), Module::default(), &Config::default());
+        let data = source.parse(PAGE, Instant::now()).unwrap();
+        assert_eq!(data.queues.keys().collect::<Vec<_>>(), ["buergerbuero_sued", "citizen", "drivers_license"]);
+
+        let citizen = data.queues["citizen"].as_ref().unwrap();
+        assert_eq!(citizen.people_waiting, 1234);
+        assert_eq!(citizen.last_called_ticket.to_string(), "B123");
+        assert_eq!(citizen.waiting_time_estimation, 65);
+        let drivers_license = data.queues["drivers_license"].as_ref().unwrap();
+        assert_eq!(drivers_license.last_called_ticket.to_string(), "F045");
+        assert!(data.queues["buergerbuero_sued"].is_err());
+
+        assert!(matches!(source.parse("<html></html>", Instant::now()), Err(ScrapeError::NotEnoughBlocks)));
+    }
+
+    #[test]
+    fn service_names() {
+        assert_eq!(HtmlSource::service_name("Bürgerbüro Süd").as_deref(), Some("buergerbuero_sued"));
+        assert_eq!(HtmlSource::service_name(" Straße & Verkehr: ").as_deref(), Some("strasse_verkehr"));
+        assert_eq!(HtmlSource::service_name(" - ").as_deref(), None);
+    }
+
+    #[test]
+    fn counts() {
+        assert_eq!(parse_count("12"), Some(12));
+        assert_eq!(parse_count("1.234"), Some(1234));
+        assert_eq!(parse_count("ca. 5"), Some(5));
+        assert_eq!(parse_count(""), Some(0));
+        assert_eq!(parse_count("viele"), None);
+        assert_eq!(parse_count("castle"), None);
+    }
+
+    #[test]
+    fn minutes() {
+        assert_eq!(parse_minutes("25 Minuten"), Some(25));
+        assert_eq!(parse_minutes("ca. 15 Min."), Some(15));
+        assert_eq!(parse_minutes("~ 20"), Some(20));
+        assert_eq!(parse_minutes("1 Stunde 5 Minuten"), Some(65));
+        assert_eq!(parse_minutes("2 Std. und 30 Min."), Some(150));
+        assert_eq!(parse_minutes("1,5 h"), Some(90));
+        assert_eq!(parse_minutes(""), Some(0));
+        assert_eq!(parse_minutes("-"), Some(0));
+        assert_eq!(parse_minutes("Minuten"), None);
+        assert_eq!(parse_minutes("unbekannt"), None);
+    }
+
+    #[test]
+    fn minute_ranges() {
+        assert_eq!(parse_minutes("10 - 15 Minuten"), Some(15));
+        assert_eq!(parse_minutes("ca. 5-10 Min."), Some(10));
+        assert_eq!(parse_minutes("20–30"), Some(30));
+        assert_eq!(parse_minutes("10 bis 20 Minuten"), Some(20));
+        assert_eq!(parse_minutes("1 - 2 Stunden"), Some(120));
+        assert_eq!(parse_minutes("45 Minuten - 1 Stunde"), Some(60));
+        assert_eq!(parse_minutes("10 -"), None);
+    }
+
+    #[test]
+    fn tokens() {
+        assert_eq!(tokenize("1,5 std."), ["1,5", "std"]);
+        assert_eq!(tokenize("ca.15min"), ["ca", "15", "min"]);
+        assert_eq!(tokenize(".5 - 10"), ["5", "-", "10"]);
+        assert_eq!(tokenize("5–10 min"), ["5", "-", "10", "min"]);
+    }
+
+    #[test]
+    fn retry_after() {
+        assert_eq!(parse_retry_after(" 120 "), Some(Duration::from_secs(120)));
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), Some(Duration::ZERO));
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+}
@@ -0,0 +1,127 @@
+//! Persistence of the ticket tracker across restarts
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{unix_timestamp, QueueState, Ticket, TicketType, TrackedTicket};
+
+
+/// Tracker state as stored in the [state file](crate::config::Config::state_file)
+#[derive(Debug,Default,Serialize,Deserialize)]
+pub struct State {
+    /// State of each queue by service name
+    #[serde(default)]
+    queues: HashMap<String, QueueRecord>,
+}
+
+/// Persisted part of a [QueueState]
+#[derive(Debug,Serialize,Deserialize)]
+struct QueueRecord {
+    /// Letter of the tickets called in the queue
+    ticket_type: Option<char>,
+
+    /// Last called ticket number
+    last_called_number: Option<usize>,
+
+    /// Last observed queue length
+    last_queue_length: Option<usize>,
+
+    /// Tickets whose waiting time is being tracked
+    #[serde(default)]
+    tickets: Vec<TicketRecord>,
+}
+
+/// Persisted [TrackedTicket]
+#[derive(Debug,Serialize,Deserialize)]
+struct TicketRecord {
+    letter: char,
+    number: usize,
+
+    /// Time the ticket was first expected to be drawn in seconds since the epoch
+    since: f64,
+
+    /// Waiting time estimation of the town hall in minutes at that time
+    waiting_time_estimation: usize,
+}
+
+
+impl State {
+    /// Read the state from a TOML file, a missing file yields an empty state
+    pub fn load(path: &Path) -> Result<Self, String> {
+        match fs::read_to_string(path) {
+            Ok(content) => toml::from_str(&content)
+                .map_err(|e| format!("cannot parse state file {}: {}", path.display(), e)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(State::default()),
+            Err(e) => Err(format!("cannot read state file {}: {}", path.display(), e)),
+        }
+    }
+
+    /// Write the state to a TOML file, replacing it atomically
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let content = toml::to_string(self)
+            .map_err(|e| format!("cannot serialize state: {}", e))?;
+        let temporary = path.with_extension("tmp");
+        fs::write(&temporary, content)
+            .and_then(|_| fs::rename(&temporary, path))
+            .map_err(|e| format!("cannot write state file {}: {}", path.display(), e))
+    }
+
+    /// Take a snapshot of the tracking state of all queues
+    pub fn capture(queues: &HashMap<String, QueueState>) -> Self {
+        let now = unix_timestamp().as_secs_f64();
+        let queues = queues.iter()
+            .map(|(service, queue)| {
+                let tickets = queue.ticket_tracker.iter()
+                    .filter_map(|(ticket, tracked)| match ticket.0 {
+                        TicketType::Letter(letter) => Some(TicketRecord {
+                            letter,
+                            number: ticket.1,
+                            since: now - tracked.since.elapsed().as_secs_f64(),
+                            waiting_time_estimation: tracked.waiting_time_estimation,
+                        }),
+                        TicketType::None => None,
+                    })
+                    .collect();
+                let record = QueueRecord {
+                    ticket_type: match queue.ticket_type {
+                        Some(TicketType::Letter(letter)) => Some(letter),
+                        _ => None,
+                    },
+                    last_called_number: queue.last_called_number,
+                    last_queue_length: queue.last_queue_length,
+                    tickets,
+                };
+                (service.clone(), record)
+            })
+            .collect();
+
+        State { queues }
+    }
+
+    /// Restore the tracking state of the queues
+    ///
+    /// Rates are not persisted, so they start from scratch.
+    pub fn restore(self, queues: &mut HashMap<String, QueueState>, service_rate_window: Duration) {
+        let now = unix_timestamp().as_secs_f64();
+        for (service, record) in self.queues {
+            let queue = queues.entry(service)
+                .or_insert_with(|| QueueState::new(service_rate_window));
+            queue.ticket_type = record.ticket_type.map(TicketType::Letter);
+            queue.last_called_number = record.last_called_number;
+            queue.last_queue_length = record.last_queue_length;
+            queue.ticket_tracker = record.tickets.into_iter()
+                .map(|t| {
+                    let age = Duration::try_from_secs_f64(now - t.since).unwrap_or_default();
+                    let tracked = TrackedTicket {
+                        since: Instant::now().checked_sub(age).unwrap_or_else(Instant::now),
+                        waiting_time_estimation: t.waiting_time_estimation,
+                    };
+                    (Ticket(TicketType::Letter(t.letter), t.number), tracked)
+                })
+                .collect();
+        }
+    }
+}
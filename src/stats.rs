@@ -0,0 +1,256 @@
+//! Daily and weekly statistics of the stored history
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{DateTime, Datelike, TimeDelta, Timelike};
+use chrono_tz::Tz;
+
+use crate::api::{Aggregate, ForecastPoint, ServiceStats};
+use crate::history::HistoryFrame;
+
+
+/// Share of the current deviation from the baseline that persists after each hour of the forecast
+const DEVIATION_DECAY: f64 = 0.5;
+
+
+/// Samples of a service grouped by day or weekday
+type Groups = BTreeMap<String, Vec<Point>>;
+
+/// Sum and count of the waiting times of a service by [hour of the week](hour_of_week)
+type Baseline = HashMap<(u32, u32), (f64, usize)>;
+
+/// Values of a stored sample relevant for the statistics
+#[derive(Debug,Clone,Copy)]
+struct Point {
+    /// Hour of the day in the configured time zone
+    hour: u32,
+
+    /// Waiting time estimation of the town hall in seconds
+    wait: f64,
+
+    people_waiting: usize,
+}
+
+/// Compute the statistics of each service from stored frames, grouping them by day and weekday in
+/// the given time zone
+pub fn compute(frames: &[HistoryFrame], timezone: &Tz) -> BTreeMap<String, ServiceStats> {
+    let mut days: HashMap<&str, Groups> = HashMap::new();
+    let mut weekdays: HashMap<&str, Groups> = HashMap::new();
+    for frame in frames {
+        let Some(time) = local_time(frame.timestamp, timezone) else {
+            continue;
+        };
+        for (service, sample) in &frame.queues {
+            let point = Point {
+                hour: time.hour(),
+                wait: sample.waiting_time_seconds,
+                people_waiting: sample.people_waiting,
+            };
+            days.entry(service).or_default()
+                .entry(time.format("%Y-%m-%d").to_string()).or_default()
+                .push(point);
+            weekdays.entry(service).or_default()
+                .entry(time.weekday().to_string()).or_default()
+                .push(point);
+        }
+    }
+
+    days.into_iter()
+        .map(|(service, days)| {
+            let stats = ServiceStats {
+                days: aggregate_groups(days),
+                weekdays: aggregate_groups(weekdays.remove(service).unwrap_or_default()),
+            };
+            (service.to_owned(), stats)
+        })
+        .collect()
+}
+
+/// Aggregate the samples of each group
+fn aggregate_groups(groups: Groups) -> BTreeMap<String, Aggregate> {
+    groups.into_iter()
+        .map(|(key, samples)| (key, aggregate(samples)))
+        .collect()
+}
+
+/// Aggregate a non-empty list of samples
+fn aggregate(points: Vec<Point>) -> Aggregate {
+    let mut waits: Vec<_> = points.iter().map(|p| p.wait).collect();
+    waits.sort_by(f64::total_cmp);
+    let median = match waits.len() % 2 {
+        0 => (waits[waits.len() / 2 - 1] + waits[waits.len() / 2]) / 2.0,
+        _ => waits[waits.len() / 2],
+    };
+
+    let mut hours: BTreeMap<u32, (usize, usize)> = BTreeMap::new();
+    for point in &points {
+        let (sum, count) = hours.entry(point.hour).or_default();
+        *sum += point.people_waiting;
+        *count += 1;
+    }
+    let busiest_hour = hours.into_iter()
+        .map(|(hour, (sum, count))| (hour, sum as f64 / count as f64))
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|(hour, _)| hour)
+        .unwrap_or_default();
+
+    Aggregate {
+        samples: points.len(),
+        average_wait_seconds: waits.iter().sum::<f64>() / waits.len() as f64,
+        median_wait_seconds: median,
+        max_wait_seconds: waits.last().copied().unwrap_or_default(),
+        busiest_hour,
+    }
+}
+
+/// Forecast the waiting time of each service for the next `hours` hours
+///
+/// The baseline is the average waiting time at the same hour of the week in the stored history,
+/// corrected by the deviation of the latest frame from its baseline, which decays over time.
+pub fn forecast(frames: &[HistoryFrame], timezone: &Tz, hours: u32) -> BTreeMap<String, Vec<ForecastPoint>> {
+    let mut baselines: HashMap<&str, Baseline> = HashMap::new();
+    for frame in frames {
+        let Some(time) = local_time(frame.timestamp, timezone) else {
+            continue;
+        };
+        for (service, sample) in &frame.queues {
+            let (sum, count) = baselines.entry(service).or_default()
+                .entry(hour_of_week(&time)).or_default();
+            *sum += sample.waiting_time_seconds;
+            *count += 1;
+        }
+    }
+
+    let latest = frames.last()
+        .and_then(|f| Some((local_time(f.timestamp, timezone)?, f)));
+    baselines.into_iter()
+        .map(|(service, baseline)| {
+            let baseline_at = |time: &DateTime<Tz>| baseline.get(&hour_of_week(time))
+                .map(|(sum, count)| sum / *count as f64);
+            let deviation = latest
+                .and_then(|(time, frame)| Some(frame.queues.get(service)?.waiting_time_seconds - baseline_at(&time)?))
+                .unwrap_or_default();
+            let start = latest.map(|(time, _)| time)
+                .unwrap_or_else(|| chrono::Utc::now().with_timezone(timezone));
+
+            let points = (1..=hours)
+                .map(|h| {
+                    let time = start + TimeDelta::hours(h as i64);
+                    let expected = baseline_at(&time)
+                        .map(|b| (b + deviation * DEVIATION_DECAY.powi(h as i32)).max(0.0));
+                    ForecastPoint {
+                        time: time.to_rfc3339(),
+                        expected_wait_seconds: expected,
+                    }
+                })
+                .collect();
+            (service.to_owned(), points)
+        })
+        .collect()
+}
+
+/// Convert seconds since the epoch to the given time zone
+fn local_time(timestamp: f64, timezone: &Tz) -> Option<DateTime<Tz>> {
+    DateTime::from_timestamp_millis((timestamp * 1000.0) as i64)
+        .map(|t| t.with_timezone(timezone))
+}
+
+/// Weekday and hour of a point in time
+fn hour_of_week(time: &DateTime<Tz>) -> (u32, u32) {
+    (time.weekday().num_days_from_monday(), time.hour())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use chrono_tz::Europe::Berlin;
+
+    use crate::history::Sample;
+
+    use super::*;
+
+    /// Monday, 2024-01-01 09:00 in Berlin
+    const MONDAY: f64 = 1704096000.0;
+    const HOUR: f64 = 60.0 * 60.0;
+    const WEEK: f64 = 7.0 * 24.0 * HOUR;
+
+    fn frame(timestamp: f64, service: &str, wait: f64, people_waiting: usize) -> HistoryFrame {
+        HistoryFrame {
+            timestamp,
+            queues: [(service.to_owned(), Sample {
+                people_waiting,
+                last_called_ticket: String::from("B001"),
+                waiting_time_seconds: wait,
+                tracked_waiting_time_seconds: None,
+            })].into(),
+        }
+    }
+
+    #[test]
+    fn empty_history() {
+        assert!(compute(&[], &Berlin).is_empty());
+    }
+
+    #[test]
+    fn days_and_weekdays() {
+        let frames = [
+            frame(MONDAY, "citizen", 600.0, 2),
+            frame(MONDAY + 0.5 * HOUR, "citizen", 1200.0, 4),
+            frame(MONDAY + HOUR, "citizen", 300.0, 10),
+            // 00:30 on Tuesday in Berlin, but still Monday in UTC
+            frame(MONDAY + 15.5 * HOUR, "drivers_license", 60.0, 1),
+            frame(MONDAY + WEEK, "citizen", 900.0, 1),
+        ];
+        let stats = compute(&frames, &Berlin);
+        assert_eq!(stats.keys().collect::<Vec<_>>(), ["citizen", "drivers_license"]);
+
+        let citizen = &stats["citizen"];
+        assert_eq!(citizen.days.keys().collect::<Vec<_>>(), ["2024-01-01", "2024-01-08"]);
+        let day = &citizen.days["2024-01-01"];
+        assert_eq!(day.samples, 3);
+        assert_eq!(day.average_wait_seconds, 700.0);
+        assert_eq!(day.median_wait_seconds, 600.0);
+        assert_eq!(day.max_wait_seconds, 1200.0);
+        assert_eq!(day.busiest_hour, 10);
+
+        let monday = &citizen.weekdays["Mon"];
+        assert_eq!(monday.samples, 4);
+        assert_eq!(monday.median_wait_seconds, 750.0);
+        assert_eq!(monday.busiest_hour, 10);
+
+        let drivers_license = &stats["drivers_license"];
+        assert_eq!(drivers_license.days.keys().collect::<Vec<_>>(), ["2024-01-02"]);
+        assert_eq!(drivers_license.weekdays.keys().collect::<Vec<_>>(), ["Tue"]);
+        assert_eq!(drivers_license.days["2024-01-02"].busiest_hour, 0);
+    }
+    #[test]
+    fn forecast_without_history() {
+        assert!(forecast(&[], &Berlin, 3).is_empty());
+    }
+
+    #[test]
+    fn forecast_deviation() {
+        let frames = [
+            frame(MONDAY, "citizen", 600.0, 2),
+            frame(MONDAY + HOUR, "citizen", 1200.0, 4),
+            frame(MONDAY + WEEK, "citizen", 1000.0, 3),
+        ];
+        let points = &forecast(&frames, &Berlin, 2)["citizen"];
+        assert_eq!(points.len(), 2);
+        assert_eq!(points[0].time, "2024-01-08T10:00:00+01:00");
+        // baseline of 10:00 plus half of the deviation of 200 from the baseline of 09:00
+        assert_eq!(points[0].expected_wait_seconds, Some(1300.0));
+        assert_eq!(points[1].time, "2024-01-08T11:00:00+01:00");
+        assert_eq!(points[1].expected_wait_seconds, None);
+    }
+
+    #[test]
+    fn forecast_not_negative() {
+        let frames = [
+            frame(MONDAY, "citizen", 2000.0, 2),
+            frame(MONDAY + HOUR, "citizen", 100.0, 4),
+            frame(MONDAY + WEEK, "citizen", 0.0, 0),
+        ];
+        let points = &forecast(&frames, &Berlin, 1)["citizen"];
+        assert_eq!(points[0].expected_wait_seconds, Some(0.0));
+    }
+}
@@ -0,0 +1,142 @@
+//! Pushing metrics via StatsD, optionally with DogStatsD tags
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::net::{ToSocketAddrs, UdpSocket};
+
+use prometheus::proto::{Metric, MetricFamily, MetricType};
+
+use crate::config::StatsdConfig;
+use crate::history::HistoryFrame;
+use crate::push::{self, Sink};
+
+
+/// Maximum payload of a single datagram, chosen to fit into the MTU of common networks
+const MAX_PACKET_SIZE: usize = 1432;
+
+
+/// Sends the metrics to a StatsD server
+///
+/// Gauges are sent as StatsD gauges. Counters, as well as the sums and counts of summaries and
+/// histograms, are sent as StatsD counters incremented by the difference to the previous push.
+/// Labels are either sent as DogStatsD tags or appended to the metric name as pairs of name and
+/// value, e.g. `erth.erth_people_waiting.service.citizen`.
+pub struct Statsd {
+    config: StatsdConfig,
+
+    /// Values of the counters at the previous push, by metric line without value
+    counters: HashMap<String, f64>,
+}
+
+
+impl Statsd {
+    pub fn new(config: &StatsdConfig) -> Self {
+        Statsd {
+            config: config.clone(),
+            counters: HashMap::new(),
+        }
+    }
+
+    /// Render the metrics as StatsD lines
+    fn lines(&mut self, families: &[MetricFamily]) -> Vec<String> {
+        let mut lines = Vec::new();
+        for family in families {
+            for metric in family.get_metric() {
+                let values = match family.get_field_type() {
+                    MetricType::COUNTER => vec![("", "c", metric.get_counter().value())],
+                    MetricType::GAUGE => vec![("", "g", metric.get_gauge().value())],
+                    MetricType::UNTYPED => vec![("", "g", metric.untyped.value())],
+                    MetricType::SUMMARY => vec![
+                        ("_sum", "c", metric.get_summary().sample_sum()),
+                        ("_count", "c", metric.get_summary().sample_count() as f64),
+                    ],
+                    MetricType::HISTOGRAM => vec![
+                        ("_sum", "c", metric.get_histogram().sample_sum()),
+                        ("_count", "c", metric.get_histogram().sample_count() as f64),
+                    ],
+                };
+                for (suffix, kind, value) in values {
+                    if !value.is_finite() {
+                        continue;
+                    }
+                    let (name, tags) = self.name(&format!("{}{}", family.name(), suffix), metric);
+                    let value = match kind {
+                        "c" => {
+                            let key = format!("{}{}", name, tags);
+                            let previous = self.counters.insert(key, value).unwrap_or(0.0);
+                            // A decreasing counter was reset, so all of its value is new
+                            let delta = if value >= previous { value - previous } else { value };
+                            if delta == 0.0 {
+                                continue;
+                            }
+                            delta
+                        },
+                        _ => value,
+                    };
+                    lines.push(format!("{}:{}|{}{}", name, value, kind, tags));
+                }
+            }
+        }
+        lines
+    }
+
+    /// Metric name of a series and its tags, with the labels either appended to the name or
+    /// rendered as DogStatsD tags
+    fn name(&self, name: &str, metric: &Metric) -> (String, String) {
+        let mut path = match self.config.prefix.is_empty() {
+            true => push::sanitize(name, &[]),
+            false => format!("{}.{}", self.config.prefix, push::sanitize(name, &[])),
+        };
+        let mut tags = String::new();
+        for label in metric.get_label() {
+            if self.config.tags {
+                tags.push(if tags.is_empty() { '#' } else { ',' });
+                let _ = write!(tags, "{}:{}", push::sanitize(label.name(), &[]), push::sanitize(label.value(), &[]));
+            } else {
+                let _ = write!(path, ".{}.{}", push::sanitize(label.name(), &[]), push::sanitize(label.value(), &[]));
+            }
+        }
+        if !tags.is_empty() {
+            tags.insert(0, '|');
+        }
+        (path, tags)
+    }
+}
+
+impl Sink for Statsd {
+    fn name(&self) -> &str {
+        "StatsD"
+    }
+
+    fn push(&mut self, _frame: &HistoryFrame, families: &[MetricFamily]) -> Result<(), String> {
+        let address = (self.config.host.as_str(), self.config.port).to_socket_addrs()
+            .map_err(|e| e.to_string())?
+            .next()
+            .ok_or_else(|| format!("cannot resolve {}", self.config.host))?;
+        let local = match address.is_ipv4() {
+            true => "0.0.0.0:0",
+            false => "[::]:0",
+        };
+        let socket = UdpSocket::bind(local)
+            .map_err(|e| e.to_string())?;
+        socket.connect(address)
+            .map_err(|e| e.to_string())?;
+
+        let mut packet = String::new();
+        for line in self.lines(families) {
+            if !packet.is_empty() && packet.len() + 1 + line.len() > MAX_PACKET_SIZE {
+                socket.send(packet.as_bytes())
+                    .map_err(|e| e.to_string())?;
+                packet.clear();
+            }
+            if !packet.is_empty() {
+                packet.push('\n');
+            }
+            packet.push_str(&line);
+        }
+        if !packet.is_empty() {
+            socket.send(packet.as_bytes())
+                .map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,77 @@
+//! Human-readable status page served on `/status` and status line served on `/now`
+use std::fmt::Write;
+
+use chrono::DateTime;
+
+use crate::api::Queues;
+
+
+/// Style sheet of the status page
+const STYLE: &str = "body{font-family:sans-serif;max-width:40em;margin:2em auto;padding:0 1em;color:#222}\
+    table{border-collapse:collapse;width:100%}\
+    th,td{text-align:left;padding:.4em;border-bottom:1px solid #ddd}\
+    td.num{text-align:right}\
+    .meta{color:#666;font-size:.9em}\
+    .error{color:#b00}";
+
+
+/// Render the status page, reloading itself every `refresh` seconds
+pub fn render(queues: Option<&Queues>, refresh: u64) -> String {
+    let mut body = String::new();
+    match queues {
+        Some(queues) => {
+            body.push_str("<table>\n<tr><th>Service</th><th>Waiting</th><th>Last called</th>\
+                <th>Waiting time</th><th>New ticket</th></tr>\n");
+            for (service, queue) in &queues.queues {
+                let _ = writeln!(body, "<tr><td>{}</td><td class=\"num\">{}</td><td>{}</td><td class=\"num\">{}</td><td class=\"num\">{}</td></tr>",
+                    escape(service), queue.people_waiting, escape(&queue.last_called_ticket),
+                    format_minutes(Some(queue.waiting_time_seconds)),
+                    format_minutes(queue.new_ticket_wait_estimate_seconds));
+            }
+            body.push_str("</table>\n");
+            for (service, error) in &queues.errors {
+                let _ = writeln!(body, "<p class=\"error\">{}: {}</p>", escape(service), escape(error));
+            }
+            let scraped_at = DateTime::parse_from_rfc3339(&queues.scraped_at)
+                .map_or_else(|_| queues.scraped_at.clone(), |t| t.format("%Y-%m-%d %H:%M:%S").to_string());
+            let _ = writeln!(body, "<p class=\"meta\">Data from {} ({} s old){}</p>", escape(&scraped_at),
+                queues.age_seconds.round(), if queues.stale { ", scraping currently fails" } else { "" });
+        },
+        None => body.push_str("<p>No data scraped yet.</p>\n"),
+    }
+
+    format!("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+        <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+        <meta http-equiv=\"refresh\" content=\"{}\">\n<title>Bürgeramt waiting times</title>\n\
+        <style>{}</style>\n</head>\n<body>\n<h1>Bürgeramt waiting times</h1>\n{}</body>\n</html>\n",
+        refresh, STYLE, body)
+}
+
+/// Render the state of all queues in a single line, e.g. `citizen: 12 waiting, ~25 min`
+pub fn line(queues: Option<&Queues>) -> String {
+    let Some(queues) = queues.filter(|q| !q.queues.is_empty()) else {
+        return String::from("no data");
+    };
+    queues.queues.iter()
+        .map(|(service, queue)| format!("{}: {} waiting, ~{} min", service, queue.people_waiting,
+            (queue.waiting_time_seconds / 60.0).round()))
+        .collect::<Vec<_>>()
+        .join(" | ")
+}
+
+
+/// Format a duration in seconds as whole minutes, or a dash if unknown
+fn format_minutes(seconds: Option<f64>) -> String {
+    match seconds {
+        Some(seconds) => format!("{} min", (seconds / 60.0).round()),
+        None => String::from("–"),
+    }
+}
+
+/// Escape text for use in HTML
+pub fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
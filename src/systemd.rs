@@ -0,0 +1,79 @@
+//! Readiness notifications and watchdog pings for systemd services, see `sd_notify(3)`
+use std::env;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::Scraper;
+
+
+/// Interval at which the scraper is checked for its first data frame before notifying readiness
+const READY_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+
+/// Pings the systemd watchdog, if enabled for the service
+pub struct Watchdog {
+    /// Time between two pings, half the watchdog timeout
+    interval: Duration,
+
+    last_ping: Instant,
+}
+
+
+impl Watchdog {
+    /// Watchdog configured by systemd via `WATCHDOG_USEC`, if it is meant for this process
+    pub fn from_env() -> Option<Self> {
+        let timeout: u64 = env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+        let pid = env::var("WATCHDOG_PID").ok().and_then(|p| p.parse::<u32>().ok());
+        if pid.is_some_and(|pid| pid != process::id()) || timeout == 0 {
+            return None;
+        }
+        Some(Watchdog {
+            interval: Duration::from_micros(timeout / 2),
+            last_ping: Instant::now(),
+        })
+    }
+
+    /// Ping the watchdog if the last ping is half the timeout ago
+    pub fn ping_if_due(&mut self) {
+        if self.last_ping.elapsed() >= self.interval {
+            notify("WATCHDOG=1");
+            self.last_ping = Instant::now();
+        }
+    }
+}
+
+
+/// Notify systemd that the service is ready as soon as the first data frame was scraped, in a
+/// background thread
+pub fn notify_ready(scraper: Arc<Mutex<Scraper>>) {
+    if env::var_os("NOTIFY_SOCKET").is_none() {
+        return;
+    }
+    thread::spawn(move || {
+        while scraper.lock().unwrap().cache.is_none() {
+            thread::sleep(READY_POLL_INTERVAL);
+        }
+        notify("READY=1");
+    });
+}
+
+/// Send a state to the notification socket of systemd, if the service has one
+pub fn notify(state: &str) {
+    let Some(path) = env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+    let path = path.to_string_lossy();
+    let address = match path.strip_prefix('@') {
+        Some(name) => SocketAddr::from_abstract_name(name),
+        None => SocketAddr::from_pathname(path.as_ref()),
+    };
+    let result = UnixDatagram::unbound()
+        .and_then(|socket| socket.send_to_addr(state.as_bytes(), &address?));
+    if let Err(e) = result {
+        eprintln!("Warning: cannot notify systemd: {}", e);
+    }
+}
@@ -0,0 +1,170 @@
+//! Notifications and queue queries via a Telegram bot
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::{Config, TelegramConfig};
+use crate::history::HistoryFrame;
+use crate::notify::{self, Event, Notifier};
+use crate::push;
+use crate::Scraper;
+
+
+/// Time in seconds the bot API holds a request for updates open if there are none
+const POLL_TIMEOUT: u64 = 30;
+
+/// Time to wait before polling again after polling failed
+const POLL_BACKOFF: Duration = Duration::from_secs(10);
+
+
+/// Sends notifications to all configured chats
+pub struct Telegram {
+    bot: Bot,
+}
+
+/// Client of the bot API
+#[derive(Clone)]
+struct Bot {
+    config: TelegramConfig,
+    client: reqwest::blocking::Client,
+}
+
+/// Response envelope of the bot API
+#[derive(Deserialize)]
+struct Response<T> {
+    ok: bool,
+    result: Option<T>,
+    description: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Update {
+    update_id: i64,
+    message: Option<Message>,
+}
+
+#[derive(Deserialize)]
+struct Message {
+    chat: Chat,
+    text: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Chat {
+    id: i64,
+}
+
+#[derive(Serialize)]
+struct SendMessage<'a> {
+    chat_id: i64,
+    text: &'a str,
+}
+
+
+impl Telegram {
+    pub fn new(config: &Config, telegram: &TelegramConfig) -> Self {
+        Telegram {
+            bot: Bot::new(config, telegram),
+        }
+    }
+
+    /// Answer `/wait` commands sent to the bot from the configured chats in a background thread
+    pub fn spawn_commands(&self, scraper: Arc<Mutex<Scraper>>) {
+        let bot = self.bot.clone();
+        thread::spawn(move || {
+            let mut offset = 0;
+            loop {
+                let updates = match bot.updates(offset) {
+                    Ok(updates) => updates,
+                    Err(e) => {
+                        eprintln!("Warning: cannot fetch Telegram updates: {}", e);
+                        thread::sleep(POLL_BACKOFF);
+                        continue;
+                    },
+                };
+
+                for update in updates {
+                    offset = offset.max(update.update_id + 1);
+                    let Some(message) = update.message else {
+                        continue;
+                    };
+                    let is_wait = message.text.as_deref()
+                        .and_then(|t| t.split_whitespace().next())
+                        .is_some_and(|c| c == "/wait" || c.starts_with("/wait@"));
+                    if !is_wait || !bot.config.chat_ids.contains(&message.chat.id) {
+                        continue;
+                    }
+
+                    let frame = scraper.lock().unwrap().cache.as_ref().map(HistoryFrame::from);
+                    let text = match frame {
+                        Some(frame) => notify::status(&frame),
+                        None => String::from("No data scraped yet"),
+                    };
+                    if let Err(e) = bot.send(message.chat.id, &text) {
+                        eprintln!("Warning: cannot answer Telegram command: {}", e);
+                    }
+                }
+            }
+        });
+    }
+}
+
+impl Notifier for Telegram {
+    fn name(&self) -> &str {
+        "Telegram"
+    }
+
+    fn notify(&mut self, event: &Event) -> Result<(), String> {
+        let text = format!("{}\n{}", event.title(), event.message());
+        for chat_id in &self.bot.config.chat_ids {
+            self.bot.send(*chat_id, &text)?;
+        }
+        Ok(())
+    }
+}
+
+impl Bot {
+    fn new(config: &Config, telegram: &TelegramConfig) -> Self {
+        Bot {
+            config: telegram.clone(),
+            client: push::client(config),
+        }
+    }
+
+    /// Call a method of the bot API
+    fn call<T: for<'de> Deserialize<'de>>(&self, request: reqwest::blocking::RequestBuilder) -> Result<T, String> {
+        let response = request.send()
+            .and_then(|r| r.text())
+            .map_err(|e| e.without_url().to_string())?;
+        let response: Response<T> = serde_json::from_str(&response)
+            .map_err(|e| e.to_string())?;
+        match (response.ok, response.result) {
+            (true, Some(result)) => Ok(result),
+            _ => Err(response.description.unwrap_or_else(|| String::from("unknown error"))),
+        }
+    }
+
+    fn method_url(&self, method: &str) -> String {
+        format!("{}/bot{}/{}", self.config.api_url.trim_end_matches('/'), self.config.token, method)
+    }
+
+    fn send(&self, chat_id: i64, text: &str) -> Result<(), String> {
+        let body = serde_json::to_vec(&SendMessage { chat_id, text })
+            .map_err(|e| e.to_string())?;
+        let request = self.client.post(self.method_url("sendMessage"))
+            .header("Content-Type", "application/json")
+            .body(body);
+        self.call::<serde_json::Value>(request)
+            .map(|_| ())
+    }
+
+    /// Wait for new updates starting at `offset`
+    fn updates(&self, offset: i64) -> Result<Vec<Update>, String> {
+        let request = self.client.get(self.method_url("getUpdates"))
+            .query(&[("offset", offset.to_string()), ("timeout", POLL_TIMEOUT.to_string())])
+            .timeout(Duration::from_secs(POLL_TIMEOUT + 10));
+        self.call(request)
+    }
+}
@@ -0,0 +1,31 @@
+//! Writing the metrics for the textfile collector of node_exporter
+use std::fs;
+use std::path::Path;
+
+use crate::config::Config;
+use crate::{metrics, Scraper};
+
+
+/// Name of the file written to the textfile directory
+const FILE_NAME: &str = concat!(env!("CARGO_PKG_NAME"), ".prom");
+
+
+/// Scrape the page once and write the metrics as served on `/metrics` to `erth-exporter.prom` in
+/// the given directory
+///
+/// The file is replaced atomically, so that the collector never reads a partially written file.
+pub fn write(config: &Config, dir: &Path) -> Result<(), String> {
+    let mut scraper = Scraper::open(config)?;
+    let content = metrics::encode(scraper.metrics(true), &[]);
+
+    let path = dir.join(FILE_NAME);
+    // the collector ignores files without the `.prom` extension
+    let temporary = dir.join(format!(".{}.{}.tmp", FILE_NAME, std::process::id()));
+    fs::write(&temporary, content)
+        .map_err(|e| format!("cannot write {}: {}", temporary.display(), e))?;
+    fs::rename(&temporary, &path)
+        .map_err(|e| {
+            let _ = fs::remove_file(&temporary);
+            format!("cannot replace {}: {}", path.display(), e)
+        })
+}
@@ -0,0 +1,112 @@
+//! Live table of the queues in the terminal, run by the `watch` command
+use std::fmt::Write as _;
+use std::io::{self, IsTerminal, Write};
+use std::thread;
+use std::time::Duration;
+
+use chrono::Utc;
+
+use crate::api::Queues;
+use crate::config::Config;
+use crate::source::HtmlSource;
+use crate::Scraper;
+
+
+/// Moves the cursor to the top left corner and clears the screen
+const CLEAR_SCREEN: &str = "\x1b[H\x1b[2J";
+
+/// Headings of the columns of the table
+const COLUMNS: [&str; 5] = ["Queue", "Waiting", "Last ticket", "Estimate", "Tracked wait"];
+
+
+/// Scrape the page every [scrape interval](Config::scrape_interval) and redraw a table of the
+/// queues until interrupted
+///
+/// Waiting times are tracked while watching, without touching the state file or the database. If
+/// the output is not a terminal, the tables are printed one after another instead.
+pub fn run(config: &Config) -> Result<(), String> {
+    let mut scraper = Scraper::new(Box::new(HtmlSource::configured(config)), config);
+    let interval = Duration::from_secs(config.scrape_interval);
+    let clear = io::stdout().is_terminal();
+    loop {
+        scraper.refresh();
+        let queues = scraper.queues(&config.timezone);
+        let mut output = match clear {
+            true => String::from(CLEAR_SCREEN),
+            false => String::new(),
+        };
+        let _ = writeln!(output, "{} - every {}s, Ctrl-C to quit", config.url, config.scrape_interval);
+        let _ = writeln!(output);
+        output.push_str(&render(config, queues.as_ref()));
+
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(output.as_bytes())
+            .and_then(|_| stdout.flush())
+            .map_err(|e| format!("cannot write output: {}", e))?;
+        drop(stdout);
+        thread::sleep(interval);
+    }
+}
+
+
+/// Table of the queues followed by the queues that could not be parsed
+fn render(config: &Config, queues: Option<&Queues>) -> String {
+    let Some(queues) = queues else {
+        let now = Utc::now().with_timezone(&config.timezone);
+        return format!("No data at {}, cannot scrape page\n", now.format("%H:%M:%S"));
+    };
+
+    let rows: Vec<[String; 5]> = queues.queues.iter()
+        .map(|(service, queue)| [
+            service.clone(),
+            queue.people_waiting.to_string(),
+            queue.last_called_ticket.clone(),
+            minutes(Some(queue.waiting_time_seconds)),
+            minutes(queue.tracked_waiting_time_seconds),
+        ])
+        .collect();
+    let mut widths = COLUMNS.map(|c| c.chars().count());
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let mut output = String::new();
+    let line = |output: &mut String, cells: [&str; 5]| {
+        let cells: Vec<_> = cells.iter().zip(widths)
+            .enumerate()
+            // names are aligned to the left, values to the right
+            .map(|(i, (cell, width))| match i {
+                0 => format!("{:<width$}", cell),
+                _ => format!("{:>width$}", cell),
+            })
+            .collect();
+        let _ = writeln!(output, "{}", cells.join("  ").trim_end());
+    };
+    line(&mut output, COLUMNS);
+    for row in &rows {
+        line(&mut output, row.each_ref().map(String::as_str));
+    }
+    for (service, error) in &queues.errors {
+        let _ = writeln!(output, "{}: {}", service, error);
+    }
+
+    let _ = writeln!(output);
+    let scraped_at = chrono::DateTime::parse_from_rfc3339(&queues.scraped_at)
+        .map_or_else(|_| queues.scraped_at.clone(), |t| t.format("%H:%M:%S").to_string());
+    let stale = match queues.stale {
+        true => " (stale, scraping fails)",
+        false => "",
+    };
+    let _ = writeln!(output, "Updated {}{}", scraped_at, stale);
+    output
+}
+
+/// Waiting time in whole minutes, or `-` if unknown
+fn minutes(seconds: Option<f64>) -> String {
+    match seconds {
+        Some(seconds) => format!("{:.0} min", seconds / 60.0),
+        None => String::from("-"),
+    }
+}
@@ -0,0 +1,43 @@
+//! Notifications via HTTP webhooks
+use crate::config::{Config, WebhookConfig};
+use crate::notify::{Event, Notifier};
+use crate::push;
+
+
+/// Posts every [Event] as JSON to a URL
+pub struct Webhook {
+    config: WebhookConfig,
+    client: reqwest::blocking::Client,
+}
+
+
+impl Webhook {
+    pub fn new(config: &Config, webhook: &WebhookConfig) -> Self {
+        Webhook {
+            config: webhook.clone(),
+            client: push::client(config),
+        }
+    }
+}
+
+impl Notifier for Webhook {
+    /// Fixed name, since webhook URLs often contain secrets
+    fn name(&self) -> &str {
+        "webhook"
+    }
+
+    fn notify(&mut self, event: &Event) -> Result<(), String> {
+        let body = serde_json::to_vec(event)
+            .map_err(|e| e.to_string())?;
+        let mut request = self.client.post(&self.config.url)
+            .header("Content-Type", "application/json")
+            .body(body);
+        for (name, value) in &self.config.headers {
+            request = request.header(name, value);
+        }
+
+        let response = request.send()
+            .map_err(|e| e.without_url().to_string())?;
+        push::check_response(response)
+    }
+}
@@ -0,0 +1,67 @@
+//! Status bar widgets served on `/widget/...`
+use schemars::JsonSchema;
+use serde::Serialize;
+
+use crate::api::Queues;
+use crate::config::WidgetConfig;
+
+
+/// Output of a [waybar custom module](https://github.com/Alexays/Waybar/wiki/Module:-Custom)
+/// with JSON return type, which is also understood by i3status-rust
+#[derive(Debug,Clone,Serialize,JsonSchema)]
+pub struct Waybar {
+    pub text: String,
+    pub tooltip: String,
+
+    /// `ok`, `warning` or `critical` depending on the waiting time, `closed` while the town hall is
+    /// closed and `unknown` without data
+    pub class: &'static str,
+
+    /// Waiting time relative to the critical threshold, capped at 100
+    pub percentage: u8,
+}
+
+
+impl Waybar {
+    /// Widget for the given service, or all services if unset
+    pub fn new(queues: Option<&Queues>, service: Option<&str>, office_open: Option<bool>, config: &WidgetConfig) -> Self {
+        let selected: Vec<_> = queues.iter()
+            .flat_map(|q| &q.queues)
+            .filter(|(s, _)| service.is_none_or(|service| service == s.as_str()))
+            .collect();
+        if selected.is_empty() {
+            return Waybar {
+                text: String::from("–"),
+                tooltip: String::from("No data"),
+                class: "unknown",
+                percentage: 0,
+            };
+        }
+
+        let text = selected.iter()
+            .map(|(_, q)| format!("{} min", (q.waiting_time_seconds / 60.0).round()))
+            .collect::<Vec<_>>()
+            .join(" | ");
+        let tooltip = selected.iter()
+            .map(|(s, q)| format!("{}: {} waiting, last called {}, ~{} min", s, q.people_waiting,
+                q.last_called_ticket, (q.waiting_time_seconds / 60.0).round()))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let wait = selected.iter()
+            .map(|(_, q)| q.waiting_time_seconds)
+            .fold(0.0, f64::max);
+        let class = match office_open {
+            Some(false) => "closed",
+            _ if wait >= config.critical as f64 => "critical",
+            _ if wait >= config.warning as f64 => "warning",
+            _ => "ok",
+        };
+
+        Waybar {
+            text,
+            tooltip,
+            class,
+            percentage: (wait / config.critical as f64 * 100.0).clamp(0.0, 100.0) as u8,
+        }
+    }
+}
@@ -0,0 +1,215 @@
+//! Sending queue states to Zabbix trapper items via the sender protocol
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use prometheus::proto::MetricFamily;
+use serde::{Deserialize, Serialize};
+
+use crate::config::ZabbixConfig;
+use crate::history::HistoryFrame;
+use crate::push::Sink;
+
+
+/// Timeout for connecting to and communicating with the Zabbix server
+const ZABBIX_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Header of every message of the Zabbix protocol, followed by the length of the data
+const HEADER: &[u8; 5] = b"ZBXD\x01";
+
+/// Maximum size of a response accepted from the server
+const MAX_RESPONSE_SIZE: u64 = 64 * 1024;
+
+
+/// Sends the values of each queue to the configured item keys of a Zabbix host
+pub struct Zabbix {
+    config: ZabbixConfig,
+}
+
+/// Request of the sender protocol
+#[derive(Serialize)]
+struct SenderData<'a> {
+    request: &'static str,
+    data: Vec<Item<'a>>,
+}
+
+/// Value of a single item
+#[derive(Serialize)]
+struct Item<'a> {
+    host: &'a str,
+    key: String,
+    value: String,
+
+    /// Time the value was scraped in seconds since the epoch
+    clock: u64,
+}
+
+/// Response of the server to [SenderData]
+#[derive(Deserialize)]
+struct Response {
+    response: String,
+
+    /// Summary like `processed: 2; failed: 0; total: 2; seconds spent: 0.000055`
+    info: Option<String>,
+}
+
+
+impl Zabbix {
+    pub fn new(config: &ZabbixConfig) -> Self {
+        Zabbix { config: config.clone() }
+    }
+
+    /// Values of the configured items for all queues of a frame
+    fn items<'a>(&'a self, frame: &HistoryFrame) -> Vec<Item<'a>> {
+        let mut items = Vec::new();
+        for (service, sample) in &frame.queues {
+            let values = [
+                (&self.config.people_waiting_key, Some(sample.people_waiting as f64)),
+                (&self.config.waiting_time_key, Some(sample.waiting_time_seconds)),
+                (&self.config.tracked_waiting_time_key, sample.tracked_waiting_time_seconds),
+            ];
+            for (key, value) in values {
+                if let (false, Some(value)) = (key.is_empty(), value.filter(|v| v.is_finite())) {
+                    items.push(Item {
+                        host: &self.config.host,
+                        key: key.replace("{service}", service),
+                        value: value.to_string(),
+                        clock: frame.timestamp as u64,
+                    });
+                }
+            }
+        }
+        items
+    }
+}
+
+impl Sink for Zabbix {
+    fn name(&self) -> &str {
+        "Zabbix"
+    }
+
+    fn push(&mut self, frame: &HistoryFrame, _families: &[MetricFamily]) -> Result<(), String> {
+        let items = self.items(frame);
+        if items.is_empty() {
+            return Ok(());
+        }
+        let request = SenderData {
+            request: "sender data",
+            data: items,
+        };
+        let payload = serde_json::to_vec(&request)
+            .map_err(|e| e.to_string())?;
+
+        let address = (self.config.server.as_str(), self.config.port).to_socket_addrs()
+            .map_err(|e| e.to_string())?
+            .next()
+            .ok_or_else(|| format!("cannot resolve {}", self.config.server))?;
+        let mut stream = TcpStream::connect_timeout(&address, ZABBIX_TIMEOUT)
+            .map_err(|e| e.to_string())?;
+        stream.set_read_timeout(Some(ZABBIX_TIMEOUT))
+            .and_then(|_| stream.set_write_timeout(Some(ZABBIX_TIMEOUT)))
+            .map_err(|e| e.to_string())?;
+
+        stream.write_all(&encode_message(payload))
+            .map_err(|e| e.to_string())?;
+
+        read_response(&mut stream)
+    }
+}
+
+
+/// Read the response of the server to [SenderData], failing unless all items were accepted
+fn read_response(mut reader: impl Read) -> Result<(), String> {
+    let mut header = [0; 13];
+    reader.read_exact(&mut header)
+        .map_err(|e| format!("cannot read response: {}", e))?;
+    if &header[..5] != HEADER {
+        return Err(String::from("invalid response header"));
+    }
+    let length = u64::from_le_bytes(header[5..].try_into().unwrap_or_default());
+    if length > MAX_RESPONSE_SIZE {
+        return Err(String::from("response too large"));
+    }
+    let mut body = Vec::new();
+    reader.take(length).read_to_end(&mut body)
+        .map_err(|e| format!("cannot read response: {}", e))?;
+    let response: Response = serde_json::from_slice(&body)
+        .map_err(|e| format!("invalid response: {}", e))?;
+
+    match response.response.as_str() {
+        "success" => match response.info {
+            // items that do not exist or are not trapper items are only reported as failed
+            Some(info) if !info.contains("failed: 0;") => Err(format!("not all items were accepted: {}", info)),
+            _ => Ok(()),
+        },
+        _ => Err(format!("request rejected: {}", response.info.unwrap_or(response.response))),
+    }
+}
+
+/// Prefix a payload with the protocol header and its length
+fn encode_message(payload: Vec<u8>) -> Vec<u8> {
+    let mut message = HEADER.to_vec();
+    message.extend((payload.len() as u64).to_le_bytes());
+    message.extend(payload);
+    message
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::history::Sample;
+
+    use super::*;
+
+    #[test]
+    fn message() {
+        let message = encode_message(b"{}".to_vec());
+        assert_eq!(message, b"ZBXD\x01\x02\x00\x00\x00\x00\x00\x00\x00{}");
+    }
+    #[test]
+    fn responses() {
+        let response = |json: &str| read_response(&encode_message(json.as_bytes().to_vec())[..]);
+        assert_eq!(response(r#"{"response":"success","info":"processed: 2; failed: 0; total: 2; seconds spent: 0.000055"}"#), Ok(()));
+        assert_eq!(response(r#"{"response":"success"}"#), Ok(()));
+        assert_eq!(response(r#"{"response":"success","info":"processed: 1; failed: 1; total: 2; seconds spent: 0.1"}"#),
+            Err(String::from("not all items were accepted: processed: 1; failed: 1; total: 2; seconds spent: 0.1")));
+        assert_eq!(response(r#"{"response":"failed","info":"host is not monitored"}"#),
+            Err(String::from("request rejected: host is not monitored")));
+        assert_eq!(response(r#"{"response":"failed"}"#), Err(String::from("request rejected: failed")));
+    }
+
+    #[test]
+    fn invalid_responses() {
+        assert_eq!(read_response(&b"HTTP/1.1 400 Bad Request\r\n"[..]), Err(String::from("invalid response header")));
+        assert!(read_response(&b"ZBXD\x01"[..]).unwrap_err().starts_with("cannot read response"));
+
+        let mut too_large = HEADER.to_vec();
+        too_large.extend((MAX_RESPONSE_SIZE + 1).to_le_bytes());
+        assert_eq!(read_response(&too_large[..]), Err(String::from("response too large")));
+
+        let mut truncated = encode_message(br#"{"response":"success"}"#.to_vec());
+        truncated.truncate(20);
+        assert!(read_response(&truncated[..]).unwrap_err().starts_with("invalid response"));
+    }
+
+    #[test]
+    fn items() {
+        let config = ZabbixConfig {
+            host: String::from("town hall"),
+            tracked_waiting_time_key: String::new(),
+            ..ZabbixConfig::default()
+        };
+        let zabbix = Zabbix::new(&config);
+        let frame = HistoryFrame {
+            timestamp: 1704096000.5,
+            queues: [(String::from("citizen"), Sample {
+                people_waiting: 12,
+                last_called_ticket: String::from("B001"),
+                waiting_time_seconds: f64::NAN,
+                tracked_waiting_time_seconds: Some(60.0),
+            })].into(),
+        };
+        let items = serde_json::to_string(&zabbix.items(&frame)).unwrap();
+        assert_eq!(items, r#"[{"host":"town hall","key":"erth.people_waiting[citizen]","value":"12","clock":1704096000}]"#);
+    }
+}